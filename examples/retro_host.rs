@@ -0,0 +1,253 @@
+//! A minimal libretro frontend for checking the core without RetroArch.
+//! Loads the core's shared library, runs a game headless and checks what
+//! comes out of it:
+//!
+//! ```text
+//! cargo build --lib
+//! cargo run --example retro_host -- target/debug/libgba_rs.so game.gba \
+//!     [system dir] [frames] [last frame.png]
+//! ```
+
+extern crate gba_rs;
+extern crate libc;
+
+use std::env;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::Path;
+use std::process;
+use std::ptr;
+use std::slice;
+use std::sync::Mutex;
+
+use gba_rs::libretro::sys::*;
+use gba_rs::png;
+
+struct Host {
+    system_dir: Option<CString>,
+    frame: u64,
+    video_frames: u64,
+    last_frame: Vec<u32>,
+    samples: usize,
+    errors: Vec<String>,
+}
+
+static mut HOST: Option<Mutex<Host>> = None;
+
+fn host() -> std::sync::MutexGuard<'static, Host> {
+    unsafe { (*ptr::addr_of!(HOST)).as_ref().unwrap().lock().unwrap() }
+}
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match cmd {
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => unsafe {
+            *(data as *const i32) == RETRO_PIXEL_FORMAT_XRGB8888
+        },
+        RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY => match host().system_dir {
+            Some(ref dir) => {
+                unsafe { *(data as *mut *const c_char) = dir.as_ptr() };
+                true
+            }
+            None => false,
+        },
+        _ => false,
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let mut host = host();
+    if (width, height, pitch) != (240, 160, 240 * 4) {
+        let error = format!("Frame was {}x{} with pitch {}", width, height, pitch);
+        host.errors.push(error);
+        return;
+    }
+    host.video_frames += 1;
+    let pixels = unsafe { slice::from_raw_parts(data as *const u32, 240 * 160) };
+    host.last_frame = pixels.to_vec();
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(_data: *const i16, frames: usize) -> usize {
+    host().samples += frames;
+    frames
+}
+
+extern "C" fn input_poll() {}
+
+// Presses Start for a few frames so games get past their title screens
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let frame = host().frame;
+    let pressed = port == 0
+        && device == RETRO_DEVICE_JOYPAD
+        && id == RETRO_DEVICE_ID_JOYPAD_START
+        && frame % 120 >= 100;
+    pressed as i16
+}
+
+struct Core {
+    lib: *mut c_void,
+}
+
+impl Core {
+    fn open(path: &str) -> Core {
+        let name = CString::new(path).unwrap();
+        let lib = unsafe { libc::dlopen(name.as_ptr(), libc::RTLD_NOW | libc::RTLD_LOCAL) };
+        if lib.is_null() {
+            let err = unsafe { CStr::from_ptr(libc::dlerror()) };
+            fail(&format!(
+                "Failed to load {}: {}",
+                path,
+                err.to_string_lossy()
+            ));
+        }
+        Core { lib: lib }
+    }
+
+    // The exported function with this name, as a function pointer of type T
+    unsafe fn sym<T: Copy>(&self, name: &str) -> T {
+        let cname = CString::new(name).unwrap();
+        let sym = libc::dlsym(self.lib, cname.as_ptr());
+        if sym.is_null() {
+            fail(&format!("Core has no {}", name));
+        }
+        *(&sym as *const *mut c_void as *const T)
+    }
+}
+
+fn fail(message: &str) -> ! {
+    eprintln!("FAIL: {}", message);
+    process::exit(1)
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 3 {
+        fail("usage: retro_host <core> <rom> [system dir] [frames] [png]");
+    }
+    let frames: u64 = args.get(4).map_or(300, |s| s.parse().unwrap());
+    unsafe {
+        HOST = Some(Mutex::new(Host {
+            system_dir: args.get(3).map(|s| CString::new(s.as_str()).unwrap()),
+            frame: 0,
+            video_frames: 0,
+            last_frame: vec![],
+            samples: 0,
+            errors: vec![],
+        }));
+    }
+
+    let core = Core::open(&args[1]);
+    unsafe {
+        let api_version: extern "C" fn() -> c_uint = core.sym("retro_api_version");
+        if api_version() != RETRO_API_VERSION {
+            fail("Wrong API version");
+        }
+        core.sym::<extern "C" fn(EnvironmentFn)>("retro_set_environment")(environment);
+        core.sym::<extern "C" fn(VideoRefreshFn)>("retro_set_video_refresh")(video_refresh);
+        core.sym::<extern "C" fn(AudioSampleFn)>("retro_set_audio_sample")(audio_sample);
+        core.sym::<extern "C" fn(AudioSampleBatchFn)>("retro_set_audio_sample_batch")(
+            audio_sample_batch,
+        );
+        core.sym::<extern "C" fn(InputPollFn)>("retro_set_input_poll")(input_poll);
+        core.sym::<extern "C" fn(InputStateFn)>("retro_set_input_state")(input_state);
+        core.sym::<extern "C" fn()>("retro_init")();
+
+        let mut info: SystemInfo = std::mem::zeroed();
+        core.sym::<unsafe extern "C" fn(*mut SystemInfo)>("retro_get_system_info")(&mut info);
+        println!(
+            "Core: {} {}",
+            CStr::from_ptr(info.library_name).to_string_lossy(),
+            CStr::from_ptr(info.library_version).to_string_lossy()
+        );
+
+        let rom = CString::new(args[2].as_str()).unwrap();
+        let game = GameInfo {
+            path: rom.as_ptr(),
+            data: ptr::null(),
+            size: 0,
+            meta: ptr::null(),
+        };
+        if !core.sym::<unsafe extern "C" fn(*const GameInfo) -> bool>("retro_load_game")(&game) {
+            fail("Game didn't load");
+        }
+
+        let mut av: SystemAvInfo = std::mem::zeroed();
+        core.sym::<unsafe extern "C" fn(*mut SystemAvInfo)>("retro_get_system_av_info")(&mut av);
+        println!(
+            "AV: {}x{} at {:.3} fps, {} Hz",
+            av.geometry.base_width, av.geometry.base_height, av.timing.fps, av.timing.sample_rate
+        );
+
+        let run: extern "C" fn() = core.sym("retro_run");
+        let run_frames = |n: u64| {
+            for _ in 0..n {
+                run();
+                host().frame += 1;
+            }
+        };
+        run_frames(frames);
+
+        {
+            let host = host();
+            if !host.errors.is_empty() {
+                fail(&host.errors.join(", "));
+            }
+            if host.video_frames != frames {
+                fail(&format!(
+                    "{} frames drawn out of {}",
+                    host.video_frames, frames
+                ));
+            }
+            let expected = av.timing.sample_rate / av.timing.fps * frames as f64;
+            if (host.samples as f64 - expected).abs() > expected * 0.01 {
+                fail(&format!(
+                    "{} samples, expected {:.0}",
+                    host.samples, expected
+                ));
+            }
+            println!("Ran {} frames, {} samples", frames, host.samples);
+        }
+
+        // Running on from a state twice should come out the same
+        let size = core.sym::<extern "C" fn() -> usize>("retro_serialize_size")();
+        let mut state = vec![0u8; size];
+        let serialize: unsafe extern "C" fn(*mut c_void, usize) -> bool =
+            core.sym("retro_serialize");
+        let unserialize: unsafe extern "C" fn(*const c_void, usize) -> bool =
+            core.sym("retro_unserialize");
+        if !serialize(state.as_mut_ptr() as *mut c_void, size) {
+            fail("Save state failed");
+        }
+        run_frames(60);
+        let first = host().last_frame.clone();
+        if !unserialize(state.as_ptr() as *const c_void, size) {
+            fail("Load state failed");
+        }
+        host().frame -= 60;
+        run_frames(60);
+        if host().last_frame != first {
+            fail("Frames differed after loading a state");
+        }
+        println!("Save state of {} bytes round trips", size);
+
+        let memory_size: extern "C" fn(c_uint) -> usize = core.sym("retro_get_memory_size");
+        let memory_data: extern "C" fn(c_uint) -> *mut c_void = core.sym("retro_get_memory_data");
+        let save_size = memory_size(RETRO_MEMORY_SAVE_RAM);
+        if save_size == 0 || memory_data(RETRO_MEMORY_SAVE_RAM).is_null() {
+            fail("No save RAM");
+        }
+        println!("Save RAM of {} bytes", save_size);
+
+        if let Some(path) = args.get(5) {
+            let host = host();
+            if let Err(err) = png::save_rgb(Path::new(path), 240, 160, &host.last_frame) {
+                fail(&format!("Failed to save {}: {}", path, err));
+            }
+        }
+
+        core.sym::<extern "C" fn()>("retro_unload_game")();
+        core.sym::<extern "C" fn()>("retro_deinit")();
+    }
+    println!("OK");
+}
@@ -0,0 +1,53 @@
+/// CRC-32 (IEEE 802.3), as used by PNG and zip
+pub fn crc32(data: &[u8]) -> u32 {
+    crc32_update(0, data)
+}
+
+/// CRC-32 (IEEE 802.3), as used by PNG and zip.  Continues from a previous
+/// result, start from 0 for a new checksum.
+pub fn crc32_update(crc: u32, data: &[u8]) -> u32 {
+    let mut crc = !crc;
+    for &b in data {
+        crc ^= b as u32;
+        for _ in 0..8 {
+            let mask = 0u32.wrapping_sub(crc & 1);
+            crc = (crc >> 1) ^ (0xedb88320 & mask);
+        }
+    }
+    !crc
+}
+
+/// Adler-32, as used by zlib streams
+pub fn adler32(data: &[u8]) -> u32 {
+    const MOD: u32 = 65521;
+    let mut a = 1u32;
+    let mut b = 0u32;
+    // 5552 is the most bytes that can be summed before b could overflow
+    for chunk in data.chunks(5552) {
+        for &x in chunk {
+            a += x as u32;
+            b += a;
+        }
+        a %= MOD;
+        b %= MOD;
+    }
+    (b << 16) | a
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_crc32() {
+        assert_eq!(0, crc32_update(0, b""));
+        assert_eq!(0xcbf43926, crc32(b"123456789"));
+        assert_eq!(0xcbf43926, crc32_update(crc32_update(0, b"1234"), b"56789"));
+    }
+
+    #[test]
+    fn test_adler32() {
+        assert_eq!(1, adler32(b""));
+        assert_eq!(0x11e60398, adler32(b"Wikipedia"));
+    }
+}
@@ -1,9 +1,10 @@
-use arm7tdmi_rs::{exception::Exception, reg, reg::Reg, Cpu as Arm7TDMICpu, Memory};
+use arm7tdmi_rs::{exception::Exception, reg::Reg, Cpu as Arm7TDMICpu, Memory};
 use shared::*;
 
 use mmu::MemoryUnit;
 
 pub use arm7tdmi_rs::exception;
+pub use arm7tdmi_rs::reg;
 
 #[derive(Serialize, Deserialize)]
 pub struct Cpu<T: MemoryUnit> {
@@ -16,13 +17,19 @@ struct MemWrapper<T>(T);
 
 impl<T: MemoryUnit> Memory for MemWrapper<Shared<T>> {
     fn r8(&mut self, addr: u32) -> u8 {
-        self.0.load8(addr)
+        let val = self.0.load8(addr);
+        self.0.cpu_read(addr, 1);
+        val
     }
     fn r16(&mut self, addr: u32) -> u16 {
-        self.0.load16(addr)
+        let val = self.0.load16(addr);
+        self.0.cpu_read(addr, 2);
+        val
     }
     fn r32(&mut self, addr: u32) -> u32 {
-        self.0.load32(addr)
+        let val = self.0.load32(addr);
+        self.0.cpu_read(addr, 4);
+        val
     }
     fn w8(&mut self, addr: u32, val: u8) {
         self.0.set8(addr, val)
@@ -71,6 +78,12 @@ impl<T: MemoryUnit> Cpu<T> {
         self.cpu = Arm7TDMICpu::new(regs)
     }
 
+    /// Takes on the register state of a saved CPU, keeping the memory it's
+    /// attached to
+    pub fn restore(&mut self, saved: Cpu<T>) {
+        self.cpu = saved.cpu;
+    }
+
     pub fn set_breaks<'a, I>(&mut self, brks: I)
     where
         I: IntoIterator<Item = &'a u32>,
@@ -98,4 +111,13 @@ impl<T: MemoryUnit> Cpu<T> {
     pub fn get_prefetch_addr(&self) -> u32 {
         self.cpu.get_prefetch_addr()
     }
+
+    /// A register's value in a bank, numbered as for `new`
+    pub fn reg(&self, bank: usize, reg: Reg) -> u32 {
+        self.cpu.reg_get(bank, reg)
+    }
+
+    pub fn set_reg(&mut self, bank: usize, reg: Reg, val: u32) {
+        self.cpu.reg_set(bank, reg, val)
+    }
 }
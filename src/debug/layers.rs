@@ -0,0 +1,208 @@
+use std::fmt::Write;
+
+use io::ppu::{bg_map, colour_rgb, obj_affine_params, ObjAttrs, ObjMode};
+use mmu::gba::Gba as GbaMmu;
+use mmu::Mmu;
+
+use super::Image;
+
+/// Number of entries in OAM
+pub const OBJECTS: u32 = 128;
+
+const OUTLINE: u32 = 0xff00ff;
+const CHECKER_LIGHT: u32 = 0x505050;
+const CHECKER_DARK: u32 = 0x383838;
+const CELL_BORDER: u32 = 0x202020;
+
+// Each object gets a cell big enough for the largest (64x64) object plus a
+// 1 pixel border
+const OAM_CELL: u32 = 66;
+const OAM_COLS: u32 = 16;
+
+pub const OAM_WIDTH: u32 = OAM_COLS * OAM_CELL;
+pub const OAM_HEIGHT: u32 = OBJECTS / OAM_COLS * OAM_CELL;
+
+/// Draws the whole of background `bg` with the part that's on screen outlined,
+/// or None if the current display mode doesn't use it.  Transparent areas
+/// are filled with the backdrop colour.
+pub fn bg_image(mmu: &GbaMmu, bg: u8) -> Option<Image> {
+    let map = bg_map(mmu, bg)?;
+    let backdrop = colour_rgb(mmu.pram.load16(0).get());
+
+    let mut img = Image::new(map.width, map.height);
+    for (p, t) in img.pixels.iter_mut().zip(map.texels.iter()) {
+        *p = match *t {
+            Some(c) => colour_rgb(c),
+            None => backdrop,
+        };
+    }
+
+    for i in 0..4 {
+        let (x0, y0) = map.viewport[i];
+        let (x1, y1) = map.viewport[(i + 1) % 4];
+        outline(&mut img, (x0, y0), (x1, y1), map.wrap);
+    }
+    Some(img)
+}
+
+/// Draws a line onto an image, wrapping around its edges if `wrap` is set
+/// and clipping to them otherwise
+fn outline(img: &mut Image, from: (i32, i32), to: (i32, i32), wrap: bool) {
+    let (dx, dy) = (to.0 - from.0, to.1 - from.1);
+    let steps = dx.abs().max(dy.abs()).max(1);
+    let (w, h) = (img.width as i32, img.height as i32);
+    for s in 0..steps + 1 {
+        let mut x = from.0 + dx * s / steps;
+        let mut y = from.1 + dy * s / steps;
+        if wrap {
+            x = x.rem_euclid(w);
+            y = y.rem_euclid(h);
+        }
+        if x >= 0 && y >= 0 {
+            img.set(x as u32, y as u32, OUTLINE);
+        }
+    }
+}
+
+/// Draws an object's tiles on their own, unflipped and untransformed, over
+/// a checkerboard where it's transparent
+pub fn obj_image(mmu: &GbaMmu, dspcnt: u16, obj: &ObjAttrs) -> Image {
+    let mut img = Image::new(obj.width, obj.height);
+    for ty in 0..obj.height {
+        for tx in 0..obj.width {
+            let idx = obj.texel_index(mmu, dspcnt, tx, ty);
+            let colour = if idx != 0 {
+                colour_rgb(obj.colour(mmu, idx))
+            } else if ((tx / 4) + (ty / 4)) % 2 == 0 {
+                CHECKER_LIGHT
+            } else {
+                CHECKER_DARK
+            };
+            img.set(tx, ty, colour);
+        }
+    }
+    img
+}
+
+/// Draws all 128 objects in a 16x8 grid, in OAM order.  Objects that aren't
+/// displayed are left as empty cells.
+pub fn oam_sheet(mmu: &GbaMmu) -> Image {
+    let dspcnt = mmu.io.load16(0).get();
+    let mut img = Image::new(OAM_WIDTH, OAM_HEIGHT);
+    img.fill(0, 0, OAM_WIDTH, OAM_HEIGHT, CELL_BORDER);
+    for o in 0..OBJECTS {
+        let obj = ObjAttrs::decode(mmu, o);
+        let (cx, cy) = ((o % OAM_COLS) * OAM_CELL, (o / OAM_COLS) * OAM_CELL);
+        img.fill(cx + 1, cy + 1, OAM_CELL - 2, OAM_CELL - 2, 0);
+        if obj.visible() {
+            img.blit(&obj_image(mmu, dspcnt, &obj), cx + 1, cy + 1);
+        }
+    }
+    img
+}
+
+/// The object drawn at the given point of `oam_sheet`
+pub fn oam_at(x: u32, y: u32) -> Option<u32> {
+    if x < OAM_WIDTH && y < OAM_HEIGHT {
+        Some((y / OAM_CELL) * OAM_COLS + x / OAM_CELL)
+    } else {
+        None
+    }
+}
+
+/// Describes an object's attributes on one line, e.g.
+/// `  5: ( 16,  40) 16x32 shape 2 size 2 normal prio 1 pal  3 tile 0x040`
+pub fn describe_obj(mmu: &GbaMmu, obj: &ObjAttrs) -> String {
+    let mut out = format!(
+        "{:3}: ({:3}, {:3}) {}x{} shape {} size {} {} prio {} ",
+        obj.index,
+        obj.x,
+        obj.y,
+        obj.width,
+        obj.height,
+        obj.shape,
+        obj.size,
+        match obj.mode {
+            ObjMode::Normal => "normal",
+            ObjMode::SemiTransparent => "semi-transparent",
+            ObjMode::Window => "window",
+            ObjMode::Prohibited => "prohibited",
+        },
+        obj.priority
+    );
+    if obj.bpp8 {
+        out.push_str("8bpp");
+    } else {
+        write!(out, "pal {:2}", obj.palette).unwrap();
+    }
+    write!(out, " tile {:#05x}", obj.tile).unwrap();
+
+    if let Some(idx) = obj.affine {
+        let p = obj_affine_params(mmu, idx);
+        let fixed = |v: u16| v as i16 as f32 / 256.0;
+        write!(
+            out,
+            " affine {} [{:.3} {:.3}; {:.3} {:.3}]",
+            idx,
+            fixed(p[0]),
+            fixed(p[1]),
+            fixed(p[2]),
+            fixed(p[3])
+        )
+        .unwrap();
+        if obj.double_size {
+            out.push_str(" double");
+        }
+    } else {
+        if obj.hflip {
+            out.push_str(" hflip");
+        }
+        if obj.vflip {
+            out.push_str(" vflip");
+        }
+    }
+    if obj.mosaic {
+        out.push_str(" mosaic");
+    }
+    if obj.disabled {
+        out.push_str(" disabled");
+    }
+    out
+}
+
+/// Lists every object in OAM, one per line
+pub fn oam_listing(mmu: &GbaMmu) -> String {
+    let mut out = String::new();
+    for o in 0..OBJECTS {
+        let obj = ObjAttrs::decode(mmu, o);
+        writeln!(out, "{}", describe_obj(mmu, &obj)).unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outline_wrap() {
+        let mut img = Image::new(8, 8);
+        outline(&mut img, (6, 1), (10, 1), true);
+        assert_eq!(OUTLINE, img.pixels[1 * 8 + 7]);
+        assert_eq!(OUTLINE, img.pixels[1 * 8 + 2]);
+        assert_eq!(0, img.pixels[1 * 8 + 3]);
+
+        let mut img = Image::new(8, 8);
+        outline(&mut img, (6, 1), (10, 1), false);
+        assert_eq!(OUTLINE, img.pixels[1 * 8 + 7]);
+        assert_eq!(0, img.pixels[1 * 8 + 1]);
+    }
+
+    #[test]
+    fn test_oam_at() {
+        assert_eq!(Some(0), oam_at(0, 0));
+        assert_eq!(Some(17), oam_at(OAM_CELL + 5, OAM_CELL));
+        assert_eq!(Some(127), oam_at(OAM_WIDTH - 1, OAM_HEIGHT - 1));
+        assert_eq!(None, oam_at(OAM_WIDTH, 0));
+    }
+}
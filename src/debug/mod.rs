@@ -0,0 +1,94 @@
+//! Decoders that turn the emulated machine's memory into images, for
+//! inspecting what a game has loaded
+
+use std::io;
+use std::path::Path;
+
+use byteorder::{ByteOrder, LittleEndian};
+
+use png;
+
+pub mod layers;
+pub mod sound;
+pub mod vram;
+
+/// An RGB image, pixels packed as 0x00RRGGBB row by row
+pub struct Image {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<u32>,
+}
+
+impl Image {
+    pub fn new(width: u32, height: u32) -> Self {
+        Image {
+            width: width,
+            height: height,
+            pixels: vec![0; (width * height) as usize],
+        }
+    }
+
+    #[inline]
+    pub fn set(&mut self, x: u32, y: u32, colour: u32) {
+        if x < self.width && y < self.height {
+            self.pixels[(y * self.width + x) as usize] = colour;
+        }
+    }
+
+    pub fn fill(&mut self, x: u32, y: u32, width: u32, height: u32, colour: u32) {
+        for j in y..y + height {
+            for i in x..x + width {
+                self.set(i, j, colour);
+            }
+        }
+    }
+
+    /// Copies another image into this one with its top left corner at (x, y)
+    pub fn blit(&mut self, other: &Image, x: u32, y: u32) {
+        for j in 0..other.height {
+            for i in 0..other.width {
+                self.set(x + i, y + j, other.pixels[(j * other.width + i) as usize]);
+            }
+        }
+    }
+
+    /// Blown up by a whole number, each pixel becoming an n by n block
+    pub fn scaled(&self, n: u32) -> Image {
+        let mut img = Image::new(self.width * n, self.height * n);
+        for y in 0..img.height {
+            for x in 0..img.width {
+                img.pixels[(y * img.width + x) as usize] =
+                    self.pixels[(y / n * self.width + x / n) as usize];
+            }
+        }
+        img
+    }
+
+    /// The pixels in the layout of an SDL RGB888 texture
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = vec![0u8; self.pixels.len() * 4];
+        LittleEndian::write_u32_into(&self.pixels, &mut bytes);
+        bytes
+    }
+
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        png::save_rgb(path, self.width, self.height, &self.pixels)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scaled() {
+        let img = Image {
+            width: 2,
+            height: 1,
+            pixels: vec![1, 2],
+        };
+        let scaled = img.scaled(2);
+        assert_eq!((4, 2), (scaled.width, scaled.height));
+        assert_eq!(vec![1, 1, 2, 2, 1, 1, 2, 2], scaled.pixels);
+    }
+}
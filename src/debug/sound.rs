@@ -0,0 +1,338 @@
+use std::fmt::Write;
+
+use bit_util::{bit, extract};
+use io::spu::{CHANNELS, CHANNEL_NAMES};
+use io::IoReg;
+
+use super::Image;
+
+/// Each channel gets a row of the oscilloscope, one pixel per sample
+pub const SCOPE_WIDTH: u32 = 548;
+pub const ROW_HEIGHT: u32 = 48;
+pub const SCOPE_HEIGHT: u32 = ROW_HEIGHT * CHANNELS as u32;
+
+// Left of each row are markers for the sides the channel is panned to, right
+// of the FIFO rows is how full the FIFO is
+const MARKER_WIDTH: u32 = 6;
+const FIFO_BAR_WIDTH: u32 = 8;
+const WAVE_WIDTH: u32 = SCOPE_WIDTH - MARKER_WIDTH - FIFO_BAR_WIDTH;
+
+const BACKGROUND: u32 = 0x101010;
+const MUTED_BACKGROUND: u32 = 0x301010;
+const CENTRE_LINE: u32 = 0x303030;
+const ROW_BORDER: u32 = 0x505050;
+const MARKER: u32 = 0xe0e0e0;
+const CHANNEL_COLOURS: [u32; CHANNELS] =
+    [0xff6060, 0xffc040, 0x60ff60, 0xc0c0c0, 0x60a0ff, 0xe060ff];
+
+const SOUNDCNT_L: u32 = 0x80;
+const SOUNDCNT_H: u32 = 0x82;
+const SOUNDCNT_X: u32 = 0x84;
+const SOUNDBIAS: u32 = 0x88;
+
+/// Draws the levels of each channel over time, oldest on the left.  Muted
+/// channels get a red background, and FIFO rows have a bar on the right
+/// showing how full the FIFO is.
+pub fn scope_image(io: &IoReg, history: &[[f32; CHANNELS]], muted: &[bool; CHANNELS]) -> Image {
+    let mut img = Image::new(SCOPE_WIDTH, SCOPE_HEIGHT);
+    let cnt_l = io.peek(SOUNDCNT_L) as u32;
+    let cnt_h = io.peek(SOUNDCNT_H) as u32;
+    let centre = ROW_HEIGHT as f32 / 2.0;
+    let scale = centre - 2.0;
+
+    for ch in 0..CHANNELS {
+        let top = ch as u32 * ROW_HEIGHT;
+        let bg = if muted[ch] {
+            MUTED_BACKGROUND
+        } else {
+            BACKGROUND
+        };
+        img.fill(0, top, SCOPE_WIDTH, ROW_HEIGHT, bg);
+        img.fill(
+            MARKER_WIDTH,
+            top + ROW_HEIGHT / 2,
+            WAVE_WIDTH,
+            1,
+            CENTRE_LINE,
+        );
+        img.fill(0, top + ROW_HEIGHT - 1, SCOPE_WIDTH, 1, ROW_BORDER);
+
+        let (left, right) = panning(cnt_l, cnt_h, ch);
+        if left {
+            img.fill(1, top + 4, MARKER_WIDTH - 2, ROW_HEIGHT / 2 - 6, MARKER);
+        }
+        if right {
+            img.fill(
+                1,
+                top + ROW_HEIGHT / 2 + 2,
+                MARKER_WIDTH - 2,
+                ROW_HEIGHT / 2 - 6,
+                MARKER,
+            );
+        }
+
+        // Joins each sample to the next with a vertical run so steep edges
+        // stay visible
+        let start = history.len().saturating_sub(WAVE_WIDTH as usize);
+        let y_of = |level: f32| (centre - level.max(-1.0).min(1.0) * scale) as u32;
+        let mut prev = None;
+        for (x, levels) in history[start..].iter().enumerate() {
+            let y = y_of(levels[ch]);
+            let (from, to) = match prev {
+                Some(p) if p < y => (p, y),
+                Some(p) => (y, p),
+                None => (y, y),
+            };
+            img.fill(
+                MARKER_WIDTH + x as u32,
+                top + from,
+                1,
+                to - from + 1,
+                CHANNEL_COLOURS[ch],
+            );
+            prev = Some(y);
+        }
+
+        if ch >= 4 {
+            let len = io.sound().fifo_len(ch - 4) as u32;
+            let height = (ROW_HEIGHT - 2) * len / 32;
+            let x = SCOPE_WIDTH - FIFO_BAR_WIDTH + 1;
+            img.fill(
+                x,
+                top + ROW_HEIGHT - 1 - height,
+                FIFO_BAR_WIDTH - 2,
+                height,
+                MARKER,
+            );
+        }
+    }
+    img
+}
+
+/// The channel whose row is at y in the oscilloscope
+pub fn scope_row(y: u32) -> Option<usize> {
+    let ch = (y / ROW_HEIGHT) as usize;
+    if ch < CHANNELS {
+        Some(ch)
+    } else {
+        None
+    }
+}
+
+// Whether a channel is sent to the left and right outputs
+fn panning(cnt_l: u32, cnt_h: u32, ch: usize) -> (bool, bool) {
+    if ch < 4 {
+        (
+            bit(cnt_l, 12 + ch as u8) == 1,
+            bit(cnt_l, 8 + ch as u8) == 1,
+        )
+    } else {
+        let shift = 8 + 4 * (ch as u8 - 4);
+        (bit(cnt_h, shift + 1) == 1, bit(cnt_h, shift) == 1)
+    }
+}
+
+fn sides(left: bool, right: bool) -> &'static str {
+    match (left, right) {
+        (true, true) => "L+R",
+        (true, false) => "L",
+        (false, true) => "R",
+        (false, false) => "off",
+    }
+}
+
+fn envelope(cnt: u32) -> String {
+    let dir = if bit(cnt, 11) == 1 { "up" } else { "down" };
+    match extract(cnt, 8, 3) {
+        0 => format!("vol {}", extract(cnt, 12, 4)),
+        step => format!("vol {} {}/{}", extract(cnt, 12, 4), dir, step),
+    }
+}
+
+fn length(max: u32, len: u32, enabled: bool) -> String {
+    if enabled {
+        format!("len {}", max - len)
+    } else {
+        "no len".to_string()
+    }
+}
+
+/// One line decoding a channel's registers
+pub fn describe_channel(io: &IoReg, ch: usize) -> String {
+    let get = |addr| io.peek(addr) as u32;
+    let cnt_l = get(SOUNDCNT_L);
+    let cnt_h = get(SOUNDCNT_H);
+    let (left, right) = panning(cnt_l, cnt_h, ch);
+    let state = if io.sound().channel_on(ch) {
+        "on"
+    } else {
+        "off"
+    };
+    let mut s = format!("{}: {} {}", CHANNEL_NAMES[ch], state, sides(left, right));
+
+    match ch {
+        0 | 1 => {
+            let (duty, freq) = if ch == 0 {
+                (get(0x62), get(0x64))
+            } else {
+                (get(0x68), get(0x6c))
+            };
+            let hz = 131072.0 / (2048 - extract(freq, 0, 11)) as f32;
+            let duty_pct = ["12.5", "25", "50", "75"][extract(duty, 6, 2) as usize];
+            write!(
+                s,
+                " {:.1}Hz duty {}% {} {}",
+                hz,
+                duty_pct,
+                envelope(duty),
+                length(64, extract(duty, 0, 6), bit(freq, 14) == 1)
+            )
+            .unwrap();
+            if ch == 0 {
+                let sweep = get(0x60);
+                if extract(sweep, 4, 3) != 0 {
+                    let dir = if bit(sweep, 3) == 1 { "down" } else { "up" };
+                    write!(
+                        s,
+                        " sweep {} time {} shift {}",
+                        dir,
+                        extract(sweep, 4, 3),
+                        extract(sweep, 0, 3)
+                    )
+                    .unwrap();
+                }
+            }
+        }
+        2 => {
+            let cnt = get(0x70);
+            let vol = get(0x72);
+            let freq = get(0x74);
+            let hz = 2097152.0 / (2048 - extract(freq, 0, 11)) as f32 / 32.0;
+            let volume = if bit(vol, 15) == 1 {
+                "75%"
+            } else {
+                ["0%", "100%", "50%", "25%"][extract(vol, 13, 2) as usize]
+            };
+            let banks = if bit(cnt, 5) == 1 {
+                "both banks".to_string()
+            } else {
+                format!("bank {}", bit(cnt, 6))
+            };
+            write!(
+                s,
+                " {:.1}Hz {} {} {}",
+                hz,
+                volume,
+                banks,
+                length(256, extract(vol, 0, 8), bit(freq, 14) == 1)
+            )
+            .unwrap();
+        }
+        3 => {
+            let env = get(0x78);
+            let freq = get(0x7c);
+            let ratio = match extract(freq, 0, 3) {
+                0 => 0.5,
+                r => r as f32,
+            };
+            let hz = 524288.0 / ratio / (2 << extract(freq, 4, 4)) as f32;
+            let width = if bit(freq, 3) == 1 { 7 } else { 15 };
+            write!(
+                s,
+                " {:.1}Hz {} bit {} {}",
+                hz,
+                width,
+                envelope(env),
+                length(64, extract(env, 0, 6), bit(freq, 14) == 1)
+            )
+            .unwrap();
+        }
+        _ => {
+            let fifo = ch - 4;
+            let shift = 2 + fifo as u8;
+            let volume = if bit(cnt_h, shift) == 1 {
+                "100%"
+            } else {
+                "50%"
+            };
+            write!(
+                s,
+                " {}/32 samples timer {} {}",
+                io.sound().fifo_len(fifo),
+                bit(cnt_h, 10 + 4 * fifo as u8),
+                volume
+            )
+            .unwrap();
+        }
+    }
+    s
+}
+
+/// One line decoding SOUNDCNT_L/H/X and SOUNDBIAS
+pub fn describe_mixer(io: &IoReg) -> String {
+    let cnt_l = io.peek(SOUNDCNT_L) as u32;
+    let cnt_h = io.peek(SOUNDCNT_H) as u32;
+    let cnt_x = io.peek(SOUNDCNT_X) as u32;
+    let bias = io.peek(SOUNDBIAS) as u32;
+    let psg = ["25%", "50%", "100%", "invalid"][extract(cnt_h, 0, 2) as usize];
+    let resolution = extract(bias, 14, 2);
+    format!(
+        "master {} PSG {} L {}/8 R {}/8, bias {:#05x} {}-bit {}Hz",
+        if bit(cnt_x, 7) == 1 { "on" } else { "off" },
+        psg,
+        extract(cnt_l, 4, 3) + 1,
+        extract(cnt_l, 0, 3) + 1,
+        extract(bias, 1, 9) << 1,
+        9 - resolution,
+        32768 << resolution
+    )
+}
+
+/// The mixer and every channel, one per line
+pub fn describe_sound(io: &IoReg) -> String {
+    let mut s = describe_mixer(io);
+    s.push('\n');
+    for ch in 0..CHANNELS {
+        s.push_str(&describe_channel(io, ch));
+        s.push('\n');
+    }
+    s
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::Mmu;
+
+    #[test]
+    fn test_describe_sound() {
+        let mut io = IoReg::new();
+        io.set16(SOUNDCNT_X, 0x80);
+        io.set16(SOUNDCNT_L, 0x2077);
+        io.set16(SOUNDCNT_H, 0x0b0e);
+        io.set16(0x68, 0xa7c0);
+        io.set16(0x6c, 0x4000 | 1798);
+
+        assert_eq!(
+            "master on PSG 100% L 8/8 R 8/8, bias 0x200 9-bit 32768Hz",
+            describe_mixer(&io)
+        );
+        assert_eq!(
+            "sq2: off L 524.3Hz duty 75% vol 10 down/7 len 64",
+            describe_channel(&io, 1)
+        );
+        assert_eq!(
+            "fifoa: on L+R 0/32 samples timer 0 100%",
+            describe_channel(&io, 4)
+        );
+        assert_eq!(7, describe_sound(&io).lines().count());
+    }
+
+    #[test]
+    fn test_scope_row() {
+        assert_eq!(Some(0), scope_row(0));
+        assert_eq!(Some(5), scope_row(SCOPE_HEIGHT - 1));
+        assert_eq!(None, scope_row(SCOPE_HEIGHT));
+    }
+}
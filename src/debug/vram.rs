@@ -0,0 +1,115 @@
+use std::fmt::Write;
+
+use bit_util::extract;
+
+use io::ppu::colour_rgb;
+use mmu::gba::Gba as GbaMmu;
+use mmu::Mmu;
+
+use super::Image;
+
+/// Number of 16k character blocks in VRAM, 4 for backgrounds and 2 for objects
+pub const CHARBLOCKS: u32 = 6;
+/// Palette banks that can be chosen, 16 for backgrounds followed by 16 for
+/// objects
+pub const PALETTE_BANKS: u32 = 32;
+
+const CHARBLOCK_SIZE: u32 = 16 * 1024;
+const SHEET_TILES: u32 = 32;
+
+const PALETTE_ENTRIES: u32 = 512;
+const SWATCH_SIZE: u32 = 8;
+const SWATCH_COLS: u32 = 16;
+
+pub const SHEET_WIDTH: u32 = SHEET_TILES * 8;
+pub const PALETTE_WIDTH: u32 = SWATCH_COLS * SWATCH_SIZE;
+pub const PALETTE_HEIGHT: u32 = PALETTE_ENTRIES / SWATCH_COLS * SWATCH_SIZE;
+
+/// Decodes a character block as a sheet of tiles, 32 tiles across.
+///
+/// For 4bpp tiles `palette` picks one of the 16 colour banks, with banks
+/// 16 and up coming from the object palette.  For 8bpp tiles only whether
+/// it's a background or object bank matters.
+pub fn tile_sheet(mmu: &GbaMmu, charblock: u32, bpp8: bool, palette: u32) -> Image {
+    debug_assert!(charblock < CHARBLOCKS && palette < PALETTE_BANKS);
+
+    let tile_bytes = if bpp8 { 64 } else { 32 };
+    let tiles = CHARBLOCK_SIZE / tile_bytes;
+    let rows = tiles / SHEET_TILES;
+
+    let pal_base = if bpp8 {
+        (palette / 16) * 0x200
+    } else {
+        (palette / 16) * 0x200 + (palette % 16) * 32
+    };
+
+    let mut img = Image::new(SHEET_WIDTH, rows * 8);
+    let base = charblock * CHARBLOCK_SIZE;
+    for t in 0..tiles {
+        let tile_addr = base + t * tile_bytes;
+        let (tx, ty) = ((t % SHEET_TILES) * 8, (t / SHEET_TILES) * 8);
+        for idx in 0..64 {
+            let colour = if bpp8 {
+                mmu.vram.load8(tile_addr + idx).get() as u32
+            } else {
+                let v = mmu.vram.load8(tile_addr + idx / 2).get() as u32;
+                extract(v, ((idx & 1) * 4) as u8, 4)
+            };
+            let c = mmu.pram.load16(pal_base + colour * 2).get();
+            img.set(tx + idx % 8, ty + idx / 8, colour_rgb(c));
+        }
+    }
+    img
+}
+
+/// Draws all 512 palette entries as swatches, 16 to a row.  The first 16
+/// rows are the background palette, the rest the object palette.
+pub fn palette_swatches(mmu: &GbaMmu) -> Image {
+    let mut img = Image::new(PALETTE_WIDTH, PALETTE_HEIGHT);
+    for i in 0..PALETTE_ENTRIES {
+        let c = mmu.pram.load16(i * 2).get();
+        let (x, y) = (
+            (i % SWATCH_COLS) * SWATCH_SIZE,
+            (i / SWATCH_COLS) * SWATCH_SIZE,
+        );
+        img.fill(x, y, SWATCH_SIZE, SWATCH_SIZE, colour_rgb(c));
+    }
+    img
+}
+
+/// The palette entry drawn at the given point of `palette_swatches`
+pub fn swatch_at(x: u32, y: u32) -> Option<u32> {
+    if x < PALETTE_WIDTH && y < PALETTE_HEIGHT {
+        Some((y / SWATCH_SIZE) * SWATCH_COLS + x / SWATCH_SIZE)
+    } else {
+        None
+    }
+}
+
+pub fn palette_entry(mmu: &GbaMmu, entry: u32) -> u16 {
+    mmu.pram.load16(entry * 2).get()
+}
+
+/// Describes a palette entry, e.g. `obj  2:15 0x7fff (31, 31, 31)`
+pub fn describe_entry(entry: u32, colour: u16) -> String {
+    let c = colour as u32;
+    format!(
+        "{} {:2}:{:2} {:#06x} ({:2}, {:2}, {:2})",
+        if entry < 256 { "bg " } else { "obj" },
+        (entry % 256) / 16,
+        entry % 16,
+        colour,
+        extract(c, 0, 5),
+        extract(c, 5, 5),
+        extract(c, 10, 5)
+    )
+}
+
+/// Lists every palette entry with its BGR555 value, one per line
+pub fn palette_listing(mmu: &GbaMmu) -> String {
+    let mut out = String::new();
+    for i in 0..PALETTE_ENTRIES {
+        writeln!(out, "{}", describe_entry(i, palette_entry(mmu, i))).unwrap();
+    }
+    out
+}
@@ -0,0 +1,334 @@
+//! The machine behind a small API for embedding it in other programs: run
+//! it a frame at a time and look at what comes out.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use gba_rs::{Emulator, GameRom, Key, KeyState, MemoryRange};
+//!
+//! let rom = GameRom::new(Path::new("game.gba")).unwrap();
+//! let mut emu = Emulator::builder(rom).build();
+//! let mut keys = KeyState::new();
+//! keys.press(Key::Start);
+//! let result = emu.run_frame(&keys);
+//! println!("frame {}, {:?}", result.frame, emu.peek8(MemoryRange::BoardWram, 0));
+//! ```
+
+use bincode;
+
+use cpu::reg::Reg;
+use io::key::KeyState;
+use io::spu::AudioOptions;
+use link::Link;
+use mmu::gba::{MemoryRange, BATTERY_SIZE};
+use mmu::MemoryUnit;
+use rom::GameRom;
+use system::System;
+use video::filter::{Filter, FilterOptions};
+
+use GBAError;
+use Result;
+
+/// Sets up an `Emulator`
+pub struct EmulatorBuilder {
+    rom: GameRom,
+    bios: Option<GameRom>,
+    direct_boot: bool,
+    sample_rate: i32,
+    filter: FilterOptions,
+    breaks: Vec<u32>,
+}
+
+impl EmulatorBuilder {
+    pub fn new(rom: GameRom) -> Self {
+        EmulatorBuilder {
+            rom: rom,
+            bios: None,
+            direct_boot: false,
+            sample_rate: AudioOptions::default().freq,
+            filter: Default::default(),
+            breaks: vec![],
+        }
+    }
+
+    /// Without a BIOS the game is booted into directly, which works until it
+    /// calls into the missing BIOS
+    pub fn bios(mut self, bios: GameRom) -> Self {
+        self.bios = Some(bios);
+        self
+    }
+
+    /// Skips the BIOS intro, straight into the game
+    pub fn direct_boot(mut self, direct_boot: bool) -> Self {
+        self.direct_boot = direct_boot;
+        self
+    }
+
+    /// The rate sound is made at, 48 kHz by default
+    pub fn sample_rate(mut self, sample_rate: i32) -> Self {
+        self.sample_rate = sample_rate;
+        self
+    }
+
+    /// Colour correction and frame blending for `frame_buffer`
+    pub fn filter(mut self, filter: FilterOptions) -> Self {
+        self.filter = filter;
+        self
+    }
+
+    /// Addresses that end up in `FrameResult::breakpoint` when run
+    pub fn breaks(mut self, breaks: Vec<u32>) -> Self {
+        self.breaks = breaks;
+        self
+    }
+
+    pub fn build(self) -> Emulator {
+        let mut sys = match self.bios {
+            Some(bios) => System::new(self.rom, bios, self.direct_boot),
+            None => System::new(self.rom, GameRom::default(), true),
+        };
+        sys.cpu.set_breaks(self.breaks.iter());
+        sys.spu.set_taken(self.sample_rate);
+        Emulator {
+            sys: sys,
+            filter: Filter::new(&self.filter),
+            sample_rate: self.sample_rate,
+            audio: vec![],
+        }
+    }
+}
+
+/// What happened in a frame
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameResult {
+    /// Frames run since power on, counting this one
+    pub frame: u64,
+    /// Where the CPU first hit a breakpoint during the frame, if it did.
+    /// The frame still runs to the end.
+    pub breakpoint: Option<u32>,
+    /// Stereo samples of sound made
+    pub samples: usize,
+}
+
+/// A GBA
+pub struct Emulator {
+    sys: Box<System>,
+    filter: Filter,
+    sample_rate: i32,
+    // The last frame's sound
+    audio: Vec<(f32, f32)>,
+}
+
+impl Emulator {
+    pub fn builder(rom: GameRom) -> EmulatorBuilder {
+        EmulatorBuilder::new(rom)
+    }
+
+    /// Runs a frame with the keys held down
+    pub fn run_frame(&mut self, keys: &KeyState) -> FrameResult {
+        self.sys.io.set_keyreg(keys);
+        self.sys.emulate_frame();
+        self.filter.process(&self.sys.ppu.frame());
+        self.audio = self.sys.spu.take_samples();
+        FrameResult {
+            frame: self.sys.frame,
+            breakpoint: self.sys.break_hits.drain(..).next(),
+            samples: self.audio.len(),
+        }
+    }
+
+    /// Frames run since power on
+    pub fn frame_count(&self) -> u64 {
+        self.sys.frame
+    }
+
+    /// The last frame run, 240x160 pixels packed as 0x00RRGGBB, after the
+    /// filter.  Empty before the first frame.
+    pub fn frame_buffer(&self) -> &[u32] {
+        self.filter.last()
+    }
+
+    /// The last frame's sound as left and right samples from -1 to 1
+    pub fn audio(&self) -> &[(f32, f32)] {
+        &self.audio
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.sample_rate
+    }
+
+    /// Reads a byte at an offset into a region of memory, None past its end
+    pub fn peek8(&self, range: MemoryRange, offset: u32) -> Option<u8> {
+        range_addr(range, offset, 1).map(|addr| self.sys.mmu.load8(addr))
+    }
+
+    pub fn peek16(&self, range: MemoryRange, offset: u32) -> Option<u16> {
+        range_addr(range, offset, 2).map(|addr| self.sys.mmu.load16(addr))
+    }
+
+    pub fn peek32(&self, range: MemoryRange, offset: u32) -> Option<u32> {
+        range_addr(range, offset, 4).map(|addr| self.sys.mmu.load32(addr))
+    }
+
+    /// The whole of a RAM region as it is, without going through the memory
+    /// map.  None for regions that aren't plain RAM.
+    pub fn ram(&self, range: MemoryRange) -> Option<&[u8]> {
+        let mmu = &self.sys.mmu;
+        match range {
+            MemoryRange::BoardWram => Some(mmu.bram.bytes()),
+            MemoryRange::ChipWram => Some(mmu.cram.bytes()),
+            MemoryRange::Palette => Some(mmu.pram.bytes()),
+            MemoryRange::VideoRam => Some(mmu.vram.bytes()),
+            MemoryRange::ObjectAttr => Some(mmu.oam.bytes()),
+            MemoryRange::GamePakSram => Some(mmu.gram.bytes()),
+            _ => None,
+        }
+    }
+
+    /// Writes a byte at an offset into a region of memory the way the CPU
+    /// would, false past its end
+    pub fn poke8(&mut self, range: MemoryRange, offset: u32, val: u8) -> bool {
+        match range_addr(range, offset, 1) {
+            Some(addr) => {
+                self.sys.mmu.set8(addr, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn poke16(&mut self, range: MemoryRange, offset: u32, val: u16) -> bool {
+        match range_addr(range, offset, 2) {
+            Some(addr) => {
+                self.sys.mmu.set16(addr, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn poke32(&mut self, range: MemoryRange, offset: u32, val: u32) -> bool {
+        match range_addr(range, offset, 4) {
+            Some(addr) => {
+                self.sys.mmu.set32(addr, val);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// A register as seen from user and system mode, like `reg::PC`
+    pub fn register(&self, reg: Reg) -> u32 {
+        self.sys.cpu.reg(0, reg)
+    }
+
+    pub fn set_register(&mut self, reg: Reg, val: u32) {
+        self.sys.cpu.set_reg(0, reg, val);
+    }
+
+    pub fn thumb_mode(&self) -> bool {
+        self.sys.cpu.thumb_mode()
+    }
+
+    /// Plugs in a link cable, or unplugs it with None.  Each frame waits for
+    /// the other ends to finish theirs.
+    pub fn set_link(&mut self, link: Option<Link>) {
+        self.sys.set_link(link);
+    }
+
+    /// Replaces the breakpoints
+    pub fn set_breaks(&mut self, breaks: &[u32]) {
+        self.sys.cpu.set_breaks(breaks.iter());
+    }
+
+    /// The whole machine's state, for `load_state`.  The ROM and BIOS
+    /// aren't included.
+    pub fn save_state(&self) -> Vec<u8> {
+        bincode::serialize(&*self.sys).unwrap()
+    }
+
+    /// The longest `save_state` can return, for sizing buffers up front
+    pub fn max_state_size(&self) -> usize {
+        self.sys.max_state_size()
+    }
+
+    /// Loads a state from `save_state`, which may have padding after it.
+    /// The frame buffer shows the state's last frame afterwards.
+    pub fn load_state(&mut self, data: &[u8]) -> Result<()> {
+        self.sys.load_state(data)?;
+        self.filter.reset();
+        self.filter.process(&self.sys.ppu.frame());
+        self.audio.clear();
+        Ok(())
+    }
+
+    /// The cartridge's battery-backed save memory, `BATTERY_SIZE` bytes
+    pub fn battery(&self) -> Vec<u8> {
+        self.sys.mmu.battery()
+    }
+
+    pub fn load_battery(&mut self, data: &[u8]) -> Result<()> {
+        if data.len() != BATTERY_SIZE {
+            return Err(GBAError::ConfigError(format!(
+                "battery save should be {} bytes, not {}",
+                BATTERY_SIZE,
+                data.len()
+            )));
+        }
+        self.sys.mmu.load_battery(data);
+        Ok(())
+    }
+}
+
+// The address of `len` bytes at an offset into a region, if they fit
+fn range_addr(range: MemoryRange, offset: u32, len: u32) -> Option<u32> {
+    let (start, end) = range.bounds();
+    match offset.checked_add(len) {
+        Some(top) if top <= end - start => Some(start + offset),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use cpu::reg;
+
+    #[test]
+    fn test_memory_and_states() {
+        let mut emu = Emulator::builder(GameRom::default()).build();
+        assert!(emu.poke32(MemoryRange::BoardWram, 0x100, 0x12345678));
+        assert_eq!(Some(0x78), emu.peek8(MemoryRange::BoardWram, 0x100));
+        assert_eq!(Some(0x1234), emu.peek16(MemoryRange::BoardWram, 0x102));
+        assert_eq!(None, emu.peek32(MemoryRange::ChipWram, 0x7ffe));
+        assert!(!emu.poke8(MemoryRange::ChipWram, 0x8000, 1));
+        assert_eq!(0x78, emu.ram(MemoryRange::BoardWram).unwrap()[0x100]);
+        assert!(emu.ram(MemoryRange::GamePakRom).is_none());
+
+        let state = emu.save_state();
+        emu.poke8(MemoryRange::BoardWram, 0x100, 0);
+        let result = emu.run_frame(&KeyState::new());
+        assert_eq!(1, result.frame);
+        assert_eq!(240 * 160, emu.frame_buffer().len());
+        assert_eq!(result.samples, emu.audio().len());
+        assert!(emu.load_state(&state).is_ok());
+        assert_eq!(0, emu.frame_count());
+        assert_eq!(Some(0x78), emu.peek8(MemoryRange::BoardWram, 0x100));
+        assert!(emu.load_state(&state[..16]).is_err());
+
+        emu.set_register(reg::PC, 0x8000100);
+        emu.register(reg::PC);
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut emu = Emulator::builder(GameRom::default()).build();
+        let mut battery = vec![0; BATTERY_SIZE];
+        battery[0] = 0x12;
+        assert!(emu.load_battery(&battery).is_ok());
+        assert_eq!(Some(0x12), emu.peek8(MemoryRange::GamePakSram, 0));
+        assert_eq!(battery, emu.battery());
+        assert!(emu.load_battery(&battery[1..]).is_err());
+    }
+}
@@ -0,0 +1,389 @@
+//! Gym-style environments for training agents on games.  Each episode
+//! starts from a save state, actions are the keys held down, and rewards
+//! come from functions reading the machine's memory.  `EnvPool` steps many
+//! environments at once across threads.
+//!
+//! ```no_run
+//! use std::path::Path;
+//!
+//! use gba_rs::env::{Env, ObservationOptions};
+//! use gba_rs::{Emulator, GameRom, Key, KeyState, MemoryRange};
+//!
+//! let rom = GameRom::new(Path::new("game.gba")).unwrap();
+//! let opts = ObservationOptions {
+//!     downsample: 2,
+//!     greyscale: true,
+//!     ..Default::default()
+//! };
+//! let mut env = Env::new(Emulator::builder(rom).build(), None, opts)
+//!     .unwrap()
+//!     .reward(|emu| emu.peek8(MemoryRange::BoardWram, 0x1234).unwrap() as f32);
+//! env.reset();
+//! let mut action = KeyState::new();
+//! action.press(Key::Right);
+//! let step = env.step(&action, 4);
+//! println!("{} {}", step.reward, step.done);
+//! ```
+
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::{self, JoinHandle};
+
+use emulator::Emulator;
+use io::key::KeyState;
+use io::ppu::{COLS, ROWS};
+use mmu::gba::MemoryRange;
+use video::scale::shrink;
+
+use Result;
+
+/// What goes into each observation
+#[derive(Clone, Debug)]
+pub struct ObservationOptions {
+    /// Frames are shrunk by this whole factor, 1 keeps them 240x160
+    pub downsample: u32,
+    /// One byte of brightness per pixel instead of three of colour
+    pub greyscale: bool,
+    /// Includes the work RAM: on-board, then on-chip
+    pub ram: bool,
+}
+
+impl Default for ObservationOptions {
+    fn default() -> Self {
+        ObservationOptions {
+            downsample: 1,
+            greyscale: false,
+            ram: false,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    pub width: u32,
+    pub height: u32,
+    /// Rows from the top, each pixel one grey byte or RGB bytes
+    pub pixels: Vec<u8>,
+    pub ram: Option<Vec<u8>>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct StepResult {
+    pub observation: Observation,
+    /// Summed over the frames the action was repeated for
+    pub reward: f32,
+    pub done: bool,
+    /// Frames run since power on
+    pub frame: u64,
+}
+
+pub type RewardFn = Box<dyn FnMut(&Emulator) -> f32 + Send>;
+pub type DoneFn = Box<dyn FnMut(&Emulator) -> bool + Send>;
+
+/// A game to play through episode by episode
+pub struct Env {
+    emu: Emulator,
+    start: Vec<u8>,
+    opts: ObservationOptions,
+    reward: Option<RewardFn>,
+    done: Option<DoneFn>,
+}
+
+impl Env {
+    /// Episodes start from `start`, a state from `Emulator::save_state`, or
+    /// from the emulator as it is now when None
+    pub fn new(mut emu: Emulator, start: Option<Vec<u8>>, opts: ObservationOptions) -> Result<Env> {
+        let start = match start {
+            Some(state) => {
+                emu.load_state(&state)?;
+                state
+            }
+            None => emu.save_state(),
+        };
+        Ok(Env {
+            emu: emu,
+            start: start,
+            opts: opts,
+            reward: None,
+            done: None,
+        })
+    }
+
+    /// Scores each frame run, 0 without one
+    pub fn reward<F: FnMut(&Emulator) -> f32 + Send + 'static>(mut self, reward: F) -> Self {
+        self.reward = Some(Box::new(reward));
+        self
+    }
+
+    /// Ends episodes, which otherwise go on until reset
+    pub fn done<F: FnMut(&Emulator) -> bool + Send + 'static>(mut self, done: F) -> Self {
+        self.done = Some(Box::new(done));
+        self
+    }
+
+    pub fn emulator(&self) -> &Emulator {
+        &self.emu
+    }
+
+    /// Starts a new episode from the start state
+    pub fn reset(&mut self) -> Observation {
+        // It loaded when the environment was made, so it still will
+        self.emu.load_state(&self.start).unwrap();
+        self.observe()
+    }
+
+    /// Holds the keys down for `repeat` frames, stopping early if the
+    /// episode ends
+    pub fn step(&mut self, action: &KeyState, repeat: u32) -> StepResult {
+        let mut reward = 0.0;
+        let mut done = false;
+        for _ in 0..repeat.max(1) {
+            self.emu.run_frame(action);
+            if let Some(ref mut f) = self.reward {
+                reward += f(&self.emu);
+            }
+            if let Some(ref mut f) = self.done {
+                done = f(&self.emu);
+            }
+            if done {
+                break;
+            }
+        }
+        StepResult {
+            observation: self.observe(),
+            reward: reward,
+            done: done,
+            frame: self.emu.frame_count(),
+        }
+    }
+
+    pub fn observe(&self) -> Observation {
+        let frame = self.emu.frame_buffer();
+        let blank;
+        let frame = if frame.is_empty() {
+            // Nothing's been drawn since power on
+            blank = vec![0; (COLS * ROWS) as usize];
+            &blank
+        } else {
+            frame
+        };
+        let (frame, width, height) = shrink(frame, COLS, ROWS, self.opts.downsample.max(1));
+
+        let mut pixels = Vec::with_capacity(frame.len() * if self.opts.greyscale { 1 } else { 3 });
+        for p in frame {
+            let (r, g, b) = (p >> 16 & 0xff, p >> 8 & 0xff, p & 0xff);
+            if self.opts.greyscale {
+                pixels.push(((r * 77 + g * 150 + b * 29) >> 8) as u8);
+            } else {
+                pixels.extend_from_slice(&[r as u8, g as u8, b as u8]);
+            }
+        }
+
+        let ram = if self.opts.ram {
+            let mut ram = self.emu.ram(MemoryRange::BoardWram).unwrap().to_vec();
+            ram.extend_from_slice(self.emu.ram(MemoryRange::ChipWram).unwrap());
+            Some(ram)
+        } else {
+            None
+        };
+
+        Observation {
+            width: width,
+            height: height,
+            pixels: pixels,
+            ram: ram,
+        }
+    }
+}
+
+enum Job {
+    Reset,
+    Step(Vec<KeyState>, u32),
+}
+
+enum Outcome {
+    Reset(Vec<Observation>),
+    Step(Vec<StepResult>),
+}
+
+struct Worker {
+    jobs: Sender<Job>,
+    outcomes: Receiver<Outcome>,
+    // Which environments it has, in the order it has them
+    indices: Vec<usize>,
+    thread: Option<JoinHandle<()>>,
+}
+
+/// Environments run in parallel on a pool of threads, each owning a share
+/// of them.  Results come back in the order the environments were given.
+pub struct EnvPool {
+    workers: Vec<Worker>,
+    len: usize,
+}
+
+impl EnvPool {
+    pub fn new(envs: Vec<Env>, threads: usize) -> Self {
+        let len = envs.len();
+        let threads = threads.max(1).min(len.max(1));
+        let mut shares: Vec<(Vec<usize>, Vec<Env>)> =
+            (0..threads).map(|_| (vec![], vec![])).collect();
+        for (i, env) in envs.into_iter().enumerate() {
+            shares[i % threads].0.push(i);
+            shares[i % threads].1.push(env);
+        }
+
+        let workers = shares
+            .into_iter()
+            .map(|(indices, mut envs)| {
+                let (jobs, job_rx) = channel();
+                let (outcome_tx, outcomes) = channel();
+                let thread = thread::spawn(move || {
+                    for job in job_rx {
+                        let outcome = match job {
+                            Job::Reset => Outcome::Reset(envs.iter_mut().map(Env::reset).collect()),
+                            Job::Step(actions, repeat) => Outcome::Step(
+                                envs.iter_mut()
+                                    .zip(actions.iter())
+                                    .map(|(env, action)| env.step(action, repeat))
+                                    .collect(),
+                            ),
+                        };
+                        if outcome_tx.send(outcome).is_err() {
+                            break;
+                        }
+                    }
+                });
+                Worker {
+                    jobs: jobs,
+                    outcomes: outcomes,
+                    indices: indices,
+                    thread: Some(thread),
+                }
+            })
+            .collect();
+        EnvPool {
+            workers: workers,
+            len: len,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn reset(&mut self) -> Vec<Observation> {
+        for worker in &self.workers {
+            worker.jobs.send(Job::Reset).unwrap();
+        }
+        self.collect(|outcome| match outcome {
+            Outcome::Reset(observations) => observations,
+            Outcome::Step(_) => unreachable!(),
+        })
+    }
+
+    /// Steps every environment, each with its own action
+    pub fn step(&mut self, actions: &[KeyState], repeat: u32) -> Vec<StepResult> {
+        assert_eq!(self.len, actions.len(), "one action per environment");
+        for worker in &self.workers {
+            let share = worker.indices.iter().map(|&i| actions[i]).collect();
+            worker.jobs.send(Job::Step(share, repeat)).unwrap();
+        }
+        self.collect(|outcome| match outcome {
+            Outcome::Step(results) => results,
+            Outcome::Reset(_) => unreachable!(),
+        })
+    }
+
+    // Puts each worker's results back in the environments' order
+    fn collect<T, F: Fn(Outcome) -> Vec<T>>(&mut self, unwrap: F) -> Vec<T> {
+        let mut results: Vec<Option<T>> = (0..self.len).map(|_| None).collect();
+        for worker in &self.workers {
+            let outcome = worker
+                .outcomes
+                .recv()
+                .expect("an environment's thread panicked");
+            for (&i, result) in worker.indices.iter().zip(unwrap(outcome)) {
+                results[i] = Some(result);
+            }
+        }
+        results.into_iter().map(Option::unwrap).collect()
+    }
+}
+
+impl Drop for EnvPool {
+    fn drop(&mut self) {
+        for mut worker in self.workers.drain(..) {
+            let thread = worker.thread.take();
+            // Closing the job channel ends the thread
+            drop(worker);
+            if let Some(thread) = thread {
+                let _ = thread.join();
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use rom::GameRom;
+
+    // An environment rewarded with the first byte of work RAM each frame
+    fn env(value: u8, opts: ObservationOptions) -> Env {
+        let mut emu = Emulator::builder(GameRom::default()).build();
+        emu.poke8(MemoryRange::BoardWram, 0, value);
+        Env::new(emu, None, opts)
+            .unwrap()
+            .reward(|emu| emu.peek8(MemoryRange::BoardWram, 0).unwrap() as f32)
+            .done(|emu| emu.frame_count() >= 3)
+    }
+
+    #[test]
+    fn test_env() {
+        let opts = ObservationOptions {
+            downsample: 4,
+            greyscale: true,
+            ram: true,
+        };
+        let mut env = env(2, opts);
+        let obs = env.reset();
+        assert_eq!((60, 40), (obs.width, obs.height));
+        assert_eq!(60 * 40, obs.pixels.len());
+        assert_eq!(Some(2), obs.ram.as_ref().map(|ram| ram[0]));
+        assert_eq!(256 * 1024 + 32 * 1024, obs.ram.unwrap().len());
+
+        let step = env.step(&KeyState::new(), 2);
+        assert_eq!((4.0, false, 2), (step.reward, step.done, step.frame));
+        // The episode ends partway through the repeats
+        let step = env.step(&KeyState::new(), 5);
+        assert_eq!((2.0, true, 3), (step.reward, step.done, step.frame));
+
+        // Episodes start from the start state's frame, not the last one the
+        // episode before drew
+        let first = env.reset();
+        assert_eq!(0, env.emulator().frame_count());
+        env.emu.poke16(MemoryRange::Palette, 0, 0x1f);
+        let step = env.step(&KeyState::new(), 1);
+        assert!(step.observation.pixels != first.pixels);
+        assert_eq!(first, env.reset());
+        let obs = Env::new(env.emu, None, Default::default())
+            .unwrap()
+            .observe();
+        assert_eq!(240 * 160 * 3, obs.pixels.len());
+    }
+
+    #[test]
+    fn test_pool() {
+        let envs = (0..5).map(|i| env(i, Default::default())).collect();
+        let mut pool = EnvPool::new(envs, 2);
+        assert_eq!(5, pool.reset().len());
+        let results = pool.step(&[KeyState::new(); 5], 2);
+        let rewards: Vec<f32> = results.iter().map(|r| r.reward).collect();
+        assert_eq!(vec![0.0, 2.0, 4.0, 6.0, 8.0], rewards);
+        assert!(results.iter().all(|r| r.frame == 2));
+    }
+}
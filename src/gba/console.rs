@@ -0,0 +1,172 @@
+//! Debugger commands typed on stdin while the emulator runs
+
+use std::io::{stdin, BufRead};
+use std::sync::mpsc::{channel, Receiver};
+
+use debug::sound::describe_sound;
+use io::ppu::Layer;
+use io::spu::{CHANNELS, CHANNEL_NAMES};
+
+use super::*;
+
+const HELP: &str = "Commands:
+  layer <name>... [on|off]  force layers off or back on, toggles without on/off
+  layers                    list the layers that are forced off
+  sound                     show the sound registers decoded
+  mute <channel>... [on|off]  mute channels or unmute them, toggles without on/off
+  solo <channel>            mute every channel but one
+  unmute                    unmute every channel
+  help                      show this message
+Layers: bg0 bg1 bg2 bg3 obj objwin win0 win1 effects, or all
+Channels: sq1 sq2 wave noise fifoa fifob, or all";
+
+/// Reads lines from stdin on a thread of its own, so the run loop can check
+/// for commands without blocking
+pub(super) struct Console {
+    rx: Receiver<String>,
+}
+
+impl Console {
+    pub fn new() -> Self {
+        let (tx, rx) = channel();
+        thread::spawn(move || {
+            let stdin = stdin();
+            for line in stdin.lock().lines() {
+                match line {
+                    Ok(line) => {
+                        if tx.send(line).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+        Console { rx: rx }
+    }
+
+    fn poll(&self) -> Option<String> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Gba {
+    /// Runs any commands typed since the last frame
+    pub(super) fn check_console(&mut self) {
+        loop {
+            let line = match self.console {
+                Some(ref console) => console.poll(),
+                None => return,
+            };
+            match line {
+                Some(line) => self.run_command(&line),
+                None => break,
+            }
+        }
+    }
+
+    fn run_command(&mut self, line: &str) {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        match args.split_first() {
+            None => {}
+            Some((&"layer", layers)) => self.layer_command(layers),
+            Some((&"layers", _)) => {
+                let hidden = self.hidden_layers();
+                if hidden.is_empty() {
+                    println!("No layers forced off");
+                } else {
+                    println!("Forced off: {}", hidden.join(" "));
+                }
+            }
+            Some((&"sound", _)) => {
+                print!("{}", describe_sound(&self.sys.io));
+                let muted: Vec<&str> = (0..CHANNELS)
+                    .filter(|&ch| self.sys.spu.channel_muted(ch))
+                    .map(|ch| CHANNEL_NAMES[ch])
+                    .collect();
+                if !muted.is_empty() {
+                    println!("Muted: {}", muted.join(" "));
+                }
+            }
+            Some((&"mute", channels)) => self.mute_command(channels),
+            Some((&"solo", &[name])) => match channel_index(name) {
+                Some(ch) => self.sys.spu.solo(ch),
+                None => println!("Unknown channel {:?}", name),
+            },
+            Some((&"solo", _)) => println!("Usage: solo <channel>"),
+            Some((&"unmute", _)) => {
+                for ch in 0..CHANNELS {
+                    self.sys.spu.set_channel_muted(ch, false);
+                }
+            }
+            Some((&"help", _)) => println!("{}", HELP),
+            Some((cmd, _)) => println!("Unknown command {:?}, try help", cmd),
+        }
+    }
+
+    fn layer_command(&mut self, args: &[&str]) {
+        let (names, hidden) = match args.split_last() {
+            Some((&"off", names)) => (names, Some(true)),
+            Some((&"on", names)) => (names, Some(false)),
+            _ => (args, None),
+        };
+        if names.is_empty() {
+            println!("Usage: layer <name>... [on|off]");
+            return;
+        }
+
+        let mut layers = vec![];
+        for name in names {
+            if *name == "all" {
+                layers.extend_from_slice(&Layer::ALL);
+            } else {
+                match Layer::from_name(name) {
+                    Some(layer) => layers.push(layer),
+                    None => {
+                        println!("Unknown layer {:?}", name);
+                        return;
+                    }
+                }
+            }
+        }
+        for layer in layers {
+            let hide = hidden.unwrap_or(!self.sys.ppu.layer_hidden(layer));
+            self.set_layer_hidden(layer, hide);
+        }
+    }
+
+    fn mute_command(&mut self, args: &[&str]) {
+        let (names, muted) = match args.split_last() {
+            Some((&"on", names)) => (names, Some(true)),
+            Some((&"off", names)) => (names, Some(false)),
+            _ => (args, None),
+        };
+        if names.is_empty() {
+            println!("Usage: mute <channel>... [on|off]");
+            return;
+        }
+
+        let mut channels = vec![];
+        for name in names {
+            if *name == "all" {
+                channels.extend(0..CHANNELS);
+            } else {
+                match channel_index(name) {
+                    Some(ch) => channels.push(ch),
+                    None => {
+                        println!("Unknown channel {:?}", name);
+                        return;
+                    }
+                }
+            }
+        }
+        for ch in channels {
+            let mute = muted.unwrap_or(!self.sys.spu.channel_muted(ch));
+            self.sys.spu.set_channel_muted(ch, mute);
+        }
+    }
+}
+
+fn channel_index(name: &str) -> Option<usize> {
+    CHANNEL_NAMES.iter().position(|&n| n == name)
+}
@@ -0,0 +1,485 @@
+//! A local socket for driving the emulator from scripts.  Clients send one
+//! JSON request per line and get one JSON reply per line back, along with
+//! events like breakpoints being hit:
+//!
+//! ```text
+//! {"cmd": "pause"}
+//! {"cmd": "step", "frames": 10}
+//! {"cmd": "press", "keys": ["A", "Start"]}
+//! {"cmd": "read_memory", "addr": 33554432, "len": 16}
+//! ```
+//!
+//! Breakpoints pause the emulator at the end of the frame they were hit in,
+//! so registers and memory read after a `break` event are as they are at the
+//! end of that frame, not at the hit.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+use serde::Serialize;
+use serde_json;
+
+use cpu::reg;
+use io::key::Key;
+use link::LocalAddr;
+use mmu::MemoryUnit;
+use GBAError;
+
+use super::*;
+
+// Reads are capped at the size of the biggest RAM, EWRAM
+const MAX_READ: u32 = 256 * 1024;
+
+#[derive(Debug, Deserialize, PartialEq)]
+#[serde(tag = "cmd", rename_all = "snake_case")]
+enum Request {
+    Pause,
+    Resume,
+    /// Runs frames while paused, replying once they've run
+    Step {
+        #[serde(default = "one")]
+        frames: u32,
+    },
+    /// Holds keys down until they're released, along with any held locally
+    Press {
+        keys: Vec<Key>,
+    },
+    Release {
+        keys: Vec<Key>,
+    },
+    ReadMemory {
+        addr: u32,
+        len: u32,
+    },
+    WriteMemory {
+        addr: u32,
+        data: Vec<u8>,
+    },
+    Registers,
+    /// Replaces the breakpoints
+    Breaks {
+        addrs: Vec<u32>,
+    },
+    SaveState {
+        path: String,
+    },
+    LoadState {
+        path: String,
+    },
+    Screenshot {
+        path: String,
+    },
+    Frame,
+}
+
+fn one() -> u32 {
+    1
+}
+
+#[derive(Default, Serialize)]
+struct Reply {
+    ok: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+    frame: u64,
+    paused: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    data: Option<Vec<u8>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    registers: Option<Registers>,
+}
+
+/// The registers as seen from user mode
+#[derive(Serialize)]
+struct Registers {
+    r: Vec<u32>,
+    cpsr: u32,
+    thumb: bool,
+}
+
+#[derive(Serialize)]
+struct Event {
+    event: &'static str,
+    /// The first breakpoint hit
+    addr: u32,
+    /// Every hit in the frame, in order
+    addrs: Vec<u32>,
+    frame: u64,
+}
+
+enum Message {
+    Connected(Sender<String>),
+    Request(String, Sender<String>),
+}
+
+/// Streams that can be read on one thread while written on another
+trait Stream: Read + Write + Send + Sized + 'static {
+    fn split(&self) -> io::Result<Self>;
+}
+
+impl Stream for TcpStream {
+    fn split(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+impl Stream for UnixStream {
+    fn split(&self) -> io::Result<Self> {
+        self.try_clone()
+    }
+}
+
+/// Accepts clients on a thread of its own, with a thread reading each
+/// client's requests and another writing its replies, so the run loop can
+/// check for requests without blocking
+pub(super) struct Control {
+    rx: Receiver<Message>,
+    clients: Vec<Sender<String>>,
+    keys: KeyState,
+    /// Frames left to step through, and who asked for them
+    steps: u32,
+    step_reply: Option<Sender<String>>,
+    // Removed again when done with
+    socket_path: Option<PathBuf>,
+}
+
+impl Control {
+    pub fn new(addr: &LocalAddr) -> io::Result<Self> {
+        let (tx, rx) = channel();
+        let socket_path = match *addr {
+            LocalAddr::Tcp(port) => {
+                let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+                thread::spawn(move || accept(listener.incoming(), tx));
+                None
+            }
+            LocalAddr::Unix(ref path) => {
+                let listener = UnixListener::bind(path)?;
+                thread::spawn(move || accept(listener.incoming(), tx));
+                Some(path.clone())
+            }
+        };
+        Ok(Control {
+            rx: rx,
+            clients: vec![],
+            keys: KeyState::new(),
+            steps: 0,
+            step_reply: None,
+            socket_path: socket_path,
+        })
+    }
+
+    fn poll(&self) -> Option<Message> {
+        self.rx.try_recv().ok()
+    }
+
+    /// Sends an event to every client still connected
+    fn broadcast<T: Serialize>(&mut self, event: &T) {
+        let line = serde_json::to_string(event).unwrap();
+        self.clients
+            .retain(|client| client.send(line.clone()).is_ok());
+    }
+}
+
+impl Drop for Control {
+    fn drop(&mut self) {
+        if let Some(ref path) = self.socket_path {
+            let _ = fs::remove_file(path);
+        }
+    }
+}
+
+fn accept<S: Stream, I: Iterator<Item = io::Result<S>>>(incoming: I, tx: Sender<Message>) {
+    for stream in incoming {
+        let stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => {
+                warn!("Control client failed to connect: {}", err);
+                continue;
+            }
+        };
+        let mut writer = match stream.split() {
+            Ok(writer) => writer,
+            Err(err) => {
+                warn!("Control client failed to connect: {}", err);
+                continue;
+            }
+        };
+
+        let (out, out_rx) = channel::<String>();
+        if tx.send(Message::Connected(out.clone())).is_err() {
+            return;
+        }
+        thread::spawn(move || {
+            for line in out_rx {
+                if writeln!(writer, "{}", line).is_err() {
+                    break;
+                }
+            }
+        });
+        let tx = tx.clone();
+        thread::spawn(move || {
+            for line in BufReader::new(stream).lines() {
+                match line {
+                    Ok(line) => {
+                        if tx.send(Message::Request(line, out.clone())).is_err() {
+                            break;
+                        }
+                    }
+                    Err(_) => break,
+                }
+            }
+        });
+    }
+}
+
+impl Gba {
+    pub(super) fn start_control(&mut self) -> Result<()> {
+        if let Some(addr) = self.opts.control.clone() {
+            let control = Control::new(&addr).map_err(GBAError::ControlError)?;
+            info!("Listening for control requests on {:?}", addr);
+            self.control = Some(control);
+        }
+        Ok(())
+    }
+
+    /// Answers any requests made since the last frame
+    pub(super) fn check_control(&mut self) {
+        loop {
+            let msg = match self.control {
+                Some(ref control) => control.poll(),
+                None => return,
+            };
+            match msg {
+                Some(Message::Connected(out)) => {
+                    if let Some(ref mut control) = self.control {
+                        control.clients.push(out);
+                    }
+                }
+                Some(Message::Request(line, out)) => {
+                    if line.trim().is_empty() {
+                        continue;
+                    }
+                    let reply = match serde_json::from_str(&line) {
+                        Ok(request) => self.control_request(request, &out),
+                        Err(err) => Some(Err(format!("Bad request: {}", err))),
+                    };
+                    if let Some(reply) = reply {
+                        let _ = out.send(self.control_reply(reply));
+                    }
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// The keys held locally along with the ones clients are holding
+    pub(super) fn control_keys(&self, mut keys: KeyState) -> KeyState {
+        if let Some(ref control) = self.control {
+            for &key in Key::ALL.iter() {
+                if control.keys.is_pressed(key) {
+                    keys.press(key);
+                }
+            }
+        }
+        keys
+    }
+
+    /// Whether a frame should be run while paused, for a client stepping
+    /// through them
+    pub(super) fn take_control_step(&mut self) -> bool {
+        match self.control {
+            Some(ref mut control) if control.steps > 0 => {
+                control.steps -= 1;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Tells clients about the breakpoints hit during the frame just run,
+    /// pausing at the end of it, and answers a step once its frames have run
+    pub(super) fn control_frame_run(&mut self) {
+        let hits = mem::replace(&mut self.sys.break_hits, vec![]);
+        if self.control.is_none() {
+            return;
+        }
+        if let Some(&addr) = hits.first() {
+            self.speed.set_paused(true);
+            self.update_speed();
+            let frame = self.sys.frame;
+            let control = self.control.as_mut().unwrap();
+            control.steps = 0;
+            control.broadcast(&Event {
+                event: "break",
+                addr: addr,
+                addrs: hits,
+                frame: frame,
+            });
+        }
+        let done = {
+            let control = self.control.as_mut().unwrap();
+            if control.steps == 0 {
+                control.step_reply.take()
+            } else {
+                None
+            }
+        };
+        if let Some(out) = done {
+            let _ = out.send(self.control_reply(Ok(Reply::default())));
+        }
+    }
+
+    fn control_reply(&self, reply: ::std::result::Result<Reply, String>) -> String {
+        let mut reply = match reply {
+            Ok(reply) => Reply { ok: true, ..reply },
+            Err(err) => Reply {
+                error: Some(err),
+                ..Default::default()
+            },
+        };
+        reply.frame = self.sys.frame;
+        reply.paused = self.speed.paused();
+        serde_json::to_string(&reply).unwrap()
+    }
+
+    // None when the reply is sent later
+    fn control_request(
+        &mut self,
+        request: Request,
+        out: &Sender<String>,
+    ) -> Option<::std::result::Result<Reply, String>> {
+        let ok = Ok(Reply::default());
+        let reply = match request {
+            Request::Pause | Request::Resume => {
+                self.speed.set_paused(request == Request::Pause);
+                self.update_speed();
+                ok
+            }
+            Request::Step { frames } => {
+                self.speed.set_paused(true);
+                self.update_speed();
+                let control = self.control.as_mut().unwrap();
+                if let Some(prev) = control.step_reply.take() {
+                    let _ = prev.send(self.control_reply(Err("Step cut short".to_string())));
+                }
+                let control = self.control.as_mut().unwrap();
+                control.steps = frames;
+                if frames > 0 {
+                    control.step_reply = Some(out.clone());
+                    return None;
+                }
+                ok
+            }
+            Request::Press { keys } => {
+                let control = self.control.as_mut().unwrap();
+                for key in keys {
+                    control.keys.press(key);
+                }
+                ok
+            }
+            Request::Release { keys } => {
+                let control = self.control.as_mut().unwrap();
+                for key in keys {
+                    control.keys.release(key);
+                }
+                ok
+            }
+            Request::ReadMemory { addr, len } => {
+                if len > MAX_READ {
+                    Err(format!("Reads are limited to {} bytes", MAX_READ))
+                } else {
+                    let data = (0..len)
+                        .map(|i| self.sys.mmu.load8(addr.wrapping_add(i)))
+                        .collect();
+                    Ok(Reply {
+                        data: Some(data),
+                        ..Default::default()
+                    })
+                }
+            }
+            Request::WriteMemory { addr, data } => {
+                for (i, &byte) in data.iter().enumerate() {
+                    self.sys.mmu.set8(addr.wrapping_add(i as u32), byte);
+                }
+                ok
+            }
+            Request::Registers => Ok(Reply {
+                registers: Some(Registers {
+                    r: (0..16).map(|r| self.sys.cpu.reg(0, r)).collect(),
+                    cpsr: self.sys.cpu.reg(0, reg::CPSR),
+                    thumb: self.sys.cpu.thumb_mode(),
+                }),
+                ..Default::default()
+            }),
+            Request::Breaks { addrs } => {
+                self.opts.breaks = addrs;
+                self.sys.cpu.set_breaks(self.opts.breaks.iter());
+                ok
+            }
+            Request::SaveState { path } => match self.save_state_file(Path::new(&path)) {
+                Ok(_) => ok,
+                Err(err) => Err(format!("Failed to save state: {}", err)),
+            },
+            Request::LoadState { path } => match self.load_state_file(Path::new(&path)) {
+                Ok(_) => {
+                    self.movie_state_loaded();
+                    ok
+                }
+                Err(err) => Err(format!("Failed to load state: {:?}", err)),
+            },
+            Request::Screenshot { path } => {
+                if self.screenshot(Path::new(&path)) {
+                    ok
+                } else {
+                    Err("Failed to save screenshot".to_string())
+                }
+            }
+            Request::Frame => ok,
+        };
+        Some(reply)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_requests() {
+        let parse = |s| serde_json::from_str::<Request>(s).unwrap();
+        assert_eq!(Request::Pause, parse(r#"{"cmd": "pause"}"#));
+        assert_eq!(Request::Step { frames: 1 }, parse(r#"{"cmd": "step"}"#));
+        assert_eq!(
+            Request::Press {
+                keys: vec![Key::A, Key::Start]
+            },
+            parse(r#"{"cmd": "press", "keys": ["A", "Start"]}"#)
+        );
+        assert_eq!(
+            Request::ReadMemory {
+                addr: 0x2000000,
+                len: 4
+            },
+            parse(r#"{"cmd": "read_memory", "addr": 33554432, "len": 4}"#)
+        );
+        assert!(serde_json::from_str::<Request>(r#"{"cmd": "jump"}"#).is_err());
+        assert!(serde_json::from_str::<Request>(r#"{"cmd": "press"}"#).is_err());
+    }
+
+    #[test]
+    fn test_break_event() {
+        let event = Event {
+            event: "break",
+            addr: 0x8000100,
+            addrs: vec![0x8000100, 0x8000200, 0x8000100],
+            frame: 3,
+        };
+        assert_eq!(
+            r#"{"event":"break","addr":134217984,"addrs":[134217984,134218240,134217984],"frame":3}"#,
+            serde_json::to_string(&event).unwrap()
+        );
+    }
+}
@@ -0,0 +1,574 @@
+use std::io::Write;
+
+use sdl2::event::{Event, WindowEvent};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::rect::Rect;
+use sdl2::render::Canvas;
+use sdl2::video::Window;
+use sdl2::VideoSubsystem;
+
+use debug::{layers, sound, vram, Image};
+use io::ppu::ObjAttrs;
+use io::spu::{Spu, CHANNELS, SCOPE_LEN};
+use mmu::gba::Gba as GbaMmu;
+
+use super::*;
+
+const VRAM_WIDTH: u32 = vram::SHEET_WIDTH + vram::PALETTE_WIDTH;
+const VRAM_HEIGHT: u32 = vram::PALETTE_HEIGHT;
+
+// Big enough for a 512x512 text background, affine backgrounds are scaled
+// down to fit
+const MAP_SIZE: u32 = 512;
+
+/// A separate window that shows a debug image
+pub(super) struct DebugWindow {
+    canvas: Canvas<Window>,
+}
+
+impl DebugWindow {
+    /// Creates a window that fits `width`x`height` images, opened at `scale`
+    /// times that size
+    pub fn new(video: &VideoSubsystem, title: &str, width: u32, height: u32, scale: u32) -> Self {
+        let window = video
+            .window(title, width * scale, height * scale)
+            .resizable()
+            .build()
+            .unwrap();
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas.set_logical_size(width, height).unwrap();
+        DebugWindow { canvas: canvas }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.canvas.window().id()
+    }
+
+    pub fn set_title(&mut self, title: &str) {
+        self.canvas.window_mut().set_title(title).unwrap();
+    }
+
+    /// Draws an image scaled to fit the window, keeping its aspect ratio
+    pub fn present(&mut self, img: &Image) {
+        let creator = self.canvas.texture_creator();
+        let mut texture = creator
+            .create_texture_streaming(PixelFormatEnum::RGB888, img.width, img.height)
+            .unwrap();
+        texture
+            .update(None, &img.to_bytes(), img.width as usize * 4)
+            .unwrap();
+        let (width, height) = self.canvas.logical_size();
+        let scale = (width as f32 / img.width as f32).min(height as f32 / img.height as f32);
+        let dst = Rect::new(
+            0,
+            0,
+            (img.width as f32 * scale) as u32,
+            (img.height as f32 * scale) as u32,
+        );
+        self.canvas.clear();
+        self.canvas.copy(&texture, None, dst).unwrap();
+        self.canvas.present();
+    }
+}
+
+/// A debug window that's redrawn from memory every frame
+pub(super) trait DebugView {
+    fn window(&self) -> &DebugWindow;
+
+    /// The key that opens and closes this view
+    fn hotkey(&self) -> Scancode;
+
+    /// Handles a key pressed in the view's window, returns whether it was
+    /// used
+    fn key(&mut self, _key: Scancode) -> bool {
+        false
+    }
+
+    /// The mouse moved to (x, y) in image coordinates
+    fn mouse(&mut self, _x: i32, _y: i32) {}
+
+    /// Takes what the view needs from the sound, and applies any changes
+    /// asked for in it, before `update`
+    fn sound(&mut self, _spu: &mut Spu) {}
+
+    fn update(&mut self, mmu: &GbaMmu);
+
+    /// Saves what the view shows next to the save file prefix
+    fn save(&self, mmu: &GbaMmu, prefix: &OsStr);
+}
+
+/// Opens the view toggled by `key`, if there is one
+fn open_view(video: &VideoSubsystem, key: Scancode) -> Option<Box<dyn DebugView>> {
+    match key {
+        Scancode::F9 => Some(Box::new(VramView::new(video))),
+        Scancode::F10 => Some(Box::new(MapView::new(video))),
+        Scancode::F11 => Some(Box::new(OamView::new(video))),
+        Scancode::O => Some(Box::new(SoundView::new(video))),
+        _ => None,
+    }
+}
+
+pub(super) fn save_path(prefix: &OsStr, suffix: &str) -> OsString {
+    let mut path = prefix.to_os_string();
+    path.push(suffix);
+    path
+}
+
+/// Tile sheet of a character block next to the full palette.
+///
+/// Left/Right change the character block, Up/Down the palette bank, Tab
+/// switches between 4bpp and 8bpp, and S saves both images as PNGs next to
+/// the save file prefix.  Hovering over a palette swatch shows its value in
+/// the title bar.
+pub(super) struct VramView {
+    window: DebugWindow,
+    charblock: u32,
+    bpp8: bool,
+    palette: u32,
+    hover: Option<u32>,
+}
+
+impl VramView {
+    fn new(video: &VideoSubsystem) -> Self {
+        VramView {
+            window: DebugWindow::new(video, "VRAM", VRAM_WIDTH, VRAM_HEIGHT, 2),
+            charblock: 0,
+            bpp8: false,
+            palette: 0,
+            hover: None,
+        }
+    }
+
+    fn sheet(&self, mmu: &GbaMmu) -> Image {
+        vram::tile_sheet(mmu, self.charblock, self.bpp8, self.palette)
+    }
+}
+
+impl DebugView for VramView {
+    fn window(&self) -> &DebugWindow {
+        &self.window
+    }
+
+    fn hotkey(&self) -> Scancode {
+        Scancode::F9
+    }
+
+    fn key(&mut self, key: Scancode) -> bool {
+        use self::Scancode::*;
+        match key {
+            Right => self.charblock = (self.charblock + 1) % vram::CHARBLOCKS,
+            Left => self.charblock = (self.charblock + vram::CHARBLOCKS - 1) % vram::CHARBLOCKS,
+            Up => self.palette = (self.palette + 1) % vram::PALETTE_BANKS,
+            Down => self.palette = (self.palette + vram::PALETTE_BANKS - 1) % vram::PALETTE_BANKS,
+            Tab => self.bpp8 = !self.bpp8,
+            _ => return false,
+        }
+        true
+    }
+
+    fn mouse(&mut self, x: i32, y: i32) {
+        let px = x - vram::SHEET_WIDTH as i32;
+        self.hover = if px >= 0 && y >= 0 {
+            vram::swatch_at(px as u32, y as u32)
+        } else {
+            None
+        };
+    }
+
+    fn update(&mut self, mmu: &GbaMmu) {
+        let mut img = Image::new(VRAM_WIDTH, VRAM_HEIGHT);
+        img.blit(&self.sheet(mmu), 0, 0);
+        img.blit(&vram::palette_swatches(mmu), vram::SHEET_WIDTH, 0);
+        self.window.present(&img);
+
+        let mut title = format!(
+            "VRAM: charblock {}, {}bpp, {} palette {}",
+            self.charblock,
+            if self.bpp8 { 8 } else { 4 },
+            if self.palette < 16 { "bg" } else { "obj" },
+            self.palette % 16
+        );
+        if let Some(entry) = self.hover {
+            title.push_str(" | ");
+            title.push_str(&vram::describe_entry(
+                entry,
+                vram::palette_entry(mmu, entry),
+            ));
+        }
+        self.window.set_title(&title);
+    }
+
+    fn save(&self, mmu: &GbaMmu, prefix: &OsStr) {
+        let sheet_path = save_path(prefix, &format!(".charblock{}.png", self.charblock));
+        let palette_path = save_path(prefix, ".palette.png");
+
+        let res = self
+            .sheet(mmu)
+            .save_png(Path::new(&sheet_path))
+            .and_then(|_| vram::palette_swatches(mmu).save_png(Path::new(&palette_path)));
+        match res {
+            Ok(_) => info!("Saved {:?} and {:?}", sheet_path, palette_path),
+            Err(err) => error!("Failed to save VRAM images: {}", err),
+        }
+    }
+}
+
+/// A whole background layer with the visible part outlined.
+///
+/// Left/Right change the background and S saves it as a PNG.
+pub(super) struct MapView {
+    window: DebugWindow,
+    bg: u8,
+}
+
+impl MapView {
+    fn new(video: &VideoSubsystem) -> Self {
+        MapView {
+            window: DebugWindow::new(video, "Background", MAP_SIZE, MAP_SIZE, 1),
+            bg: 0,
+        }
+    }
+}
+
+impl DebugView for MapView {
+    fn window(&self) -> &DebugWindow {
+        &self.window
+    }
+
+    fn hotkey(&self) -> Scancode {
+        Scancode::F10
+    }
+
+    fn key(&mut self, key: Scancode) -> bool {
+        match key {
+            Scancode::Right => self.bg = (self.bg + 1) % 4,
+            Scancode::Left => self.bg = (self.bg + 3) % 4,
+            _ => return false,
+        }
+        true
+    }
+
+    fn update(&mut self, mmu: &GbaMmu) {
+        let title = match layers::bg_image(mmu, self.bg) {
+            Some(img) => {
+                self.window.present(&img);
+                format!("BG{}: {}x{}", self.bg, img.width, img.height)
+            }
+            None => {
+                self.window.present(&Image::new(MAP_SIZE, MAP_SIZE));
+                format!("BG{}: not used in this mode", self.bg)
+            }
+        };
+        self.window.set_title(&title);
+    }
+
+    fn save(&self, mmu: &GbaMmu, prefix: &OsStr) {
+        let path = save_path(prefix, &format!(".bg{}.png", self.bg));
+        match layers::bg_image(mmu, self.bg) {
+            Some(img) => match img.save_png(Path::new(&path)) {
+                Ok(_) => info!("Saved {:?}", path),
+                Err(err) => error!("Failed to save background: {}", err),
+            },
+            None => warn!("BG{} isn't used in this mode", self.bg),
+        }
+    }
+}
+
+/// Every object in OAM drawn on its own.
+///
+/// Hovering over an object shows its attributes in the title bar, and S saves
+/// the sheet as a PNG along with a listing of all the objects.
+pub(super) struct OamView {
+    window: DebugWindow,
+    hover: Option<u32>,
+}
+
+impl OamView {
+    fn new(video: &VideoSubsystem) -> Self {
+        OamView {
+            window: DebugWindow::new(video, "OAM", layers::OAM_WIDTH, layers::OAM_HEIGHT, 1),
+            hover: None,
+        }
+    }
+}
+
+impl DebugView for OamView {
+    fn window(&self) -> &DebugWindow {
+        &self.window
+    }
+
+    fn hotkey(&self) -> Scancode {
+        Scancode::F11
+    }
+
+    fn mouse(&mut self, x: i32, y: i32) {
+        self.hover = if x >= 0 && y >= 0 {
+            layers::oam_at(x as u32, y as u32)
+        } else {
+            None
+        };
+    }
+
+    fn update(&mut self, mmu: &GbaMmu) {
+        self.window.present(&layers::oam_sheet(mmu));
+        let title = match self.hover {
+            Some(o) => format!(
+                "OAM | {}",
+                layers::describe_obj(mmu, &ObjAttrs::decode(mmu, o))
+            ),
+            None => "OAM".to_string(),
+        };
+        self.window.set_title(&title);
+    }
+
+    fn save(&self, mmu: &GbaMmu, prefix: &OsStr) {
+        let sheet_path = save_path(prefix, ".oam.png");
+        let listing_path = save_path(prefix, ".oam.txt");
+
+        let res = layers::oam_sheet(mmu)
+            .save_png(Path::new(&sheet_path))
+            .and_then(|_| File::create(Path::new(&listing_path)))
+            .and_then(|mut f| f.write_all(layers::oam_listing(mmu).as_bytes()));
+        match res {
+            Ok(_) => info!("Saved {:?} and {:?}", sheet_path, listing_path),
+            Err(err) => error!("Failed to save OAM: {}", err),
+        }
+    }
+}
+
+/// Oscilloscope of each sound channel, with how full the FIFOs are.
+///
+/// 1-6 toggle muting each channel, F1-F6 solo one and 0 unmutes them all.
+/// The title bar shows SOUNDCNT_L/H/X and SOUNDBIAS decoded, or the registers
+/// of the channel under the mouse.  S saves the oscilloscope as a PNG along
+/// with a listing of all the sound registers.
+pub(super) struct SoundView {
+    window: DebugWindow,
+    hover: Option<usize>,
+    history: Vec<[f32; CHANNELS]>,
+    muted: [bool; CHANNELS],
+    changes: Vec<SoundChange>,
+}
+
+enum SoundChange {
+    ToggleMute(usize),
+    Solo(usize),
+    UnmuteAll,
+}
+
+const MUTE_KEYS: [Scancode; CHANNELS] = [
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Num5,
+    Scancode::Num6,
+];
+const SOLO_KEYS: [Scancode; CHANNELS] = [
+    Scancode::F1,
+    Scancode::F2,
+    Scancode::F3,
+    Scancode::F4,
+    Scancode::F5,
+    Scancode::F6,
+];
+
+impl SoundView {
+    fn new(video: &VideoSubsystem) -> Self {
+        SoundView {
+            window: DebugWindow::new(video, "Sound", sound::SCOPE_WIDTH, sound::SCOPE_HEIGHT, 1),
+            hover: None,
+            history: vec![[0.0; CHANNELS]; SCOPE_LEN],
+            muted: [false; CHANNELS],
+            changes: vec![],
+        }
+    }
+}
+
+impl DebugView for SoundView {
+    fn window(&self) -> &DebugWindow {
+        &self.window
+    }
+
+    fn hotkey(&self) -> Scancode {
+        Scancode::O
+    }
+
+    fn key(&mut self, key: Scancode) -> bool {
+        if let Some(ch) = MUTE_KEYS.iter().position(|&k| k == key) {
+            self.changes.push(SoundChange::ToggleMute(ch));
+        } else if let Some(ch) = SOLO_KEYS.iter().position(|&k| k == key) {
+            self.changes.push(SoundChange::Solo(ch));
+        } else if key == Scancode::Num0 {
+            self.changes.push(SoundChange::UnmuteAll);
+        } else {
+            return false;
+        }
+        true
+    }
+
+    fn mouse(&mut self, x: i32, y: i32) {
+        self.hover = if x >= 0 && y >= 0 {
+            sound::scope_row(y as u32)
+        } else {
+            None
+        };
+    }
+
+    fn sound(&mut self, spu: &mut Spu) {
+        for change in self.changes.drain(..) {
+            match change {
+                SoundChange::ToggleMute(ch) => {
+                    let muted = spu.channel_muted(ch);
+                    spu.set_channel_muted(ch, !muted);
+                }
+                SoundChange::Solo(ch) => spu.solo(ch),
+                SoundChange::UnmuteAll => {
+                    for ch in 0..CHANNELS {
+                        spu.set_channel_muted(ch, false);
+                    }
+                }
+            }
+        }
+        self.history = spu.scope();
+        for ch in 0..CHANNELS {
+            self.muted[ch] = spu.channel_muted(ch);
+        }
+    }
+
+    fn update(&mut self, mmu: &GbaMmu) {
+        self.window
+            .present(&sound::scope_image(&mmu.io, &self.history, &self.muted));
+        let title = match self.hover {
+            Some(ch) => format!("Sound | {}", sound::describe_channel(&mmu.io, ch)),
+            None => format!("Sound | {}", sound::describe_mixer(&mmu.io)),
+        };
+        self.window.set_title(&title);
+    }
+
+    fn save(&self, mmu: &GbaMmu, prefix: &OsStr) {
+        let scope_path = save_path(prefix, ".scope.png");
+        let listing_path = save_path(prefix, ".sound.txt");
+
+        let res = sound::scope_image(&mmu.io, &self.history, &self.muted)
+            .save_png(Path::new(&scope_path))
+            .and_then(|_| File::create(Path::new(&listing_path)))
+            .and_then(|mut f| f.write_all(sound::describe_sound(&mmu.io).as_bytes()));
+        match res {
+            Ok(_) => info!("Saved {:?} and {:?}", scope_path, listing_path),
+            Err(err) => error!("Failed to save sound: {}", err),
+        }
+    }
+}
+
+/// Writes what the debug views show for a save state out next to it: a tile
+/// sheet for each character block, the palette swatches, each background
+/// the display mode uses, the OAM sheet, and listings of the palette values
+/// and objects.
+pub fn debug_dump(state: &Path, bpp8: bool, palette: u32) -> Result<()> {
+    use GBAError::OutputError;
+
+    let mem = StateMemory::load(state)?;
+    let path = |suffix: String| {
+        let mut p = state.as_os_str().to_os_string();
+        p.push(suffix);
+        p
+    };
+
+    for cb in 0..vram::CHARBLOCKS {
+        let sheet_path = path(format!(".charblock{}.png", cb));
+        vram::tile_sheet(&mem.mmu, cb, bpp8, palette)
+            .save_png(Path::new(&sheet_path))
+            .map_err(OutputError)?;
+    }
+    vram::palette_swatches(&mem.mmu)
+        .save_png(Path::new(&path(".palette.png".to_string())))
+        .map_err(OutputError)?;
+
+    let mut listing =
+        File::create(Path::new(&path(".palette.txt".to_string()))).map_err(OutputError)?;
+    listing
+        .write_all(vram::palette_listing(&mem.mmu).as_bytes())
+        .map_err(OutputError)?;
+
+    for bg in 0..4 {
+        if let Some(img) = layers::bg_image(&mem.mmu, bg) {
+            img.save_png(Path::new(&path(format!(".bg{}.png", bg))))
+                .map_err(OutputError)?;
+        }
+    }
+    layers::oam_sheet(&mem.mmu)
+        .save_png(Path::new(&path(".oam.png".to_string())))
+        .map_err(OutputError)?;
+    let mut listing =
+        File::create(Path::new(&path(".oam.txt".to_string()))).map_err(OutputError)?;
+    listing
+        .write_all(layers::oam_listing(&mem.mmu).as_bytes())
+        .map_err(OutputError)?;
+    info!("Dumped VRAM and OAM from {:?}", state);
+    Ok(())
+}
+
+impl Gba {
+    /// F9 toggles the VRAM viewer, F10 the background viewer, F11 the OAM
+    /// viewer and O the sound viewer
+    pub(super) fn check_debug_views(&mut self, key: Scancode) {
+        match self.debug_views.iter().position(|v| v.hotkey() == key) {
+            Some(i) => {
+                self.debug_views.remove(i);
+            }
+            None => {
+                if let Some(view) = open_view(&self.ctx.video().unwrap(), key) {
+                    self.debug_views.push(view);
+                }
+            }
+        }
+    }
+
+    /// Handles events aimed at debug windows, returns whether the event was
+    /// used
+    pub(super) fn debug_event(&mut self, event: &Event) -> bool {
+        let id = match *event {
+            Event::Window { window_id, .. }
+            | Event::KeyDown { window_id, .. }
+            | Event::MouseMotion { window_id, .. } => window_id,
+            _ => return false,
+        };
+        let i = match self.debug_views.iter().position(|v| v.window().id() == id) {
+            Some(i) => i,
+            None => return false,
+        };
+        match *event {
+            Event::Window {
+                win_event: WindowEvent::Close,
+                ..
+            } => {
+                self.debug_views.remove(i);
+                true
+            }
+            Event::KeyDown {
+                scancode: Some(Scancode::S),
+                ..
+            } => {
+                self.debug_views[i].save(&self.sys.mmu, &self.opts.save_file);
+                true
+            }
+            Event::KeyDown {
+                scancode: Some(code),
+                ..
+            } => self.debug_views[i].key(code),
+            Event::MouseMotion { x, y, .. } => {
+                self.debug_views[i].mouse(x, y);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(super) fn update_debug_views(&mut self) {
+        for view in &mut self.debug_views {
+            view.sound(&mut self.sys.spu);
+            view.update(&self.sys.mmu);
+        }
+    }
+}
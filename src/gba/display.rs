@@ -0,0 +1,71 @@
+//! Getting frames from the PPU to the window: post-processing, upscaling,
+//! drawing the on-screen display over them and fitting them to the window's
+//! size
+
+use sdl2::rect::Rect;
+
+use super::*;
+
+impl Gba {
+    // Upscales the last processed frame into the texture, which is remade if
+    // the scaler's size has changed.  This happens even when no frame's been
+    // run so the on-screen display stays up to date while paused.
+    fn upload_frame<'t>(
+        &mut self,
+        creator: &'t TextureCreator<WindowContext>,
+        texture: &mut Texture<'t>,
+    ) {
+        if self.filter.last().is_empty() {
+            return;
+        }
+        let scale = self.upscaler.factor();
+        let (frame, width, height) = self.upscaler.scale(self.filter.last(), COLS, ROWS);
+        self.osd.draw(frame, width, scale, Instant::now());
+        let query = texture.query();
+        if (query.width, query.height) != (width, height) {
+            *texture = creator
+                .create_texture_streaming(PixelFormatEnum::RGB888, width, height)
+                .unwrap();
+        }
+        let mut bytes = vec![0u8; frame.len() * 4];
+        LittleEndian::write_u32_into(frame, &mut bytes);
+        texture.update(None, &bytes, width as usize * 4).unwrap();
+    }
+
+    /// Where the frame is drawn in the window, as the options say to fit it
+    pub(super) fn frame_rect(&self) -> Rect {
+        let window = self.canvas.output_size().unwrap();
+        let (x, y, w, h) = self.upscaler.frame_rect(window, (COLS, ROWS));
+        Rect::new(x, y, w.max(1), h.max(1))
+    }
+
+    pub(super) fn draw_frame<'t>(
+        &mut self,
+        creator: &'t TextureCreator<WindowContext>,
+        texture: &mut Texture<'t>,
+    ) {
+        self.upload_frame(creator, texture);
+        let rect = self.frame_rect();
+        self.canvas.clear();
+        self.canvas.copy(texture, None, rect).unwrap();
+    }
+
+    /// G switches between the scalers, M between the LCD masks
+    pub(super) fn check_display_keys(&mut self, key: Scancode) {
+        match key {
+            Scancode::G => {
+                self.upscaler.next_scaler();
+                let msg = format!("Scaler: {:?}", self.upscaler.scaler());
+                info!("{}", msg);
+                self.osd.message(msg);
+            }
+            Scancode::M => {
+                self.upscaler.next_mask();
+                let msg = format!("Mask: {:?}", self.upscaler.mask());
+                info!("{}", msg);
+                self.osd.message(msg);
+            }
+            _ => {}
+        }
+    }
+}
@@ -0,0 +1,317 @@
+//! Mapping from the keyboard and game controllers onto the GBA's keys
+
+use std::collections::BTreeMap;
+use std::io::Read;
+
+use sdl2::controller::{Axis, Button, GameController};
+use sdl2::keyboard::KeyboardState;
+use sdl2::GameControllerSubsystem;
+use serde_json;
+
+use io::key::Key;
+
+use super::*;
+
+const DEFAULT_DEAD_ZONE: u16 = 8000;
+
+/// Keys the main window acts on itself, so a GBA key bound to one gets
+/// pressed along with the hotkey
+const HOTKEYS: [Scancode; 38] = [
+    Scancode::Escape,
+    Scancode::Tab,
+    Scancode::Backspace,
+    Scancode::Space,
+    Scancode::Grave,
+    Scancode::Minus,
+    Scancode::B,
+    Scancode::C,
+    Scancode::G,
+    Scancode::H,
+    Scancode::M,
+    Scancode::O,
+    Scancode::R,
+    Scancode::V,
+    Scancode::Num0,
+    Scancode::Num1,
+    Scancode::Num2,
+    Scancode::Num3,
+    Scancode::Num4,
+    Scancode::Num5,
+    Scancode::Num6,
+    Scancode::Num7,
+    Scancode::Num8,
+    Scancode::Num9,
+    Scancode::F1,
+    Scancode::F2,
+    Scancode::F3,
+    Scancode::F4,
+    Scancode::F5,
+    Scancode::F6,
+    Scancode::F7,
+    Scancode::F8,
+    Scancode::F9,
+    Scancode::F10,
+    Scancode::F11,
+    Scancode::F12,
+    Scancode::LCtrl,
+    Scancode::RCtrl,
+];
+
+/// Which inputs press each GBA key.
+///
+/// Loaded from a JSON file that maps key names (`A`, `B`, `Select`, `Start`,
+/// `Right`, `Left`, `Up`, `Down`, `R`, `L`) onto lists of inputs:
+///
+/// ```json
+/// {
+///     "keyboard": { "A": ["L", "Return"], "Up": ["W", "Up"] },
+///     "controller": { "A": ["b"], "Up": ["dpup", "lefty-"] },
+///     "dead_zone": 8000
+/// }
+/// ```
+///
+/// Keyboard inputs are SDL scancode names.  Controller inputs are SDL game
+/// controller button names, or axis names followed by `+` or `-` for the
+/// direction the stick has to be pushed past `dead_zone` (out of 32767) to
+/// count.  A missing `keyboard` or `controller` section keeps the default
+/// bindings for it, keys left out of a section present are unbound.  Binding
+/// one of the window's own hotkeys, like Space for pause, is allowed but
+/// warned about.
+#[derive(Clone, Debug)]
+pub struct InputMap {
+    keys: Vec<(Scancode, Key)>,
+    buttons: Vec<(Button, Key)>,
+    axes: Vec<(Axis, bool, Key)>,
+    dead_zone: u16,
+}
+
+#[derive(Deserialize)]
+struct InputConfig {
+    keyboard: Option<BTreeMap<Key, Vec<String>>>,
+    controller: Option<BTreeMap<Key, Vec<String>>>,
+    dead_zone: Option<u16>,
+}
+
+impl Default for InputMap {
+    fn default() -> Self {
+        InputMap {
+            keys: vec![
+                (Scancode::L, Key::A),
+                (Scancode::K, Key::B),
+                (Scancode::Z, Key::Select),
+                (Scancode::X, Key::Start),
+                (Scancode::D, Key::Right),
+                (Scancode::A, Key::Left),
+                (Scancode::W, Key::Up),
+                (Scancode::S, Key::Down),
+                (Scancode::P, Key::R),
+                (Scancode::I, Key::L),
+            ],
+            // Face buttons by position, so the right one is A like on a GBA
+            buttons: vec![
+                (Button::B, Key::A),
+                (Button::A, Key::B),
+                (Button::Back, Key::Select),
+                (Button::Start, Key::Start),
+                (Button::DPadRight, Key::Right),
+                (Button::DPadLeft, Key::Left),
+                (Button::DPadUp, Key::Up),
+                (Button::DPadDown, Key::Down),
+                (Button::RightShoulder, Key::R),
+                (Button::LeftShoulder, Key::L),
+            ],
+            axes: vec![
+                (Axis::LeftX, true, Key::Right),
+                (Axis::LeftX, false, Key::Left),
+                (Axis::LeftY, false, Key::Up),
+                (Axis::LeftY, true, Key::Down),
+            ],
+            dead_zone: DEFAULT_DEAD_ZONE,
+        }
+    }
+}
+
+impl InputMap {
+    pub fn load(path: &Path) -> Result<Self> {
+        use GBAError::ConfigError;
+
+        let mut text = String::new();
+        File::open(path)
+            .and_then(|mut f| f.read_to_string(&mut text))
+            .map_err(|err| ConfigError(format!("{:?}: {}", path, err)))?;
+        let config: InputConfig = serde_json::from_str(&text)
+            .map_err(|err| ConfigError(format!("{:?}: {}", path, err)))?;
+
+        let mut map = InputMap::default();
+        if let Some(keyboard) = config.keyboard {
+            map.keys.clear();
+            for (&key, names) in &keyboard {
+                for name in names {
+                    let code = Scancode::from_name(name)
+                        .ok_or_else(|| ConfigError(format!("Unknown key {:?}", name)))?;
+                    map.keys.push((code, key));
+                }
+            }
+        }
+        if let Some(controller) = config.controller {
+            map.buttons.clear();
+            map.axes.clear();
+            for (&key, names) in &controller {
+                for name in names {
+                    match split_axis(name) {
+                        Some((axis, positive)) => {
+                            let axis = Axis::from_string(axis)
+                                .ok_or_else(|| ConfigError(format!("Unknown axis {:?}", name)))?;
+                            map.axes.push((axis, positive, key));
+                        }
+                        None => {
+                            let button = Button::from_string(name)
+                                .ok_or_else(|| ConfigError(format!("Unknown button {:?}", name)))?;
+                            map.buttons.push((button, key));
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(dead_zone) = config.dead_zone {
+            map.dead_zone = dead_zone;
+        }
+        for (code, key) in map.hotkey_clashes() {
+            warn!("{} is bound to {:?} but is also a hotkey", code.name(), key);
+        }
+        Ok(map)
+    }
+
+    /// Keyboard bindings on keys the window also uses as hotkeys
+    fn hotkey_clashes(&self) -> Vec<(Scancode, Key)> {
+        self.keys
+            .iter()
+            .filter(|&&(code, _)| HOTKEYS.contains(&code))
+            .cloned()
+            .collect()
+    }
+
+    /// The GBA keys pressed by the keyboard keys `held` says are down
+    pub fn keyboard_state<F: Fn(Scancode) -> bool>(&self, held: F) -> KeyState {
+        let mut state = KeyState::new();
+        for &(code, key) in &self.keys {
+            if held(code) {
+                state.press(key);
+            }
+        }
+        state
+    }
+}
+
+/// Splits an axis binding like `leftx+` into the axis name and whether it's
+/// the positive direction, or None if it's not an axis binding
+fn split_axis(name: &str) -> Option<(&str, bool)> {
+    if name.ends_with('+') {
+        Some((&name[..name.len() - 1], true))
+    } else if name.ends_with('-') {
+        Some((&name[..name.len() - 1], false))
+    } else {
+        None
+    }
+}
+
+#[inline]
+fn axis_pushed(value: i16, positive: bool, dead_zone: u16) -> bool {
+    if positive {
+        value as i32 > dead_zone as i32
+    } else {
+        (value as i32) < -(dead_zone as i32)
+    }
+}
+
+/// The input mapping along with the controllers that are plugged in
+pub(super) struct Input {
+    map: InputMap,
+    subsystem: GameControllerSubsystem,
+    controllers: Vec<GameController>,
+}
+
+impl Input {
+    pub fn new(map: InputMap, subsystem: GameControllerSubsystem) -> Self {
+        Input {
+            map: map,
+            subsystem: subsystem,
+            controllers: vec![],
+        }
+    }
+
+    /// Opens a newly connected controller.  SDL also reports the controllers
+    /// connected at startup this way.
+    pub fn add_controller(&mut self, index: u32) {
+        match self.subsystem.open(index) {
+            Ok(controller) => {
+                info!("Controller connected: {}", controller.name());
+                self.controllers.push(controller);
+            }
+            Err(err) => warn!("Failed to open controller {}: {}", index, err),
+        }
+    }
+
+    pub fn remove_controller(&mut self, id: i32) {
+        self.controllers.retain(|c| c.instance_id() != id);
+    }
+
+    pub fn key_state(&self, keys: &KeyboardState) -> KeyState {
+        let map = &self.map;
+        let mut state = map.keyboard_state(|code| keys.is_scancode_pressed(code));
+        for controller in &self.controllers {
+            for &(button, key) in &map.buttons {
+                if controller.button(button) {
+                    state.press(key);
+                }
+            }
+            for &(axis, positive, key) in &map.axes {
+                if axis_pushed(controller.axis(axis), positive, map.dead_zone) {
+                    state.press(key);
+                }
+            }
+        }
+        state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_split_axis() {
+        assert_eq!(Some(("leftx", true)), split_axis("leftx+"));
+        assert_eq!(Some(("lefty", false)), split_axis("lefty-"));
+        assert_eq!(None, split_axis("dpup"));
+    }
+
+    #[test]
+    fn test_axis_dead_zone() {
+        assert!(!axis_pushed(8000, true, 8000));
+        assert!(axis_pushed(8001, true, 8000));
+        assert!(!axis_pushed(-32768, true, 8000));
+        assert!(axis_pushed(-32768, false, 8000));
+        assert!(!axis_pushed(-100, false, 8000));
+    }
+
+    #[test]
+    fn test_config_format() {
+        let config: InputConfig = serde_json::from_str(
+            r#"{ "controller": { "A": ["b", "x"], "Up": ["lefty-"] }, "dead_zone": 100 }"#,
+        )
+        .unwrap();
+        assert!(config.keyboard.is_none());
+        let controller = config.controller.unwrap();
+        assert_eq!(vec!["b", "x"], controller[&Key::A]);
+        assert_eq!(Some(100), config.dead_zone);
+    }
+
+    #[test]
+    fn test_hotkey_clashes() {
+        let mut map = InputMap::default();
+        assert!(map.hotkey_clashes().is_empty());
+        map.keys.push((Scancode::Space, Key::A));
+        assert_eq!(vec![(Scancode::Space, Key::A)], map.hotkey_clashes());
+    }
+}
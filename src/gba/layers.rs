@@ -0,0 +1,96 @@
+use sdl2::pixels::Color;
+use sdl2::rect::Rect;
+
+use io::ppu::Layer;
+
+use super::*;
+
+/// Hotkeys that toggle forcing each layer off
+const LAYER_KEYS: [(Scancode, Layer); 9] = [
+    (Scancode::F1, Layer::Bg0),
+    (Scancode::F2, Layer::Bg1),
+    (Scancode::F3, Layer::Bg2),
+    (Scancode::F4, Layer::Bg3),
+    (Scancode::F5, Layer::Obj),
+    (Scancode::F6, Layer::ObjWindow),
+    (Scancode::F7, Layer::Win0),
+    (Scancode::F8, Layer::Win1),
+    (Scancode::F12, Layer::Effects),
+];
+
+// Each hidden layer gets a marker of its own colour in its own slot along the
+// top of the screen, in the order of `Layer::ALL`
+const MARKER_SIZE: u32 = 4;
+const MARKER_COLOURS: [(u8, u8, u8); 9] = [
+    (0xff, 0x40, 0x40),
+    (0x40, 0xff, 0x40),
+    (0x40, 0x80, 0xff),
+    (0xff, 0xff, 0x40),
+    (0xff, 0x40, 0xff),
+    (0x80, 0x40, 0xc0),
+    (0x40, 0xff, 0xff),
+    (0x40, 0xa0, 0xa0),
+    (0xff, 0xff, 0xff),
+];
+
+impl Gba {
+    /// F1-F4 toggle BG0-3, F5 OBJ, F6 the OBJ window, F7/F8 WIN0/WIN1 and
+    /// F12 colour effects
+    pub(super) fn check_layer_keys(&mut self, key: Scancode) {
+        if let Some(&(_, layer)) = LAYER_KEYS.iter().find(|&&(k, _)| k == key) {
+            let hidden = !self.sys.ppu.layer_hidden(layer);
+            self.set_layer_hidden(layer, hidden);
+        }
+    }
+
+    pub(super) fn set_layer_hidden(&mut self, layer: Layer, hidden: bool) {
+        self.sys.ppu.set_layer_hidden(layer, hidden);
+        let msg = format!(
+            "{} {}",
+            layer.name(),
+            if hidden { "forced off" } else { "shown" }
+        );
+        info!("{}", msg);
+        self.osd.message(msg);
+
+        let hidden = self.hidden_layers();
+        let title = if hidden.is_empty() {
+            "GBA".to_string()
+        } else {
+            format!("GBA [off: {}]", hidden.join(" "))
+        };
+        self.canvas.window_mut().set_title(&title).unwrap();
+    }
+
+    pub(super) fn hidden_layers(&self) -> Vec<&'static str> {
+        Layer::ALL
+            .iter()
+            .filter(|&&l| self.sys.ppu.layer_hidden(l))
+            .map(|l| l.name())
+            .collect()
+    }
+
+    /// Marks the layers that are forced off along the top of the screen
+    pub(super) fn draw_layer_indicator(&mut self) {
+        // Sized in GBA pixels, so they scale with the frame
+        let frame = self.frame_rect();
+        let scale = (frame.width() / COLS).max(1);
+        for (i, &layer) in Layer::ALL.iter().enumerate() {
+            if !self.sys.ppu.layer_hidden(layer) {
+                continue;
+            }
+            let (r, g, b) = MARKER_COLOURS[i];
+            self.canvas.set_draw_color(Color::RGB(r, g, b));
+            let x = 1 + (i as u32) * (MARKER_SIZE + 1);
+            self.canvas
+                .fill_rect(Rect::new(
+                    frame.x() + (x * scale) as i32,
+                    frame.y() + scale as i32,
+                    MARKER_SIZE * scale,
+                    MARKER_SIZE * scale,
+                ))
+                .unwrap();
+        }
+        self.canvas.set_draw_color(Color::RGB(0, 0, 0));
+    }
+}
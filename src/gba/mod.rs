@@ -3,15 +3,16 @@ use std::default::Default;
 use std::ffi::{OsStr, OsString};
 use std::fs::File;
 use std::mem;
-use std::path::Path;
-use std::ptr;
+use std::path::{Path, PathBuf};
 use std::thread;
 use std::time::{Duration, Instant};
 
+use byteorder::{ByteOrder, LittleEndian};
 use flame;
 
 use sdl2;
-use sdl2::audio::{AudioDevice, AudioSpecDesired};
+use sdl2::audio::AudioDevice;
+use sdl2::event::Event;
 use sdl2::keyboard::Scancode;
 use sdl2::pixels::PixelFormatEnum;
 use sdl2::render::{Canvas, Texture, TextureCreator};
@@ -20,20 +21,38 @@ use sdl2::Sdl;
 
 use shared::Shared;
 
+use GBAError;
 use Result;
 
-use cpu::Cpu;
 use io::key::KeyState;
-use io::ppu::{Ppu, COLS, ROWS};
-use io::spu::{SoundBuf, Spu, FREQ, SAMPLES};
-use io::IoReg;
-use mmu::gba::Gba as GbaMmu;
+use io::ppu::{COLS, ROWS};
+use io::spu::SoundBuf;
+use link::{Link, LinkSetup, LocalAddr};
 use rom::GameRom;
-
+use system::{System, CYCLES_PER_FRAME, CYCLES_PER_SEC};
+use video;
+
+mod console;
+mod control;
+mod debug_view;
+mod display;
+mod input;
+mod layers;
+mod movie;
+mod osd;
+mod recording;
+mod rewind;
 mod save_state;
+mod speed;
 
-const CYCLES_PER_SEC: u64 = 16 * 1024 * 1024;
-const CYCLES_PER_FRAME: u64 = 280896;
+pub use self::debug_view::debug_dump;
+pub use self::input::InputMap;
+pub use self::rewind::RewindOptions;
+pub use self::save_state::StateMemory;
+pub use self::speed::{parse_turbo, SyncMode};
+pub use io::spu::AudioOptions;
+pub use video::filter::{ColourCorrection, FilterOptions, FrameBlend};
+pub use video::scale::{DisplayOptions, Mask, Scaler};
 
 #[derive(Clone, Debug)]
 pub struct Options {
@@ -42,6 +61,38 @@ pub struct Options {
     pub step_frames: bool,
     pub direct_boot: bool,
     pub save_file: OsString,
+    pub console: bool,
+    /// Socket to take JSON control requests on
+    pub control: Option<LocalAddr>,
+    /// Link cable to other emulators
+    pub link: Option<LinkSetup>,
+    pub input: InputMap,
+    pub record_movie: Option<OsString>,
+    pub play_movie: Option<OsString>,
+    /// Save state to start the recorded movie from
+    pub movie_state: Option<OsString>,
+    pub rewind: RewindOptions,
+    /// Speed multiplier when fast-forwarding, None runs as fast as possible
+    pub turbo: Option<f32>,
+    pub sync: SyncMode,
+    /// None runs without sound
+    pub audio: Option<AudioOptions>,
+    pub record_audio: Option<OsString>,
+    /// Also record each sound channel on its own
+    pub record_stems: bool,
+    /// Colour correction and frame blending for what's shown
+    pub filter: FilterOptions,
+    /// Upscaling and how the frame fits the window
+    pub display: DisplayOptions,
+    /// Show the frame rate, audio buffer and keys held over the frame
+    pub show_stats: bool,
+    pub record_video: Option<OsString>,
+    /// Record the sound along with videos
+    pub record_video_audio: bool,
+    /// Frames to save a screenshot after, counted from power on
+    pub screenshot_frames: Vec<u64>,
+    /// Screenshots also get a copy upscaled this many times when above 1
+    pub screenshot_scale: u32,
 }
 
 impl Default for Options {
@@ -52,111 +103,151 @@ impl Default for Options {
             step_frames: false,
             direct_boot: false,
             save_file: OsStr::new("gba").to_os_string(),
+            console: false,
+            control: None,
+            link: None,
+            input: Default::default(),
+            record_movie: None,
+            play_movie: None,
+            movie_state: None,
+            rewind: Default::default(),
+            turbo: None,
+            sync: SyncMode::Audio,
+            audio: Some(Default::default()),
+            record_audio: None,
+            record_stems: false,
+            filter: Default::default(),
+            display: Default::default(),
+            show_stats: false,
+            record_video: None,
+            record_video_audio: false,
+            screenshot_frames: vec![],
+            screenshot_scale: 1,
         }
     }
 }
 
 /// Parent container for all components of the system
-pub struct Gba<'a> {
+pub struct Gba {
     opts: Options,
 
-    pub ctx: Sdl,
-
+    // Fields are dropped in order, so SDL is shut down last
     canvas: Canvas<Window>,
-    texture_creator: TextureCreator<WindowContext>,
-    texture: Texture<'a>,
-    audio: AudioDevice<SoundBuf>,
-
-    cpu: Cpu<GbaMmu<'a>>,
-    mmu: GbaMmu<'a>,
-    io: IoReg<'a>,
-    ppu: Ppu<'a>,
-    spu: Spu<'a>,
+    filter: video::filter::Filter,
+    upscaler: video::scale::Upscaler,
+    osd: video::osd::Osd,
+    audio: Option<AudioDevice<SoundBuf>>,
+    input: input::Input,
+
+    debug_views: Vec<Box<dyn debug_view::DebugView>>,
+    console: Option<console::Console>,
+    control: Option<control::Control>,
+    movie: Option<movie::ActiveMovie>,
+    rewind: Option<rewind::Rewind>,
+    rewinding: bool,
+    speed: speed::Speed,
+    video_recording: Option<video::record::Recording>,
+
+    sys: Box<System>,
+
+    pub ctx: Sdl,
 }
 
-impl<'a> Gba<'a> {
+impl Gba {
     pub fn new(rom: GameRom, bios: GameRom, options: Options) -> Box<Self> {
-        unsafe {
-            let mut gba: Box<Gba> = Box::new(mem::uninitialized());
-            ptr::write(&mut gba.opts, options);
-
-            ptr::write(&mut gba.ctx, sdl2::init().unwrap());
-            let video = gba.ctx.video().unwrap();
-            let window = video
-                .window("GBA", 720, 480)
-                .position_centered()
-                .build()
-                .unwrap();
-
-            ptr::write(&mut gba.canvas, window.into_canvas().build().unwrap());
-            gba.canvas.set_logical_size(COLS, ROWS).unwrap();
-            ptr::write(&mut gba.texture_creator, gba.canvas.texture_creator());
-            info!(
-                "Default pixel format: {:?}",
-                gba.texture_creator.default_pixel_format()
-            );
-            ptr::write(
-                &mut gba.texture,
-                mem::transmute(
-                    gba.texture_creator
-                        .create_texture_streaming(PixelFormatEnum::RGB888, COLS, ROWS)
-                        .unwrap(),
-                ),
-            );
-
-            ptr::write(&mut gba.io, IoReg::new());
-            ptr::write(
-                &mut gba.mmu,
-                GbaMmu::new(rom, bios, Shared::new(&mut gba.io)),
-            );
-
-            ptr::write(&mut gba.cpu, Cpu::new(Shared::new(&mut gba.mmu), &[]));
-            if gba.opts.direct_boot {
-                gba.cpu.init_direct();
-            } else {
-                gba.cpu.init_arm();
+        let ctx = sdl2::init().unwrap();
+        let video = ctx.video().unwrap();
+        let window = video
+            .window("GBA", 720, 480)
+            .position_centered()
+            .resizable()
+            .build()
+            .unwrap();
+
+        let mut canvas = window.into_canvas().build().unwrap();
+        canvas.window_mut().set_minimum_size(COLS, ROWS).unwrap();
+
+        let mut sys = System::new(rom, bios, options.direct_boot);
+        sys.cpu.set_breaks(options.breaks.iter());
+
+        let audio = match options.audio {
+            Some(ref audio_opts) => {
+                let audio = ctx.audio().unwrap();
+                let spu = &mut sys.spu;
+                let res = audio.open_playback(None, &audio_opts.desired_spec(), |spec| {
+                    info!("Audio spec: {:?}", spec);
+                    spu.set_device(spec.freq, audio_opts);
+                    spu.get_callback()
+                });
+                match res {
+                    Ok(device) => {
+                        device.resume();
+                        Some(device)
+                    }
+                    Err(err) => {
+                        warn!("Failed to open audio, running without sound: {}", err);
+                        None
+                    }
+                }
             }
-            let opts = Shared::new(&mut gba.opts);
-            gba.cpu.set_breaks(opts.breaks.iter());
-
-            ptr::write(
-                &mut gba.ppu,
-                Ppu::new(
-                    Shared::new(&mut gba.texture),
-                    Shared::new(&mut gba.io),
-                    Shared::new(&mut gba.mmu),
-                ),
-            );
-
-            ptr::write(&mut gba.spu, Spu::new(Shared::new(&mut gba.io)));
-
-            let desired_spec = AudioSpecDesired {
-                freq: Some(FREQ),
-                channels: Some(2),
-                samples: Some((SAMPLES * 2) as u16),
-            };
-            let audio = gba.ctx.audio().unwrap();
-            let device = audio
-                .open_playback(None, &desired_spec, |spec| {
-                    warn!("Audio spec: {:?}", spec);
-                    gba.spu.get_callback()
-                })
-                .unwrap();
-            ptr::write(&mut gba.audio, device);
-            gba.audio.resume();
-
-            let cpu = Shared::new(&mut gba.cpu);
-            let ppu = Shared::new(&mut gba.ppu);
-            gba.mmu.init(cpu);
-            gba.io.init(cpu, Shared::new(&mut gba.mmu), ppu);
-
-            gba
-        }
+            None => None,
+        };
+
+        let input = input::Input::new(options.input.clone(), ctx.game_controller().unwrap());
+        let console = if options.console {
+            Some(console::Console::new())
+        } else {
+            None
+        };
+        let rewind = if options.rewind.depth > 0 {
+            Some(rewind::Rewind::new(&options.rewind))
+        } else {
+            None
+        };
+
+        Box::new(Gba {
+            filter: video::filter::Filter::new(&options.filter),
+            upscaler: video::scale::Upscaler::new(&options.display),
+            osd: video::osd::Osd::new(options.show_stats),
+            speed: speed::Speed::new(options.turbo),
+            opts: options,
+
+            canvas: canvas,
+            audio: audio,
+            input: input,
+
+            debug_views: Vec::new(),
+            console: console,
+            control: None,
+            movie: None,
+            rewind: rewind,
+            rewinding: false,
+            video_recording: None,
+
+            sys: sys,
+
+            ctx: ctx,
+        })
     }
 
     pub fn run(&mut self) -> Result<()> {
-        let mut frame = 0;
+        self.start_movie()?;
+        self.start_control()?;
+        self.start_link()?;
+        self.start_audio_recording();
+        self.start_video_recording();
+        self.update_speed();
         let mut event_pump = self.ctx.event_pump().unwrap();
+        // The texture borrows its creator, so both live only as long as the
+        // loop drawing to them
+        let texture_creator = self.canvas.texture_creator();
+        info!(
+            "Default pixel format: {:?}",
+            texture_creator.default_pixel_format()
+        );
+        let mut texture = texture_creator
+            .create_texture_streaming(PixelFormatEnum::RGB888, COLS, ROWS)
+            .unwrap();
 
         let frame_duration = Duration::new(
             0,
@@ -165,22 +256,25 @@ impl<'a> Gba<'a> {
         let mut prev_time = Instant::now();
         loop {
             let _guard = flame::start_guard("frame cycle");
-            let start = Instant::now();
-
-            flame::span_of("frame emu", || self.emulate_frame());
-            flame::span_of("frame copy", || {
-                self.canvas.copy(&self.texture, None, None).unwrap()
-            });
-            flame::span_of("frame present", || self.canvas.present());
+            let run_frame;
 
             {
                 event_pump.pump_events();
                 let keys = event_pump.keyboard_state();
-                self.io.set_keyreg(&KeyState::new_from_keystate(&keys));
-
                 if keys.is_scancode_pressed(Scancode::Escape) {
                     break;
                 }
+                self.set_fast_forward(keys.is_scancode_pressed(Scancode::Tab));
+                // Backspace rewinds while held, even while paused
+                let rewound = self.check_rewind(keys.is_scancode_pressed(Scancode::Backspace));
+                run_frame = rewound && (!self.speed.paused() || self.take_control_step());
+
+                // The keys are read before the frame so a movie can record
+                // exactly which keys each frame ran with
+                let live = self.control_keys(self.input.key_state(&keys));
+                let state = self.movie_input(live);
+                self.sys.io.set_keyreg(&state);
+                self.update_stats(&state);
                 if keys.is_scancode_pressed(Scancode::B) {
                     log::set_max_level(match log::max_level() {
                         log::LevelFilter::Debug => log::LevelFilter::Error,
@@ -188,6 +282,27 @@ impl<'a> Gba<'a> {
                     });
                 }
             }
+
+            if run_frame {
+                flame::span_of("frame emu", || self.sys.emulate_frame());
+                self.control_frame_run();
+                self.osd.frame_run(Instant::now());
+                flame::span_of("frame filter", || {
+                    self.filter.process(&self.sys.ppu.frame())
+                });
+                self.scheduled_screenshot();
+                self.record_video_frame();
+                if !self.rewinding {
+                    self.rewind_snapshot();
+                }
+            }
+            flame::span_of("frame copy", || {
+                self.draw_frame(&texture_creator, &mut texture)
+            });
+            self.draw_layer_indicator();
+            flame::span_of("frame present", || self.canvas.present());
+            self.update_debug_views();
+
             loop {
                 let ctrl = {
                     let keys = event_pump.keyboard_state();
@@ -195,17 +310,40 @@ impl<'a> Gba<'a> {
                         || keys.is_scancode_pressed(Scancode::RCtrl)
                 };
                 if let Some(event) = event_pump.poll_event() {
-                    if let sdl2::event::Event::KeyDown { scancode, .. } = event {
-                        if let Some(code) = scancode {
+                    if self.debug_event(&event) {
+                        continue;
+                    }
+                    match event {
+                        Event::KeyDown {
+                            scancode: Some(code),
+                            ..
+                        } => {
                             self.check_save(code, ctrl);
+                            self.check_debug_views(code);
+                            self.check_layer_keys(code);
+                            self.check_speed_keys(code);
+                            self.check_record_keys(code);
+                            self.check_screenshot_keys(code);
+                            self.check_video_keys(code);
+                            self.check_display_keys(code);
+                            self.check_osd_keys(code);
                         }
+                        Event::ControllerDeviceAdded { which, .. } => {
+                            self.input.add_controller(which)
+                        }
+                        Event::ControllerDeviceRemoved { which, .. } => {
+                            self.input.remove_controller(which)
+                        }
+                        _ => {}
                     }
                 } else {
                     break;
                 }
             }
+            self.check_console();
+            self.check_control();
             if self.opts.step_frames {
-                info!("Frame: {}", frame);
+                info!("Frame: {}", self.sys.frame);
                 loop {
                     let event = event_pump.wait_event();
                     if let sdl2::event::Event::KeyDown { scancode, .. } = event {
@@ -216,32 +354,25 @@ impl<'a> Gba<'a> {
                 }
             }
 
-            let end = Instant::now();
-            if self.opts.fps_limit {
-                if end < prev_time + frame_duration {
-                    let sleep_time = (prev_time + frame_duration) - end;
-                    thread::sleep(sleep_time);
-                }
-            }
-            prev_time = prev_time + frame_duration;
-
-            let now = Instant::now();
-            info!("{} fps", 1_000_000_000u32 / ((now - start).subsec_nanos()));
-            frame += 1;
+            self.wait_for_frame(&mut prev_time, frame_duration);
         }
+        self.finish_movie();
+        self.finish_video_recording();
+        self.finish_audio_recording();
         Ok(())
     }
 
-    fn emulate_frame(&mut self) {
-        for _ in 0..CYCLES_PER_FRAME {
-            self.cycle();
+    // Blocks until everyone on the link cable has connected
+    fn start_link(&mut self) -> Result<()> {
+        if let Some(setup) = self.opts.link.clone() {
+            info!("Waiting for the link cable to connect: {:?}", setup);
+            let link = Link::open(&setup).map_err(GBAError::LinkError)?;
+            let (player, players) = link.player();
+            info!("Linked as player {} of {}", player + 1, players);
+            self.osd
+                .message(format!("Linked as player {} of {}", player + 1, players));
+            self.sys.set_link(Some(link));
         }
-    }
-
-    fn cycle(&mut self) {
-        self.cpu.cycle();
-        self.ppu.cycle();
-        self.spu.cycle();
-        self.io.cycle();
+        Ok(())
     }
 }
@@ -0,0 +1,328 @@
+//! Recording the keys held on each frame so a run can be played back exactly
+
+use std::io::{Error, ErrorKind, Read, Write};
+use std::path::PathBuf;
+
+use bincode;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use zstd;
+
+use checksum::crc32;
+use io::key::KeyState;
+
+use super::*;
+
+use GBAError;
+
+const MAGIC: &[u8; 8] = b"GBAMOVIE";
+const VERSION: u32 = 2;
+
+/// What a movie was recorded with, which has to match for it to play back
+/// the same way
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct MovieHeader {
+    pub game_title: String,
+    pub game_code: String,
+    pub rom_crc32: u32,
+    pub bios_crc32: u32,
+    pub direct_boot: bool,
+    /// CRC32 of the battery-backed save memory the movie starts with
+    pub save_crc32: u32,
+    /// Number of times a save state was loaded while recording
+    pub rerecords: u32,
+}
+
+/// The movie file: the magic bytes and version, followed by the rest
+/// compressed with zstd
+#[derive(Serialize, Deserialize)]
+pub struct Movie {
+    pub header: MovieHeader,
+    /// The frame the movie starts on, counted from power on
+    pub start_frame: u64,
+    /// The save state the movie starts from, or None if it starts from power
+    /// on
+    pub state: Option<Vec<u8>>,
+    /// KEYINPUT for each frame
+    pub inputs: Vec<u16>,
+}
+
+impl Movie {
+    pub fn read<R: Read>(mut reader: R) -> ::Result<Movie> {
+        use GBAError::MovieError;
+
+        let mut magic = [0u8; 8];
+        reader
+            .read_exact(&mut magic)
+            .map_err(|err| MovieError(err.to_string()))?;
+        if &magic != MAGIC {
+            return Err(MovieError("not a movie file".to_string()));
+        }
+        let version = reader
+            .read_u32::<LittleEndian>()
+            .map_err(|err| MovieError(err.to_string()))?;
+        if version != VERSION {
+            return Err(MovieError(format!("unsupported movie version {}", version)));
+        }
+
+        let decoder = zstd::Decoder::new(reader).map_err(|err| MovieError(err.to_string()))?;
+        bincode::deserialize_from(decoder).map_err(|err| MovieError(err.to_string()))
+    }
+
+    pub fn write<W: Write>(&self, mut writer: W) -> ::std::io::Result<()> {
+        writer.write_all(MAGIC)?;
+        writer.write_u32::<LittleEndian>(VERSION)?;
+        let mut encoder = zstd::Encoder::new(writer, 3)?;
+        bincode::serialize_into(&mut encoder, self)
+            .map_err(|err| Error::new(ErrorKind::Other, err))?;
+        encoder.finish()?;
+        Ok(())
+    }
+
+    /// The keys for a frame, if the movie covers it
+    fn input(&self, frame: u64) -> Option<u16> {
+        frame
+            .checked_sub(self.start_frame)
+            .and_then(|i| self.inputs.get(i as usize))
+            .cloned()
+    }
+
+    /// Sets the keys for a frame, dropping any recorded after it
+    fn record(&mut self, frame: u64, keyinput: u16) {
+        if let Some(i) = frame.checked_sub(self.start_frame) {
+            self.inputs.truncate(i as usize);
+            // A state from after the end leaves a gap, which is filled with
+            // no keys held
+            self.inputs.resize(i as usize, 0x3ff);
+            self.inputs.push(keyinput);
+        }
+    }
+}
+
+#[derive(PartialEq)]
+enum Mode {
+    Recording,
+    Playing,
+}
+
+pub(super) struct ActiveMovie {
+    movie: Movie,
+    path: PathBuf,
+    mode: Mode,
+}
+
+impl Gba {
+    fn movie_header(&self) -> MovieHeader {
+        MovieHeader {
+            game_title: self.sys.mmu.rom.title(),
+            game_code: self.sys.mmu.rom.game_code(),
+            rom_crc32: crc32(&self.sys.mmu.rom),
+            bios_crc32: crc32(self.sys.mmu.bios.rom()),
+            direct_boot: self.opts.direct_boot,
+            save_crc32: crc32(&self.sys.mmu.battery()),
+            rerecords: 0,
+        }
+    }
+
+    /// Starts playing or recording a movie if the options ask for one, before
+    /// the first frame is run
+    pub(super) fn start_movie(&mut self) -> Result<()> {
+        use GBAError::MovieError;
+
+        if let Some(path) = self.opts.play_movie.clone() {
+            let file = File::open(&path).map_err(|err| MovieError(err.to_string()))?;
+            let movie = Movie::read(file)?;
+
+            let header = self.movie_header();
+            if movie.header.rom_crc32 != header.rom_crc32 {
+                return Err(MovieError(format!(
+                    "movie was recorded with a different ROM ({} {})",
+                    movie.header.game_code, movie.header.game_title
+                )));
+            }
+            if movie.header.bios_crc32 != header.bios_crc32 {
+                warn!("Movie was recorded with a different BIOS, it may not play back the same");
+            }
+            if movie.header.direct_boot != self.opts.direct_boot {
+                self.opts.direct_boot = movie.header.direct_boot;
+                if movie.header.direct_boot {
+                    self.sys.cpu.init_direct();
+                } else {
+                    self.sys.cpu.init_arm();
+                }
+                self.sys.cpu.set_breaks(self.opts.breaks.iter());
+            }
+            if let Some(ref state) = movie.state {
+                self.load_state(&state[..])?;
+            }
+            // Checked once the movie's state is loaded, since that brings its
+            // own save memory
+            if movie.header.save_crc32 != crc32(&self.sys.mmu.battery()) {
+                return Err(MovieError(
+                    "movie was recorded with different save data".to_string(),
+                ));
+            }
+            info!(
+                "Playing {:?}: {} frames, {} rerecords",
+                path,
+                movie.inputs.len(),
+                movie.header.rerecords
+            );
+            self.movie = Some(ActiveMovie {
+                movie: movie,
+                path: PathBuf::from(path),
+                mode: Mode::Playing,
+            });
+        } else if let Some(path) = self.opts.record_movie.clone() {
+            let state = match self.opts.movie_state.clone() {
+                Some(state_path) => {
+                    let mut state = vec![];
+                    File::open(&state_path)
+                        .and_then(zstd::Decoder::new)
+                        .and_then(|mut reader| reader.read_to_end(&mut state))
+                        .map_err(|err| GBAError::StateLoadError(err.into()))?;
+                    self.load_state(&state[..])?;
+                    Some(state)
+                }
+                None => None,
+            };
+            info!("Recording {:?}", path);
+            self.movie = Some(ActiveMovie {
+                movie: Movie {
+                    header: self.movie_header(),
+                    start_frame: self.sys.frame,
+                    state: state,
+                    inputs: vec![],
+                },
+                path: PathBuf::from(path),
+                mode: Mode::Recording,
+            });
+        }
+        Ok(())
+    }
+
+    /// The keys to use for the next frame.  When playing a movie these are
+    /// the recorded ones and live input is ignored, when recording the live
+    /// ones are added to the movie.
+    pub(super) fn movie_input(&mut self, live: KeyState) -> KeyState {
+        let frame = self.sys.frame;
+        match self.movie {
+            Some(ActiveMovie {
+                ref mut movie,
+                mode: Mode::Recording,
+                ..
+            }) => {
+                movie.record(frame, live.keyinput());
+                live
+            }
+            Some(ActiveMovie {
+                ref movie,
+                mode: Mode::Playing,
+                ..
+            }) => match movie.input(frame) {
+                Some(keyinput) => KeyState::from_keyinput(keyinput),
+                None => {
+                    info!("Movie finished on frame {}", frame);
+                    self.movie = None;
+                    live
+                }
+            },
+            None => live,
+        }
+    }
+
+    /// Whether a movie is being played back, which loading a state would
+    /// throw out of sync
+    pub(super) fn movie_playing(&self) -> bool {
+        match self.movie {
+            Some(ref active) => active.mode == Mode::Playing,
+            None => false,
+        }
+    }
+
+    /// Counts a rerecord when a save state is loaded while recording, the
+    /// frames after the state are recorded over
+    pub(super) fn movie_state_loaded(&mut self) {
+        let frame = self.sys.frame;
+        if let Some(ref mut active) = self.movie {
+            if active.mode == Mode::Recording {
+                if frame < active.movie.start_frame {
+                    warn!("Save state is from before the movie started");
+                }
+                active.movie.header.rerecords += 1;
+            }
+        }
+    }
+
+    /// Writes out the movie being recorded
+    pub(super) fn finish_movie(&mut self) {
+        if let Some(active) = self.movie.take() {
+            if active.mode == Mode::Recording {
+                let res = File::create(&active.path).and_then(|f| active.movie.write(f));
+                match res {
+                    Ok(_) => info!(
+                        "Saved {:?}: {} frames, {} rerecords",
+                        active.path,
+                        active.movie.inputs.len(),
+                        active.movie.header.rerecords
+                    ),
+                    Err(err) => error!("Failed to save movie: {}", err),
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn movie() -> Movie {
+        Movie {
+            header: MovieHeader {
+                game_title: "TEST".to_string(),
+                game_code: "ATST".to_string(),
+                rom_crc32: 0x12345678,
+                bios_crc32: 0x9abcdef0,
+                direct_boot: true,
+                save_crc32: 0x0badf00d,
+                rerecords: 2,
+            },
+            start_frame: 10,
+            state: Some(vec![1, 2, 3]),
+            inputs: vec![0x3ff, 0x3fe, 0x3bf],
+        }
+    }
+
+    #[test]
+    fn test_movie_roundtrip() {
+        let m = movie();
+        let mut file = vec![];
+        m.write(&mut file).unwrap();
+        assert_eq!(MAGIC, &file[..8]);
+
+        let read = Movie::read(&file[..]).unwrap();
+        assert_eq!(m.header, read.header);
+        assert_eq!(m.start_frame, read.start_frame);
+        assert_eq!(m.state, read.state);
+        assert_eq!(m.inputs, read.inputs);
+
+        file[0] = b'X';
+        assert!(Movie::read(&file[..]).is_err());
+    }
+
+    #[test]
+    fn test_movie_frames() {
+        let mut m = movie();
+        assert_eq!(None, m.input(9));
+        assert_eq!(Some(0x3fe), m.input(11));
+        assert_eq!(None, m.input(13));
+
+        // Recording over an earlier frame drops the ones after it
+        m.record(11, 0x3f0);
+        assert_eq!(vec![0x3ff, 0x3f0], m.inputs);
+        m.record(14, 0x3f1);
+        assert_eq!(vec![0x3ff, 0x3f0, 0x3ff, 0x3ff, 0x3f1], m.inputs);
+        m.record(5, 0);
+        assert_eq!(5, m.inputs.len());
+    }
+}
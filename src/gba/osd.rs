@@ -0,0 +1,39 @@
+//! Status shown over the frame: messages about what's just happened, and
+//! stats on how the emulation's running
+
+use io::key::{Key, KeyState};
+
+use super::*;
+
+impl Gba {
+    /// H shows and hides the stats
+    pub(super) fn check_osd_keys(&mut self, key: Scancode) {
+        if key == Scancode::H {
+            self.osd.toggle_stats();
+        }
+    }
+
+    /// Updates the stats for the frame about to be run with `keys` held
+    pub(super) fn update_stats(&mut self, keys: &KeyState) {
+        if !self.osd.stats_shown() {
+            return;
+        }
+        let fps = self.osd.fps(Instant::now());
+        let full_speed = CYCLES_PER_SEC as f32 / CYCLES_PER_FRAME as f32;
+        let audio = if self.audio.is_some() {
+            format!("AUDIO {:.0}%", self.sys.spu.buffer_fill() * 100.0)
+        } else {
+            "AUDIO OFF".to_string()
+        };
+        let held: Vec<String> = Key::ALL
+            .iter()
+            .filter(|&&key| keys.is_pressed(key))
+            .map(|key| format!("{:?}", key))
+            .collect();
+        self.osd.set_stats(vec![
+            format!("{} FPS {:.0}%", fps, fps as f32 / full_speed * 100.0),
+            audio,
+            format!("KEYS {}", held.join(" ")),
+        ]);
+    }
+}
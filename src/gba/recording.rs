@@ -0,0 +1,224 @@
+//! Recording the sound, video and screenshots to files while running
+
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use debug::Image;
+use video::record::Recording;
+
+use super::debug_view::save_path;
+use super::*;
+
+/// A name for a new recording next to the save file, made unique by the time
+/// it was started in milliseconds
+pub(super) fn timestamped_path(prefix: &OsStr, ext: &str) -> OsString {
+    let millis = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_secs() * 1000 + d.subsec_millis() as u64)
+        .unwrap_or(0);
+    save_path(prefix, &format!(".{}.{}", millis, ext))
+}
+
+// The upscaled copy of a screenshot goes next to it, `shot.png` getting
+// `shot.3x.png`
+fn scaled_path(path: &Path, scale: u32) -> PathBuf {
+    let ext = match path.extension() {
+        Some(ext) => format!("{}x.{}", scale, ext.to_string_lossy()),
+        None => format!("{}x", scale),
+    };
+    path.with_extension(ext)
+}
+
+impl Gba {
+    /// Starts recording the sound if the options ask for it, before the first
+    /// frame is run
+    pub(super) fn start_audio_recording(&mut self) {
+        if let Some(path) = self.opts.record_audio.clone() {
+            self.record_audio(&path);
+        }
+    }
+
+    fn record_audio(&mut self, path: &OsStr) {
+        match self
+            .sys
+            .spu
+            .start_recording(Path::new(path), self.opts.record_stems)
+        {
+            Ok(_) => {
+                info!("Recording audio to {:?}", path);
+                self.osd.message("Recording audio".to_string());
+            }
+            Err(err) => {
+                error!("Failed to record audio to {:?}: {}", path, err);
+                self.osd.message("Failed to record audio".to_string());
+            }
+        }
+    }
+
+    /// R starts and stops recording the sound.  Recordings started this way
+    /// are named by the time they were started.
+    pub(super) fn check_record_keys(&mut self, key: Scancode) {
+        if key != Scancode::R {
+            return;
+        }
+        if self.sys.spu.recording() {
+            self.finish_audio_recording();
+        } else {
+            let path = timestamped_path(&self.opts.save_file, "wav");
+            self.record_audio(&path);
+        }
+    }
+
+    /// Starts recording video if the options ask for it, before the first
+    /// frame is run
+    pub(super) fn start_video_recording(&mut self) {
+        if let Some(path) = self.opts.record_video.clone() {
+            self.record_video(&path);
+        }
+    }
+
+    fn record_video(&mut self, path: &OsStr) {
+        let path = Path::new(path);
+        let res = Recording::create(path, COLS, ROWS, CYCLES_PER_SEC, CYCLES_PER_FRAME);
+        match res {
+            Ok(recording) => {
+                info!("Recording video to {:?}", path);
+                self.osd.message("Recording video".to_string());
+                self.video_recording = Some(recording);
+            }
+            Err(err) => {
+                error!("Failed to record video to {:?}: {}", path, err);
+                self.osd.message("Failed to record video".to_string());
+                return;
+            }
+        }
+
+        // The sound's recorded in emulated time too, so starting both on the
+        // same frame keeps them in sync
+        if self.opts.record_video_audio {
+            let audio_path = if path == Path::new("-") {
+                timestamped_path(&self.opts.save_file, "wav")
+            } else {
+                path.with_extension("wav").into_os_string()
+            };
+            if self.sys.spu.recording() {
+                warn!("Already recording audio, stopping it to record the video's sound");
+            }
+            self.record_audio(&audio_path);
+        }
+    }
+
+    /// Adds the frame just run to the video being recorded
+    pub(super) fn record_video_frame(&mut self) {
+        let res = match self.video_recording {
+            Some(ref mut recording) => recording.write(&self.sys.ppu.frame()),
+            None => return,
+        };
+        if let Err(err) = res {
+            error!("Failed to record video: {}", err);
+            self.osd.message("Failed to record video".to_string());
+            self.finish_video_recording();
+        }
+    }
+
+    /// V starts and stops recording video, named by the time it was started
+    pub(super) fn check_video_keys(&mut self, key: Scancode) {
+        if key != Scancode::V {
+            return;
+        }
+        if self.video_recording.is_some() {
+            self.finish_video_recording();
+        } else {
+            let path = timestamped_path(&self.opts.save_file, "y4m");
+            self.record_video(&path);
+        }
+    }
+
+    pub(super) fn finish_video_recording(&mut self) {
+        if let Some(recording) = self.video_recording.take() {
+            match recording.finish() {
+                Ok(path) => {
+                    info!("Saved video recording {:?}", path);
+                    self.osd.message("Saved video recording".to_string());
+                }
+                Err(err) => error!("Failed to finish video recording: {}", err),
+            }
+            if self.opts.record_video_audio {
+                self.finish_audio_recording();
+            }
+        }
+    }
+
+    /// C saves a screenshot
+    pub(super) fn check_screenshot_keys(&mut self, key: Scancode) {
+        if key == Scancode::C {
+            let path = timestamped_path(&self.opts.save_file, "png");
+            self.screenshot(Path::new(&path));
+        }
+    }
+
+    /// Takes the screenshots the options ask for after the frame just run
+    pub(super) fn scheduled_screenshot(&mut self) {
+        if self.opts.screenshot_frames.contains(&self.sys.frame) {
+            let path = save_path(
+                &self.opts.save_file,
+                &format!(".frame{}.png", self.sys.frame),
+            );
+            self.screenshot(Path::new(&path));
+        }
+    }
+
+    /// Saves the frame on screen exactly as drawn, 240x160, and an upscaled
+    /// copy too if the options ask for one.  False if any failed to save.
+    pub(super) fn screenshot(&mut self, path: &Path) -> bool {
+        let frame = Image {
+            width: COLS,
+            height: ROWS,
+            pixels: self.sys.ppu.frame(),
+        };
+        let mut saved = vec![(path.to_path_buf(), frame.save_png(path))];
+        if self.opts.screenshot_scale > 1 {
+            let scaled_path = scaled_path(path, self.opts.screenshot_scale);
+            let res = frame
+                .scaled(self.opts.screenshot_scale)
+                .save_png(&scaled_path);
+            saved.push((scaled_path, res));
+        }
+        let mut failed = false;
+        for (path, res) in saved {
+            match res {
+                Ok(_) => info!("Saved screenshot {:?}", path),
+                Err(err) => {
+                    error!("Failed to save screenshot {:?}: {}", path, err);
+                    failed = true;
+                }
+            }
+        }
+        self.osd.message(if failed {
+            "Failed to save screenshot".to_string()
+        } else {
+            "Saved screenshot".to_string()
+        });
+        !failed
+    }
+
+    pub(super) fn finish_audio_recording(&mut self) {
+        if let Some(path) = self.sys.spu.stop_recording() {
+            info!("Saved audio recording {:?}", path);
+            self.osd.message("Saved audio recording".to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_scaled_path() {
+        assert_eq!(
+            Path::new("saves/gba.123.3x.png"),
+            scaled_path(Path::new("saves/gba.123.png"), 3)
+        );
+        assert_eq!(Path::new("shot.2x"), scaled_path(Path::new("shot"), 2));
+    }
+}
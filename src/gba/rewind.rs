@@ -0,0 +1,170 @@
+//! Stepping back through recent gameplay.
+//!
+//! Snapshots of the whole system are taken every few frames.  Only the newest
+//! is kept whole, the older ones are kept as the XOR of each snapshot with the
+//! one after it, compressed with zstd.  Most of memory doesn't change between
+//! snapshots so the differences are mostly zeros and compress down to very
+//! little.
+
+use std::collections::VecDeque;
+
+use bincode;
+use zstd;
+
+use super::*;
+
+/// How rewinding is set up
+#[derive(Clone, Debug)]
+pub struct RewindOptions {
+    /// Frames between snapshots
+    pub interval: u32,
+    /// Snapshots kept, 0 turns rewinding off
+    pub depth: usize,
+}
+
+impl Default for RewindOptions {
+    fn default() -> Self {
+        // 20 seconds at 60fps
+        RewindOptions {
+            interval: 4,
+            depth: 300,
+        }
+    }
+}
+
+// Turns a snapshot back into the one before it
+struct Delta {
+    len: usize,
+    xor: Vec<u8>,
+}
+
+pub(super) struct Rewind {
+    interval: u32,
+    depth: usize,
+    latest: Option<Vec<u8>>,
+    // Oldest first
+    deltas: VecDeque<Delta>,
+}
+
+impl Rewind {
+    pub fn new(options: &RewindOptions) -> Self {
+        Rewind {
+            interval: options.interval.max(1),
+            depth: options.depth,
+            latest: None,
+            deltas: VecDeque::new(),
+        }
+    }
+
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if let Some(latest) = self.latest.take() {
+            let xor = zstd::encode_all(&xor(&latest, &snapshot)[..], 1).unwrap();
+            self.deltas.push_back(Delta {
+                len: latest.len(),
+                xor: xor,
+            });
+            while self.deltas.len() >= self.depth {
+                self.deltas.pop_front();
+            }
+        }
+        self.latest = Some(snapshot);
+    }
+
+    /// Drops the newest snapshot and returns the one before it, which stays
+    /// as the newest since that's where the system goes back to.  The newest
+    /// is never much older than the current frame, so skipping it makes each
+    /// step go back a whole interval.
+    fn step_back(&mut self) -> Option<Vec<u8>> {
+        let delta = self.deltas.pop_back()?;
+        let latest = self.latest.take().unwrap();
+        let diff = zstd::decode_all(&delta.xor[..]).unwrap();
+        let mut prev = xor(&latest, &diff);
+        prev.truncate(delta.len);
+        self.latest = Some(prev.clone());
+        Some(prev)
+    }
+}
+
+/// XORs two buffers together, as though the shorter one was padded with
+/// zeros
+fn xor(a: &[u8], b: &[u8]) -> Vec<u8> {
+    let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+    let mut out = long.to_vec();
+    for (o, &s) in out.iter_mut().zip(short.iter()) {
+        *o ^= s;
+    }
+    out
+}
+
+impl Gba {
+    /// Steps back while the rewind key is held, with the sound muted.
+    /// Returns whether a frame should be run, which it shouldn't once there's
+    /// nothing left to go back to.
+    pub(super) fn check_rewind(&mut self, held: bool) -> bool {
+        let held = held && self.rewind.is_some();
+        if held != self.rewinding {
+            self.rewinding = held;
+            self.sys.spu.set_muted(held);
+            if held {
+                self.movie_state_loaded();
+            }
+        }
+        !held || self.rewind_step()
+    }
+
+    /// Takes a snapshot if one is due on this frame
+    pub(super) fn rewind_snapshot(&mut self) {
+        let due = match self.rewind {
+            Some(ref rewind) => self.sys.frame % rewind.interval as u64 == 0,
+            None => false,
+        };
+        if due {
+            let snapshot = bincode::serialize(&*self).unwrap();
+            self.rewind.as_mut().unwrap().push(snapshot);
+        }
+    }
+
+    /// Goes back a snapshot, returns false when there are none left
+    fn rewind_step(&mut self) -> bool {
+        let snapshot = match self.rewind {
+            Some(ref mut rewind) => rewind.step_back(),
+            None => None,
+        };
+        match snapshot {
+            Some(snapshot) => match self.load_state(&snapshot[..]) {
+                Ok(_) => true,
+                Err(err) => {
+                    error!("Failed to rewind: {:?}", err);
+                    false
+                }
+            },
+            None => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rewind_order() {
+        let mut rewind = Rewind::new(&RewindOptions {
+            interval: 1,
+            depth: 3,
+        });
+        rewind.push(vec![1, 2, 3]);
+        rewind.push(vec![1, 2, 4, 5]);
+        rewind.push(vec![9, 2]);
+        rewind.push(vec![9, 2, 0]);
+
+        // Only the newest 3 are kept, and the newest is skipped
+        assert_eq!(Some(vec![9, 2]), rewind.step_back());
+        assert_eq!(Some(vec![1, 2, 4, 5]), rewind.step_back());
+        assert_eq!(None, rewind.step_back());
+
+        // Where it went back to is still there to go back to again
+        rewind.push(vec![7]);
+        assert_eq!(Some(vec![1, 2, 4, 5]), rewind.step_back());
+    }
+}
@@ -1,18 +1,23 @@
-use std::fmt;
+use std::io::{self, Read};
 use std::result::Result;
 
 use bincode;
 use zstd;
 
-use serde::de;
-use serde::de::{SeqAccess, Visitor};
-use serde::ser::SerializeStruct;
-use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use serde::{Serialize, Serializer};
 
 use super::*;
 
-impl<'a> Gba<'a> {
-    pub(super) fn check_save(&mut self, key: Scancode, _ctrl: bool) {
+use cpu::Cpu;
+use io::IoReg;
+use mmu::gba::Gba as GbaMmu;
+
+use GBAError;
+
+impl Gba {
+    /// Number keys save to the numbered save state, or load from it when
+    /// ctrl is held
+    pub(super) fn check_save(&mut self, key: Scancode, ctrl: bool) {
         use self::Scancode::*;
         let index = match key {
             Num0 => 0,
@@ -32,64 +37,103 @@ impl<'a> Gba<'a> {
         }
         let mut path = self.opts.save_file.to_os_string();
         path.push(format!("{}.sav", index));
-        match File::create(Path::new(&path)) {
-            Ok(file) => {
-                let mut writer = zstd::Encoder::new(&file, 1).unwrap();
-                bincode::serialize_into(&mut writer, self).unwrap();
+        if ctrl {
+            if self.movie_playing() {
+                self.osd
+                    .message("Can't load states while playing a movie".to_string());
+                return;
+            }
+            match self.load_state_file(Path::new(&path)) {
+                Ok(_) => {
+                    info!("Loaded file {:?}", path);
+                    self.osd.message(format!("Loaded state {}", index));
+                    self.movie_state_loaded();
+                }
+                Err(err) => {
+                    error!("Failed to load save state: {:?}", err);
+                    self.osd.message(format!("Failed to load state {}", index));
+                }
+            }
+            return;
+        }
+        match self.save_state_file(Path::new(&path)) {
+            Ok(_) => {
                 info!("Saved file {:?}", path);
+                self.osd.message(format!("Saved state {}", index));
+            }
+            Err(err) => {
+                error!("Failed to write save state: {}", err);
+                self.osd.message(format!("Failed to save state {}", index));
             }
-            Err(err) => error!("Failed to create save state: {}", err),
         }
     }
-}
 
-impl<'a> Serialize for Gba<'a> {
-    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
-        let mut s = serializer.serialize_struct("gba_rs::Gba", 4)?;
-        s.serialize_field("cpu", &self.cpu)?;
-        s.serialize_field("mmu", &self.mmu)?;
-        s.serialize_field("io", &self.io)?;
-        s.serialize_field("ppu", &self.ppu)?;
-        s.end()
+    pub(super) fn save_state_file(&self, path: &Path) -> io::Result<()> {
+        let file = File::create(path)?;
+        let mut writer = zstd::Encoder::new(&file, 1)?;
+        bincode::serialize_into(&mut writer, self)
+            .map_err(|err| io::Error::new(io::ErrorKind::Other, err))?;
+        // The stream has to be finished for the state to be loadable
+        writer.finish()?;
+        Ok(())
+    }
+
+    pub(super) fn load_state_file(&mut self, path: &Path) -> ::Result<()> {
+        let file = File::open(path).map_err(|err| GBAError::StateLoadError(err.into()))?;
+        let reader =
+            zstd::Decoder::new(file).map_err(|err| GBAError::StateLoadError(err.into()))?;
+        self.load_state(reader)
+    }
+
+    /// Replaces the state of the running system with a saved one, keeping
+    /// the ROM, BIOS and everything outside the emulated hardware.  The
+    /// loaded frame is shown straight away, without blending in frames from
+    /// before the load.
+    pub(super) fn load_state<R: Read>(&mut self, reader: R) -> ::Result<()> {
+        self.sys.load_state(reader)?;
+        self.filter.reset();
+        self.filter.process(&self.sys.ppu.frame());
+        Ok(())
     }
 }
 
-impl<'de> Deserialize<'de> for Gba<'static> {
-    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
-        struct GbaVisitor;
-        impl<'de> Visitor<'de> for GbaVisitor {
-            type Value = Gba<'static>;
+/// The memory and IO registers out of a save state file, for inspecting a
+/// state without a running system
+pub struct StateMemory {
+    pub mmu: GbaMmu,
+    pub io: IoReg,
+}
 
-            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
-                formatter.write_str("struct Gba")
-            }
+// Save states are written field by field, so the leading fields can be read
+// without the rest
+#[derive(Deserialize)]
+struct StateMemoryFields {
+    #[allow(dead_code)]
+    cpu: Cpu<GbaMmu>,
+    mmu: GbaMmu,
+    io: IoReg,
+}
 
-            fn visit_seq<V: SeqAccess<'de>>(self, mut seq: V) -> Result<Gba<'static>, V::Error> {
-                let cpu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(0, &self))?;
-                let mmu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(1, &self))?;
-                let io = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(2, &self))?;
-                let ppu = seq
-                    .next_element()?
-                    .ok_or_else(|| de::Error::invalid_length(3, &self))?;
+impl StateMemory {
+    pub fn load(path: &Path) -> ::Result<Box<StateMemory>> {
+        let file = File::open(path).map_err(|err| GBAError::StateLoadError(err.into()))?;
+        let reader =
+            zstd::Decoder::new(file).map_err(|err| GBAError::StateLoadError(err.into()))?;
+        let fields: StateMemoryFields =
+            bincode::deserialize_from(reader).map_err(GBAError::StateLoadError)?;
 
-                unsafe {
-                    let mut gba: Gba<'static> = mem::uninitialized();
-                    ptr::write(&mut gba.cpu, cpu);
-                    ptr::write(&mut gba.mmu, mmu);
-                    ptr::write(&mut gba.io, io);
-                    ptr::write(&mut gba.ppu, ppu);
-                    Ok(gba)
-                }
-            }
-        }
+        let mut state = Box::new(StateMemory {
+            mmu: fields.mmu,
+            io: fields.io,
+        });
+        // Boxed, so the registers stay put
+        state.mmu.io = unsafe { Shared::new(&mut state.io) };
+        Ok(state)
+    }
+}
 
-        const FIELDS: &'static [&'static str] = &["cpu", "mmu", "io", "ppu"];
-        deserializer.deserialize_struct("gba_rs::Gba", FIELDS, GbaVisitor)
+impl Serialize for Gba {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.sys.serialize(serializer)
     }
 }
@@ -0,0 +1,195 @@
+use std::time::{Duration, Instant};
+
+use super::*;
+
+/// What the emulation is paced by
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum SyncMode {
+    /// Keeps the sound buffer at the latency asked for, so the audio device's clock sets
+    /// the pace.  Falls back to `Video` when there's no sound being played.
+    Audio,
+    /// Sleeps until each frame is due by the wall clock
+    Video,
+}
+
+/// How fast the emulation runs relative to a real GBA, changed with hotkeys
+/// while running
+pub(super) struct Speed {
+    /// Multiplier for fast-forward and turbo, None runs as fast as possible
+    turbo_rate: Option<f32>,
+    /// Held down to fast-forward
+    fast_forward: bool,
+    /// Toggled on to fast-forward
+    turbo: bool,
+    /// Halvings of the speed for slow motion
+    slow: u32,
+    paused: bool,
+}
+
+// Slow motion goes down to 1/4 speed
+const SLOW_STEPS: u32 = 3;
+
+impl Speed {
+    pub fn new(turbo_rate: Option<f32>) -> Self {
+        Speed {
+            turbo_rate: turbo_rate,
+            fast_forward: false,
+            turbo: false,
+            slow: 0,
+            paused: false,
+        }
+    }
+
+    /// The speed multiplier, None when running as fast as possible
+    pub fn rate(&self) -> Option<f32> {
+        if self.fast_forward || self.turbo {
+            self.turbo_rate
+        } else {
+            Some(1.0 / (1 << self.slow) as f32)
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused
+    }
+
+    pub fn set_paused(&mut self, paused: bool) {
+        self.paused = paused;
+    }
+
+    /// How long a frame should take at the current speed, None if frames
+    /// shouldn't be waited on.  Paused frames take the normal time so the
+    /// window stays responsive without spinning.
+    pub fn frame_duration(&self, base: Duration) -> Option<Duration> {
+        if self.paused {
+            return Some(base);
+        }
+        self.rate().map(|rate| {
+            let nanos = base.as_secs() as f64 * 1e9 + base.subsec_nanos() as f64;
+            let scaled = (nanos / rate as f64) as u64;
+            Duration::new(scaled / 1_000_000_000, (scaled % 1_000_000_000) as u32)
+        })
+    }
+
+    fn describe(&self) -> String {
+        if self.paused {
+            return "paused".to_string();
+        }
+        match self.rate() {
+            Some(rate) => format!("{}x speed", rate),
+            None => "unlimited speed".to_string(),
+        }
+    }
+}
+
+/// Parses the fast-forward speed: a multiplier above 1, or `unlimited`
+pub fn parse_turbo(s: &str) -> ::std::result::Result<Option<f32>, String> {
+    if s == "unlimited" {
+        return Ok(None);
+    }
+    match s.parse::<f32>() {
+        Ok(rate) if rate > 1.0 && rate.is_finite() => Ok(Some(rate)),
+        _ => Err("turbo must be a multiplier above 1 or unlimited".to_string()),
+    }
+}
+
+impl Gba {
+    /// Backquote toggles turbo, minus steps through slow motion speeds and
+    /// space pauses
+    pub(super) fn check_speed_keys(&mut self, key: Scancode) {
+        match key {
+            Scancode::Grave => self.speed.turbo = !self.speed.turbo,
+            Scancode::Minus => self.speed.slow = (self.speed.slow + 1) % SLOW_STEPS,
+            Scancode::Space => self.speed.paused = !self.speed.paused,
+            _ => return,
+        }
+        let msg = format!("Running at {}", self.speed.describe());
+        info!("{}", msg);
+        self.osd.message(msg);
+        self.update_speed();
+    }
+
+    /// Tab fast-forwards while held
+    pub(super) fn set_fast_forward(&mut self, held: bool) {
+        if held != self.speed.fast_forward {
+            self.speed.fast_forward = held;
+            self.update_speed();
+        }
+    }
+
+    /// Keeps the sound in step with the emulation speed
+    pub(super) fn update_speed(&mut self) {
+        let rate = if self.speed.paused || self.audio.is_none() {
+            Some(0.0)
+        } else if self.opts.fps_limit {
+            self.speed.rate()
+        } else {
+            None
+        };
+        self.sys.spu.set_speed(rate.unwrap_or(0.0));
+    }
+
+    /// Waits until it's time for the next frame
+    pub(super) fn wait_for_frame(&mut self, prev_time: &mut Instant, base: Duration) {
+        let duration = self.speed.frame_duration(base);
+        if self.opts.sync == SyncMode::Audio && self.sys.spu.playing() {
+            // The audio device takes samples at its own pace, so waiting for
+            // it to use up what was queued keeps the emulation in step with
+            // it.  Give up if it stops taking them.
+            let deadline = Instant::now() + duration.unwrap_or(base) * 2;
+            while self.sys.spu.backed_up() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(1));
+            }
+            *prev_time = Instant::now();
+            return;
+        }
+
+        let end = Instant::now();
+        match duration {
+            Some(duration) if self.opts.fps_limit || self.speed.paused => {
+                let next = *prev_time + duration;
+                if end < next {
+                    thread::sleep(next - end);
+                    *prev_time = next;
+                } else {
+                    // Running behind, don't rush to catch up afterwards
+                    *prev_time = end;
+                }
+            }
+            _ => *prev_time = end,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_speed_rate() {
+        let mut speed = Speed::new(Some(4.0));
+        assert_eq!(Some(1.0), speed.rate());
+        speed.slow = 2;
+        assert_eq!(Some(0.25), speed.rate());
+        speed.fast_forward = true;
+        assert_eq!(Some(4.0), speed.rate());
+
+        let base = Duration::new(0, 16_000_000);
+        assert_eq!(
+            Some(Duration::new(0, 4_000_000)),
+            speed.frame_duration(base)
+        );
+        speed.turbo_rate = None;
+        assert_eq!(None, speed.frame_duration(base));
+        speed.paused = true;
+        assert_eq!(Some(base), speed.frame_duration(base));
+    }
+
+    #[test]
+    fn test_parse_turbo() {
+        assert_eq!(Ok(None), parse_turbo("unlimited"));
+        assert_eq!(Ok(Some(2.5)), parse_turbo("2.5"));
+        assert!(parse_turbo("1").is_err());
+        assert!(parse_turbo("fast").is_err());
+    }
+}
@@ -9,10 +9,10 @@ use super::IoReg;
 const CHANNELS: usize = 4;
 
 #[derive(Copy, Clone, Default, Serialize, Deserialize)]
-pub struct Dma<'a> {
+pub struct Dma {
     chs: [DmaCh; CHANNELS],
     #[serde(skip)]
-    io: Shared<IoReg<'a>>,
+    io: Shared<IoReg>,
 
     active_len: u32,
 }
@@ -28,7 +28,8 @@ struct DmaCh {
 pub enum Trigger {
     HBlank,
     VBlank,
-    SoundFifo,
+    /// A sound FIFO, by its register address, has room for more samples
+    SoundFifo(u32),
 }
 
 #[derive(Copy, Clone, PartialEq, Debug)]
@@ -52,8 +53,8 @@ fn addr_bits(reg: Register, ch: usize) -> u32 {
     }
 }
 
-impl<'a> Dma<'a> {
-    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+impl Dma {
+    pub fn init(&mut self, io: Shared<IoReg>) {
         self.io = io;
     }
 
@@ -82,10 +83,21 @@ impl<'a> Dma<'a> {
             let run = match trigger {
                 Trigger::HBlank => timing == 2,
                 Trigger::VBlank => timing == 1,
-                Trigger::SoundFifo => timing == 3 && (ch == 1 || ch == 2),
+                Trigger::SoundFifo(fifo) => {
+                    timing == 3 && (ch == 1 || ch == 2) && self.chs[ch].dad == 0x0400_0000 | fifo
+                }
             };
-            if run {
-                self.refresh(ch, ctrl as u16, true);
+            if !run {
+                continue;
+            }
+            self.refresh(ch, ctrl as u16, true);
+            if let Trigger::SoundFifo(_) = trigger {
+                // Always 4 words to the same address, whatever the count and
+                // control say
+                self.chs[ch].len = 4;
+                let fifo_ctrl = (ctrl as u16 | (1 << 10)) & !(3 << 5) | (2 << 5);
+                self.start(ch, fifo_ctrl);
+            } else {
                 self.start(ch, ctrl as u16);
             }
         }
@@ -127,7 +139,7 @@ impl<'a> Dma<'a> {
     }
 }
 
-fn do_copy<'a>(regs: &mut DmaCh, mmu: &mut GbaMmu<'a>, ctrl: u16) {
+fn do_copy(regs: &mut DmaCh, mmu: &mut GbaMmu, ctrl: u16) {
     let ctrl = ctrl as u32;
     let halfword = bit(ctrl, 10) == 0;
     let word = if halfword { 2 } else { 4 };
@@ -1,54 +1,81 @@
-use sdl2::keyboard::KeyboardState;
-
 use bit_util::bit;
 
 use super::{IoReg, KEYCNT, KEYINPUT};
 
+/// The GBA's buttons, in KEYINPUT bit order
+#[derive(Copy, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Deserialize)]
+pub enum Key {
+    A,
+    B,
+    Select,
+    Start,
+    Right,
+    Left,
+    Up,
+    Down,
+    R,
+    L,
+}
+
+impl Key {
+    pub const ALL: [Key; 10] = [
+        Key::A,
+        Key::B,
+        Key::Select,
+        Key::Start,
+        Key::Right,
+        Key::Left,
+        Key::Up,
+        Key::Down,
+        Key::R,
+        Key::L,
+    ];
+
+    #[inline]
+    fn mask(self) -> u16 {
+        1 << (self as u16)
+    }
+}
+
+/// The set of keys held down
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
 pub struct KeyState {
-    a: bool,
-    b: bool,
-    select: bool,
-    start: bool,
-    r: bool,
-    l: bool,
-    u: bool,
-    d: bool,
-    br: bool,
-    bl: bool,
+    pressed: u16,
 }
 
 impl KeyState {
-    // TODO: abstract away key selections, use a trait here
-    pub fn new_from_keystate(state: &KeyboardState) -> Self {
-        use sdl2::keyboard::Scancode::*;
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// The keys held down according to a KEYINPUT value
+    pub fn from_keyinput(keyinput: u16) -> Self {
         KeyState {
-            a: state.is_scancode_pressed(L),
-            b: state.is_scancode_pressed(K),
-            select: state.is_scancode_pressed(Z),
-            start: state.is_scancode_pressed(X),
-            r: state.is_scancode_pressed(D),
-            l: state.is_scancode_pressed(A),
-            u: state.is_scancode_pressed(W),
-            d: state.is_scancode_pressed(S),
-            br: state.is_scancode_pressed(P),
-            bl: state.is_scancode_pressed(I),
+            pressed: !keyinput & 0x3ff,
         }
     }
+
+    pub fn press(&mut self, key: Key) {
+        self.pressed |= key.mask();
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.pressed &= !key.mask();
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed & key.mask() != 0
+    }
+
+    /// The value of KEYINPUT, which has a 0 bit for each key held down
+    pub fn keyinput(&self) -> u16 {
+        !self.pressed & 0x3ff
+    }
 }
 
-impl<'a> IoReg<'a> {
+impl IoReg {
     pub fn set_keyreg(&mut self, state: &KeyState) {
-        let vals = ((state.a as u16) << 0)
-            | ((state.b as u16) << 1)
-            | ((state.select as u16) << 2)
-            | ((state.start as u16) << 3)
-            | ((state.r as u16) << 4)
-            | ((state.l as u16) << 5)
-            | ((state.u as u16) << 6)
-            | ((state.d as u16) << 7)
-            | ((state.br as u16) << 8)
-            | ((state.bl as u16) << 9);
-        let reg = !vals & 0x3ff;
+        let reg = state.keyinput();
         self.set_priv(KEYINPUT, reg);
 
         let keycnt = self.get_priv(KEYCNT);
@@ -69,3 +96,22 @@ impl<'a> IoReg<'a> {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_keyinput() {
+        let mut state = KeyState::new();
+        assert_eq!(0x3ff, state.keyinput());
+        state.press(Key::A);
+        state.press(Key::Up);
+        state.press(Key::L);
+        assert_eq!(0x3ff & !((1 << 0) | (1 << 6) | (1 << 9)), state.keyinput());
+        assert_eq!(state, KeyState::from_keyinput(state.keyinput()));
+        state.release(Key::Up);
+        assert!(!state.is_pressed(Key::Up));
+        assert!(state.is_pressed(Key::L));
+    }
+}
@@ -1,11 +1,14 @@
 mod dma;
 pub mod key;
 pub mod ppu;
+pub mod sio;
 pub mod spu;
 mod timer;
 
-use self::dma::Dma;
+use self::dma::{Dma, Trigger};
 use self::ppu::Ppu;
+use self::sio::{Sio, UART_QUEUE_LEN};
+use self::spu::{Sound, FIFO_LEN};
 use self::timer::Timers;
 
 use cpu::{exception, Cpu};
@@ -25,22 +28,28 @@ const IE: u32 = 0x200;
 const IF: u32 = 0x202;
 const IME: u32 = 0x208;
 
+/// The most the sound FIFOs and UART queues hold, which is all that changes
+/// the length of a save state
+pub const MAX_QUEUED: usize = 2 * FIFO_LEN + 2 * UART_QUEUE_LEN;
+
 #[derive(Serialize, Deserialize)]
-pub struct IoReg<'a> {
+pub struct IoReg {
     reg: Ram,
 
     #[serde(skip)]
-    cpu: Shared<Cpu<GbaMmu<'a>>>,
+    cpu: Shared<Cpu<GbaMmu>>,
     #[serde(skip)]
-    mmu: Shared<GbaMmu<'a>>,
+    mmu: Shared<GbaMmu>,
     #[serde(skip)]
-    ppu: Shared<Ppu<'a>>,
+    ppu: Shared<Ppu>,
 
-    timers: Timers<'a>,
-    dma: Dma<'a>,
+    timers: Timers,
+    dma: Dma,
+    sound: Sound,
+    sio: Sio,
 }
 
-impl<'a> IoReg<'a> {
+impl IoReg {
     pub fn new() -> Self {
         let mut io = IoReg {
             reg: Ram::new(IO_REG_SIZE),
@@ -49,6 +58,8 @@ impl<'a> IoReg<'a> {
             ppu: Shared::empty(),
             timers: Default::default(),
             dma: Default::default(),
+            sound: Default::default(),
+            sio: Default::default(),
         };
         io.set_initial();
         io
@@ -59,28 +70,79 @@ impl<'a> IoReg<'a> {
         self.reg.set16(0x26, 0x100);
         self.reg.set16(0x30, 0x100);
         self.reg.set16(0x36, 0x100);
+        // SOUNDBIAS as the BIOS leaves it
+        self.reg.set16(0x88, 0x200);
     }
 
-    pub fn init(
-        &mut self,
-        cpu: Shared<Cpu<GbaMmu<'a>>>,
-        mmu: Shared<GbaMmu<'a>>,
-        ppu: Shared<Ppu<'a>>,
-    ) {
+    pub fn init(&mut self, cpu: Shared<Cpu<GbaMmu>>, mmu: Shared<GbaMmu>, ppu: Shared<Ppu>) {
         self.cpu = cpu;
         self.mmu = mmu;
         self.ppu = ppu;
 
-        let io = Shared::new(self);
+        // The IoReg is in place in its System by now
+        let io = unsafe { Shared::new(self) };
+        self.timers.init(io);
+        self.dma.init(io);
+    }
+
+    /// Takes on the registers of a save state, keeping the links to the rest
+    /// of the system
+    pub fn restore(&mut self, saved: IoReg) {
+        self.reg = saved.reg;
+        self.timers = saved.timers;
+        self.dma = saved.dma;
+        self.sound = saved.sound;
+        self.sio.restore(saved.sio);
+
+        // As in `init`
+        let io = unsafe { Shared::new(self) };
         self.timers.init(io);
         self.dma.init(io);
     }
 
     pub fn cycle(&mut self) {
         self.timers.cycle();
+        self.sio_cycle();
         self.check_interrupt();
     }
 
+    /// Feeds the sound FIFOs played by a timer that just overflowed
+    fn timer_overflow(&mut self, timer: u32) {
+        let timers = Sound::fifo_timers(&self.reg);
+        for (fifo, &t) in timers.iter().enumerate() {
+            if t == timer && self.sound.fifo_timer(fifo) {
+                self.dma.trigger(Trigger::SoundFifo(0xa0 + 4 * fifo as u32));
+            }
+        }
+    }
+
+    pub fn sound(&self) -> &Sound {
+        &self.sound
+    }
+
+    /// Bytes held in the sound FIFOs and UART queues, out of `MAX_QUEUED`
+    pub fn queued(&self) -> usize {
+        self.sound.fifo_len(0) + self.sound.fifo_len(1) + self.sio.queued()
+    }
+
+    /// Side effects of the CPU reading registers, which plain loads don't
+    /// have
+    pub fn cpu_read(&mut self, addr: u32, len: u32) {
+        self.sio_read(addr, len);
+    }
+
+    /// Finishes drawing the current line before the palette, VRAM or OAM it's
+    /// drawn from is written, so the write only shows on the rest of the line
+    pub fn video_written(&mut self) {
+        self.ppu.flush();
+    }
+
+    /// Reads a register as it was written, including write-only bits, for
+    /// debuggers
+    pub fn peek(&self, addr: u32) -> u16 {
+        self.get_priv(addr)
+    }
+
     pub fn dma_length(&self) -> u32 {
         self.dma.length()
     }
@@ -120,6 +182,8 @@ impl<'a> IoReg<'a> {
         }
         let val = match addr {
             0x100 | 0x104 | 0x108 | 0x10c => self.timers.get((addr - 0x100) / 4),
+            0x128 => self.sio_control(),
+            0x12a => self.sio_data8(),
             _ => self.reg.load16(addr).get(),
         };
         let wo = wo_mask(addr);
@@ -135,6 +199,12 @@ impl<'a> IoReg<'a> {
             // If not writable, no point in doing anything
             return;
         }
+        if addr < 0x60 {
+            // Display registers, finish drawing the pixels the old values
+            // apply to before changing them
+            self.ppu.flush();
+        }
+
         let ro = ro_mask(addr);
         let old = self.get_priv(addr);
         let nval = (ro & old) | (!ro & val);
@@ -146,10 +216,12 @@ impl<'a> IoReg<'a> {
 
     fn updated(&mut self, addr: u32, old: u16, new: u16) {
         match addr {
-            0x28 | 0x2a | 0x2c | 0x2e => self.ppu.update_bg2ref(),
-            0x38 | 0x3a | 0x3c | 0x3e => self.ppu.update_bg3ref(),
+            0x28 | 0x2a | 0x2c | 0x2e => self.ppu.bgref_written(2),
+            0x38 | 0x3a | 0x3c | 0x3e => self.ppu.bgref_written(3),
             0xBA | 0xC6 | 0xD2 | 0xDE => self.dma.updated(addr - 0xB0, old, new),
+            0x60..=0xa6 => self.sound.written(addr, new, &self.reg),
             0x102 | 0x106 | 0x10a | 0x10e => self.timers.updated((addr - 0x102) / 4, old, new),
+            0x128 | 0x12a => self.sio_written(addr, old, new),
             0x130 => {
                 let keycnt = self.get_priv(KEYCNT);
                 self.check_key_intr(keycnt, new);
@@ -170,7 +242,7 @@ impl<'a> IoReg<'a> {
 }
 
 // TODO: implement read/write masks
-impl<'a> Mmu for IoReg<'a> {
+impl Mmu for IoReg {
     fn load8(&self, addr: u32) -> MemoryRead<u8> {
         use self::MemoryRead::*;
 
@@ -1,6 +1,7 @@
+use std::cmp::min;
 use std::default::Default;
 
-use sdl2::render::Texture;
+use byteorder::{ByteOrder, LittleEndian};
 
 use mmu::gba::Gba as GbaMmu;
 use shared::Shared;
@@ -10,6 +11,8 @@ use super::*;
 
 mod render;
 
+pub use self::render::{bg_map, colour_rgb, obj_affine_params, Layer, ObjAttrs, ObjMode};
+
 pub const COLS: u32 = 240;
 pub const ROWS: u32 = 160;
 
@@ -19,49 +22,59 @@ const FRAME_BYTES: usize = ROW_BYTES * (ROWS as usize);
 
 /// Handle scanline drawing here
 // We skip almost everything because at the moment, save states can only be taken at frame
-// boundaries
+// boundaries.  The last frame's kept so it's what's on screen after loading.
 #[derive(Serialize, Deserialize)]
-pub struct Ppu<'a> {
-    #[serde(skip)]
-    texture: Shared<Texture<'a>>,
-
-    #[serde(skip, default = "empty_frame")]
-    pixels: [u8; FRAME_BYTES],
+pub struct Ppu {
+    // On the heap so a Ppu is cheap to move around, like when a save state's
+    // read in on a frontend's thread with a small stack
+    pixels: Box<[u8]>,
 
     #[serde(skip)]
-    io: Shared<IoReg<'a>>,
+    io: Shared<IoReg>,
     #[serde(skip)]
-    mmu: Shared<GbaMmu<'a>>,
+    mmu: Shared<GbaMmu>,
     col: u32,
     row: u32,
     delay: u8,
 
+    // Columns of the current line that have already been drawn, the line is
+    // drawn lazily so register writes partway through a line take effect
+    // at the right pixel
+    #[serde(skip)]
+    drawn: u32,
+
     #[serde(skip)]
     state: render::RenderState,
 }
 
-fn empty_frame() -> [u8; FRAME_BYTES] {
-    [0u8; FRAME_BYTES]
+fn empty_frame() -> Box<[u8]> {
+    vec![0u8; FRAME_BYTES].into_boxed_slice()
 }
 
-impl<'a> Ppu<'a> {
-    pub fn new(
-        texture: Shared<Texture<'a>>,
-        io: Shared<IoReg<'a>>,
-        mmu: Shared<GbaMmu<'a>>,
-    ) -> Self {
+impl Ppu {
+    pub fn new(io: Shared<IoReg>, mmu: Shared<GbaMmu>) -> Self {
         Ppu {
-            texture: texture,
-            pixels: [0u8; FRAME_BYTES],
+            pixels: empty_frame(),
             io: io,
             mmu: mmu,
             col: 0,
             row: 0,
             delay: 0,
+            drawn: 0,
             state: Default::default(),
         }
     }
 
+    /// Takes on the position in the frame and the last frame drawn of a save
+    /// state
+    pub fn restore(&mut self, saved: Ppu) {
+        self.pixels = saved.pixels;
+        self.col = saved.col;
+        self.row = saved.row;
+        self.delay = saved.delay;
+        self.drawn = 0;
+    }
+
     pub fn cycle(&mut self) {
         if self.delay != 0 {
             self.delay -= 1;
@@ -79,6 +92,7 @@ impl<'a> Ppu<'a> {
 
         self.col += 1;
         if self.col == 240 {
+            self.line_end();
             self.hblank();
         } else if self.col == 308 {
             self.col = 0;
@@ -88,7 +102,6 @@ impl<'a> Ppu<'a> {
                 self.vblank();
             } else if self.row == 228 {
                 self.row = 0;
-                self.vblank_end();
             }
         }
     }
@@ -117,10 +130,31 @@ impl<'a> Ppu<'a> {
         ds &= !2; // unset hblank flag
         self.io.set_priv(DISPSTAT, ds);
 
+        self.drawn = 0;
+        if self.state.bg2ref_dirty {
+            self.update_bg2ref();
+        }
+        if self.state.bg3ref_dirty {
+            self.update_bg3ref();
+        }
+    }
+
+    fn line_end(&mut self) {
         if self.row < 160 {
-            // The borrow checker is really strict... self.row.clone() didn't work
-            let row = self.row;
-            self.render_line(row);
+            self.flush();
+            self.finish_line();
+        }
+    }
+
+    /// Draws the current line up to the current column.  This needs to be
+    /// called before any display register changes so that the change only
+    /// affects the rest of the line.
+    pub fn flush(&mut self) {
+        let end = min(self.col, COLS);
+        if self.row < ROWS && self.drawn < end {
+            let (row, start) = (self.row, self.drawn);
+            self.render_span(row, start, end);
+            self.drawn = end;
         }
     }
 
@@ -146,36 +180,158 @@ impl<'a> Ppu<'a> {
         self.io.dma.trigger(Trigger::VBlank);
     }
 
-    fn vblank_end(&mut self) {
-        // wrap around, blit our image to the texture
-        let pixels = Shared::new(&mut self.pixels);
-        self.texture
-            .with_lock(None, |buf, pitch| {
-                for row in 0..160 {
-                    let buf_start = row * pitch;
-                    let pix_start = row * ROW_BYTES;
-                    buf[buf_start..buf_start + ROW_BYTES]
-                        .clone_from_slice(&pixels[pix_start..pix_start + ROW_BYTES]);
-                }
-            })
-            .unwrap();
+    /// The last frame drawn, pixels packed as 0x00RRGGBB.  Frames end with
+    /// VBlank, so between frames this is what's on screen.
+    pub fn frame(&self) -> Vec<u32> {
+        let mut frame = vec![0; (COLS * ROWS) as usize];
+        LittleEndian::read_u32_into(&self.pixels, &mut frame);
+        frame
+    }
+
+    /// Marks the BG2/BG3 reference point registers as written.  The internal
+    /// reference points are latched at the start of the next line.
+    pub fn bgref_written(&mut self, bg: u8) {
+        match bg {
+            2 => self.state.bg2ref_dirty = true,
+            3 => self.state.bg3ref_dirty = true,
+            _ => unreachable!(),
+        }
     }
 
-    pub fn update_bg2ref(&mut self) {
+    fn update_bg2ref(&mut self) {
         let xl = self.io.get_priv(0x28);
         let xh = self.io.get_priv(0x2a);
         let yl = self.io.get_priv(0x2c);
         let yh = self.io.get_priv(0x2e);
 
         self.state.bg2ref = render::BgRef::new(xl, xh, yl, yh);
+        self.state.bg2ref_dirty = false;
     }
 
-    pub fn update_bg3ref(&mut self) {
+    fn update_bg3ref(&mut self) {
         let xl = self.io.get_priv(0x38);
         let xh = self.io.get_priv(0x3a);
         let yl = self.io.get_priv(0x3c);
         let yh = self.io.get_priv(0x3e);
 
         self.state.bg3ref = render::BgRef::new(xl, xh, yl, yh);
+        self.state.bg3ref_dirty = false;
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::MemoryUnit;
+    use rom::GameRom;
+    use system::System;
+
+    const GREEN: u16 = 0x3e0;
+    const RED: u16 = 0x1f;
+
+    // Runs the PPU on its own until it's at a column of a line
+    fn run_to(sys: &mut System, row: u32, col: u32) {
+        while sys.ppu.row != row || sys.ppu.col != col {
+            sys.ppu.cycle();
+        }
+    }
+
+    fn pixel(sys: &System, row: u32, col: u32) -> u32 {
+        sys.ppu.frame()[(row * COLS + col) as usize]
+    }
+
+    // Mode 3 with BG2 on, its bitmap filled with `colour(x, y)`
+    fn bitmap<F: Fn(u32, u32) -> u16>(colour: F) -> Box<System> {
+        let mut sys = System::new(GameRom::default(), GameRom::default(), true);
+        for y in 0..ROWS {
+            for x in 0..COLS {
+                sys.mmu.set16(0x6000000 + 2 * (y * COLS + x), colour(x, y));
+            }
+        }
+        sys.mmu.set16(0x4000000, 0x0403);
+        sys
+    }
+
+    #[test]
+    fn test_dispcnt_mid_line() {
+        let mut sys = bitmap(|_, _| GREEN);
+        sys.mmu.set16(0x5000000, RED);
+        run_to(&mut sys, 10, 120);
+        // BG2 off leaves the backdrop for the rest of the line
+        sys.mmu.set16(0x4000000, 0x0003);
+        run_to(&mut sys, 11, 0);
+        assert_eq!(colour_rgb(GREEN), pixel(&sys, 10, 119));
+        assert_eq!(colour_rgb(RED), pixel(&sys, 10, 120));
+        assert_eq!(colour_rgb(RED), pixel(&sys, 10, 239));
+    }
+
+    #[test]
+    fn test_palette_mid_line() {
+        let mut sys = System::new(GameRom::default(), GameRom::default(), true);
+        // Mode 4 with BG2 on, every pixel palette entry 1
+        for i in 0..ROWS * COLS / 2 {
+            sys.mmu.set16(0x6000000 + 2 * i, 0x0101);
+        }
+        sys.mmu.set16(0x5000002, GREEN);
+        sys.mmu.set16(0x4000000, 0x0404);
+        run_to(&mut sys, 10, 120);
+        sys.mmu.set16(0x5000002, RED);
+        run_to(&mut sys, 11, 0);
+        assert_eq!(colour_rgb(GREEN), pixel(&sys, 10, 119));
+        assert_eq!(colour_rgb(RED), pixel(&sys, 10, 120));
+        assert_eq!(colour_rgb(RED), pixel(&sys, 10, 239));
+    }
+
+    #[test]
+    fn test_hofs_mid_line() {
+        let mut sys = System::new(GameRom::default(), GameRom::default(), true);
+        // Tile 1 is solid colour 1, tile 0 is transparent
+        for i in 0..16 {
+            sys.mmu.set16(0x6000020 + 2 * i, 0x1111);
+        }
+        // Every other column of the map has tile 1
+        for i in 0..32 * 32 {
+            sys.mmu.set16(0x600f800 + 2 * i, (i % 2 == 0) as u16);
+        }
+        sys.mmu.set16(0x5000000, RED);
+        sys.mmu.set16(0x5000002, GREEN);
+        sys.mmu.set16(0x4000008, 0x1f00);
+        sys.mmu.set16(0x4000000, 0x0100);
+
+        run_to(&mut sys, 10, 120);
+        sys.mmu.set16(0x4000010, 8);
+        run_to(&mut sys, 11, 0);
+        // Scrolled a tile along, the columns swap over at the write
+        for col in 0..COLS {
+            let tile_one = (col / 8 % 2 == 0) == (col < 120);
+            let expected = if tile_one { GREEN } else { RED };
+            assert_eq!(colour_rgb(expected), pixel(&sys, 10, col), "{}", col);
+        }
+    }
+
+    #[test]
+    fn test_bgref_latched_next_line() {
+        let mut sys = bitmap(|x, _| x as u16);
+        run_to(&mut sys, 5, 120);
+        // 10 pixels across, in 24.8 fixed point
+        sys.mmu.set16(0x4000028, 10 << 8);
+        run_to(&mut sys, 7, 0);
+        assert_eq!(colour_rgb(130), pixel(&sys, 5, 130));
+        assert_eq!(colour_rgb(10), pixel(&sys, 6, 0));
+        assert_eq!(colour_rgb(140), pixel(&sys, 6, 130));
+    }
+
+    #[test]
+    fn test_pb_pd_accumulate() {
+        let mut sys = bitmap(|x, y| (x | y << 8) as u16 & 0x7fff);
+        // A pixel across and two lines down for every line
+        sys.mmu.set16(0x4000022, 0x100);
+        sys.mmu.set16(0x4000026, 0x200);
+        run_to(&mut sys, 40, 0);
+        for row in 0..40 {
+            let expected = (10 + row) | (2 * row) << 8;
+            assert_eq!(colour_rgb(expected as u16), pixel(&sys, row, 10), "{}", row);
+        }
     }
 }
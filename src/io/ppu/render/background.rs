@@ -83,29 +83,62 @@ impl RotScaleCtrl {
     }
 }
 
+/// Looks up the colour of a point in a rotation/scaling background, which
+/// must be within the background's bounds.  None if it's transparent.
+#[inline]
+fn rotscale_texel(mmu: &GbaMmu, ctrl: &RotScaleCtrl, ix: u32, iy: u32) -> Option<u16> {
+    let (xsize, _) = ctrl.size();
+    let base = ctrl.base_addr();
+
+    let colour = match *ctrl {
+        RotScaleCtrl::TileMap(_) => {
+            let tile_idx = (ix / 8) + (iy / 8) * (xsize / 8);
+            let addr = base + tile_idx;
+            let tile = mmu.vram.load8(addr).get() as u32;
+            // 256 colours / 1 palette
+            // one tile is 64 bytes
+            let px_idx = (ix % 8) + (iy % 8) * 8;
+            mmu.vram
+                .load8(ctrl.tile_base_addr() + tile * 64 + px_idx)
+                .get()
+        }
+        RotScaleCtrl::Bitmap(_) => {
+            // mode 3/5 are direct colours, 4 is palette
+            let idx = iy * xsize + ix;
+            if ctrl.is_palette() {
+                mmu.vram.load8(base + idx).get()
+            } else {
+                return Some(mmu.vram.load16(base + idx * 2).get());
+            }
+        }
+    };
+    if colour == 0 {
+        None
+    } else {
+        Some(mmu.pram.load16(colour as u32 * 2).get())
+    }
+}
+
 // FIXME: mosaic
 pub(super) fn render_rotscale_line(
     line: &mut LineBuf,
     mmu: &GbaMmu,
-    bgref: &mut BgRef,
+    bgref: &BgRef,
     params: RotScaleParams,
     ctrl: RotScaleCtrl,
     bg: u8,
+    start: u32,
+    end: u32,
 ) {
     // upper 8 bits are priority
     // add 1 so OBJ will have lower priority here
     let prio = (ctrl.priority() << 28) | ((bg as u32 + 1) << 25);
 
-    let base = ctrl.base_addr();
-    let tile_base = ctrl.tile_base_addr();
-
     let (xsize, ysize) = ctrl.size();
 
-    let is_palette = ctrl.is_palette();
-
-    let mut xval = bgref.xref;
-    let mut yval = bgref.yref;
-    for x in 0..COLS {
+    let mut xval = bgref.xref.wrapping_add(start.wrapping_mul(params.a));
+    let mut yval = bgref.yref.wrapping_add(start.wrapping_mul(params.c));
+    for x in start..end {
         let nx = xval >> 8;
         let ny = yval >> 8;
 
@@ -116,35 +149,9 @@ pub(super) fn render_rotscale_line(
         };
 
         let colour = if ix < xsize && iy < ysize {
-            match ctrl {
-                RotScaleCtrl::TileMap(_) => {
-                    let tile_idx = (ix / 8) + (iy / 8) * (xsize / 8);
-                    let addr = base + tile_idx;
-                    let tile = mmu.vram.load8(addr).get() as u32;
-                    // 256 colours / 1 palette
-                    // one tile is 64 bytes
-                    let px_idx = (ix % 8) + (iy % 8) * 8;
-                    let colour = mmu.vram.load8(tile_base + tile * 64 + px_idx).get();
-                    if colour == 0 {
-                        TRANSPARENT
-                    } else {
-                        mmu.pram.load16(colour as u32 * 2).get() as u32 | prio
-                    }
-                }
-                RotScaleCtrl::Bitmap(_) => {
-                    // mode 3/5 are direct colours, 4 is palette
-                    let idx = iy * xsize + ix;
-                    if is_palette {
-                        let colour = mmu.vram.load8(base + idx).get();
-                        if colour == 0 {
-                            TRANSPARENT
-                        } else {
-                            mmu.pram.load16(colour as u32 * 2).get() as u32 | prio
-                        }
-                    } else {
-                        mmu.vram.load16(base + idx * 2).get() as u32 | prio
-                    }
-                }
+            match rotscale_texel(mmu, &ctrl, ix, iy) {
+                Some(c) => c as u32 | prio,
+                None => TRANSPARENT,
             }
         } else {
             TRANSPARENT
@@ -155,9 +162,6 @@ pub(super) fn render_rotscale_line(
         xval = xval.wrapping_add(params.a);
         yval = yval.wrapping_add(params.c);
     }
-
-    bgref.xref = bgref.xref.wrapping_add(params.b);
-    bgref.yref = bgref.yref.wrapping_add(params.d);
 }
 
 trait TextCtrl {
@@ -201,68 +205,187 @@ impl TextCtrl for u16 {
     }
 }
 
-pub(super) fn render_textmode_line(line: &mut LineBuf, row: u32, mmu: &GbaMmu, bg: u8) {
-    let ctrl = mmu.io.get_priv(8 + (bg as u32) * 2);
-    let prio = (ctrl.priority() << 28) | (1 << 27) | ((bg as u32) << 25);
+/// Looks up the colour of a point in a text background, which must be
+/// within the background's bounds.  None if it's transparent.
+#[inline]
+fn text_texel(mmu: &GbaMmu, ctrl: u16, nx: u32, ny: u32) -> Option<u16> {
+    let (xsize, ysize) = ctrl.size();
+    let c256 = ctrl.is256c();
+
+    let map = if xsize == 256 || ysize == 256 {
+        (nx >= 256) as u32 + (ny >= 256) as u32
+    } else {
+        (nx >= 256) as u32 + ((ny >= 256) as u32) * 2
+    };
+
+    let ix = nx % 256;
+    let iy = ny % 256;
+
+    let tile_idx = (ix / 8) + (iy / 8) * 32;
+    let addr = ctrl.base_addr() + map * (2 * 1024) + tile_idx * 2;
+
+    let tile = mmu.vram.load16(addr).get() as u32;
+
+    let palette = if c256 { 0 } else { extract(tile, 12, 4) };
+    let tile_num = extract(tile, 0, 10);
+
+    // * 1 if c16, * 2 otherwise
+    let tx = if bit(tile, 10) == 0 {
+        ix % 8
+    } else {
+        7 - (ix % 8)
+    };
+    let ty = if bit(tile, 11) == 0 {
+        iy % 8
+    } else {
+        7 - (iy % 8)
+    };
+    let px_idx = (tx % 8) + (ty % 8) * 8;
 
-    let base = ctrl.base_addr();
     let tile_base = ctrl.tile_base_addr();
+    let px_addr = tile_num * 64 + px_idx;
+    let palette_colour = if c256 {
+        mmu.vram.load8(tile_base + px_addr).get()
+    } else {
+        (mmu.vram.load8(tile_base + px_addr / 2).get() >> ((px_addr & 1) * 4)) & 0xf
+    };
+    if palette_colour == 0 {
+        None
+    } else {
+        Some(
+            mmu.pram
+                .load16(palette * 32 + (palette_colour as u32) * 2)
+                .get(),
+        )
+    }
+}
+
+pub(super) fn render_textmode_line(
+    line: &mut LineBuf,
+    row: u32,
+    mmu: &GbaMmu,
+    bg: u8,
+    start: u32,
+    end: u32,
+) {
+    let ctrl = mmu.io.get_priv(8 + (bg as u32) * 2);
+    let prio = (ctrl.priority() << 28) | (1 << 27) | ((bg as u32) << 25);
 
     let (xsize, ysize) = ctrl.size();
 
     let xoff = extract(mmu.io.get_priv(0x10 + (bg as u32) * 4) as u32, 0, 9);
     let yoff = extract(mmu.io.get_priv(0x12 + (bg as u32) * 4) as u32, 0, 9);
 
-    let c256 = ctrl.is256c();
-
-    for x in 0..COLS {
+    for x in start..end {
         let nx = (x + xoff) & (xsize - 1);
         let ny = (row + yoff) & (ysize - 1);
 
-        let map = if xsize == 256 || ysize == 256 {
-            (nx >= 256) as u32 + (ny >= 256) as u32
-        } else {
-            (nx >= 256) as u32 + ((ny >= 256) as u32) * 2
+        line[x as usize] = match text_texel(mmu, ctrl, nx, ny) {
+            Some(c) => c as u32 | prio,
+            None => TRANSPARENT,
         };
+    }
+}
 
-        let ix = nx % 256;
-        let iy = ny % 256;
-
-        let tile_idx = (ix / 8) + (iy / 8) * 32;
-        let addr = base + map * (2 * 1024) + tile_idx * 2;
-
-        let tile = mmu.vram.load16(addr).get() as u32;
+/// A whole background layer decoded at once, for debugging
+pub struct BgMap {
+    pub width: u32,
+    pub height: u32,
+    /// BGR555 colours row by row, None where transparent
+    pub texels: Vec<Option<u16>>,
+    /// The corners of the screen mapped onto the layer, in the order
+    /// top left, top right, bottom right, bottom left
+    pub viewport: [(i32, i32); 4],
+    pub wrap: bool,
+}
 
-        let palette = if c256 { 0 } else { extract(tile, 12, 4) };
-        // FIXME: horizontal flip, vertical flip
-        let tile_num = extract(tile, 0, 10);
+/// Decodes the whole of a background layer as the current display mode sets
+/// it up, or None if the mode doesn't have that layer
+pub fn bg_map(mmu: &GbaMmu, bg: u8) -> Option<BgMap> {
+    let dspcnt = mmu.io.get_priv(DSPCNT);
+    let mode = extract(dspcnt as u32, 0, 3);
+    let bgu = bg as u32;
+
+    let text = match (mode, bg) {
+        (0, _) | (1, 0) | (1, 1) => true,
+        (1, 2) | (2, 2) | (2, 3) | (3, 2) | (4, 2) | (5, 2) => false,
+        _ => return None,
+    };
+
+    if text {
+        let ctrl = mmu.io.get_priv(8 + bgu * 2);
+        let (width, height) = ctrl.size();
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                texels.push(text_texel(mmu, ctrl, x, y));
+            }
+        }
 
-        // * 1 if c16, * 2 otherwise
-        let tx = if bit(tile, 10) == 0 {
-            ix % 8
+        let xoff = extract(mmu.io.get_priv(0x10 + bgu * 4) as u32, 0, 9) as i32;
+        let yoff = extract(mmu.io.get_priv(0x12 + bgu * 4) as u32, 0, 9) as i32;
+        let (w, h) = (COLS as i32, ROWS as i32);
+        Some(BgMap {
+            width: width,
+            height: height,
+            texels: texels,
+            viewport: [
+                (xoff, yoff),
+                (xoff + w, yoff),
+                (xoff + w, yoff + h),
+                (xoff, yoff + h),
+            ],
+            wrap: true,
+        })
+    } else {
+        let ctrl = if mode < 3 {
+            RotScaleCtrl::TileMap(mmu.io.get_priv(8 + bgu * 2))
         } else {
-            7 - (ix % 8)
+            RotScaleCtrl::Bitmap(dspcnt)
         };
-        let ty = if bit(tile, 11) == 0 {
-            iy % 8
-        } else {
-            7 - (iy % 8)
-        };
-        let px_idx = (tx % 8) + (ty % 8) * 8;
+        let (width, height) = ctrl.size();
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                texels.push(rotscale_texel(mmu, &ctrl, x, y));
+            }
+        }
 
-        let px_addr = tile_num * 64 + px_idx;
-        let palette_colour = if c256 {
-            mmu.vram.load8(tile_base + px_addr).get()
-        } else {
-            (mmu.vram.load8(tile_base + px_addr / 2).get() >> ((px_addr & 1) * 4)) & 0xf
-        };
-        line[x as usize] = if palette_colour == 0 {
-            TRANSPARENT
-        } else {
-            mmu.pram
-                .load16(palette * 32 + (palette_colour as u32) * 2)
-                .get() as u32
-                | prio
+        let regs = 0x20 + (bgu - 2) * 0x10;
+        let params = RotScaleParams::new(
+            mmu.io.get_priv(regs),
+            mmu.io.get_priv(regs + 2),
+            mmu.io.get_priv(regs + 4),
+            mmu.io.get_priv(regs + 6),
+        );
+        let bgref = BgRef::new(
+            mmu.io.get_priv(regs + 8),
+            mmu.io.get_priv(regs + 0xa),
+            mmu.io.get_priv(regs + 0xc),
+            mmu.io.get_priv(regs + 0xe),
+        );
+        let corner = |sx: u32, sy: u32| {
+            let x = bgref
+                .xref
+                .wrapping_add(sx.wrapping_mul(params.a))
+                .wrapping_add(sy.wrapping_mul(params.b));
+            let y = bgref
+                .yref
+                .wrapping_add(sx.wrapping_mul(params.c))
+                .wrapping_add(sy.wrapping_mul(params.d));
+            ((x as i32) >> 8, (y as i32) >> 8)
         };
+        Some(BgMap {
+            width: width,
+            height: height,
+            texels: texels,
+            viewport: [
+                corner(0, 0),
+                corner(COLS, 0),
+                corner(COLS, ROWS),
+                corner(0, ROWS),
+            ],
+            wrap: ctrl.wrap(),
+        })
     }
 }
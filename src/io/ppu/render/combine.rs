@@ -130,28 +130,28 @@ fn blend_semitrans(
     }
 }
 
-impl<'a> Ppu<'a> {
-    fn bg0_drawline(&mut self, mode: u32, row: u32, dspcnt: u16) -> bool {
+impl Ppu {
+    fn bg0_drawline(&mut self, mode: u32, row: u32, dspcnt: u16, start: u32, end: u32) -> bool {
         let bg0en = mode <= 1 && bit(dspcnt as u32, 8) == 1;
         if bg0en {
-            render_textmode_line(&mut self.state.line0, row, &self.mmu, 0);
+            render_textmode_line(&mut self.state.line0, row, &self.mmu, 0, start, end);
         }
         bg0en
     }
 
-    fn bg1_drawline(&mut self, mode: u32, row: u32, dspcnt: u16) -> bool {
+    fn bg1_drawline(&mut self, mode: u32, row: u32, dspcnt: u16, start: u32, end: u32) -> bool {
         let bg1en = mode <= 1 && bit(dspcnt as u32, 9) == 1;
         if bg1en {
-            render_textmode_line(&mut self.state.line1, row, &self.mmu, 1);
+            render_textmode_line(&mut self.state.line1, row, &self.mmu, 1, start, end);
         }
         bg1en
     }
 
-    fn bg2_drawline(&mut self, mode: u32, row: u32, dspcnt: u16) -> bool {
+    fn bg2_drawline(&mut self, mode: u32, row: u32, dspcnt: u16, start: u32, end: u32) -> bool {
         let bg2en = bit(dspcnt as u32, 10) == 1;
         if bg2en {
             if mode == 0 {
-                render_textmode_line(&mut self.state.line2, row, &self.mmu, 2);
+                render_textmode_line(&mut self.state.line2, row, &self.mmu, 2, start, end);
             } else {
                 let rparams = RotScaleParams::new(
                     self.io.get_priv(0x20),
@@ -169,21 +169,23 @@ impl<'a> Ppu<'a> {
                 render_rotscale_line(
                     &mut self.state.line2,
                     &self.mmu,
-                    &mut self.state.bg2ref,
+                    &self.state.bg2ref,
                     rparams,
                     ctrl,
                     2,
+                    start,
+                    end,
                 );
             }
         }
         bg2en
     }
 
-    fn bg3_drawline(&mut self, mode: u32, row: u32, dspcnt: u16) -> bool {
+    fn bg3_drawline(&mut self, mode: u32, row: u32, dspcnt: u16, start: u32, end: u32) -> bool {
         let bg3en = (mode == 0 || mode == 2) && bit(dspcnt as u32, 11) == 1;
         if bg3en {
             if mode == 0 {
-                render_textmode_line(&mut self.state.line3, row, &self.mmu, 3);
+                render_textmode_line(&mut self.state.line3, row, &self.mmu, 3, start, end);
             } else {
                 let rparams = RotScaleParams::new(
                     self.io.get_priv(0x30),
@@ -195,17 +197,19 @@ impl<'a> Ppu<'a> {
                 render_rotscale_line(
                     &mut self.state.line3,
                     &self.mmu,
-                    &mut self.state.bg3ref,
+                    &self.state.bg3ref,
                     rparams,
                     RotScaleCtrl::TileMap(self.io.get_priv(0xe)),
                     3,
+                    start,
+                    end,
                 );
             }
         }
         bg3en
     }
 
-    fn obj_drawline(&mut self, _mode: u32, row: u32, dspcnt: u16) -> bool {
+    fn obj_drawline(&mut self, _mode: u32, row: u32, dspcnt: u16, start: u32, end: u32) -> bool {
         let objen = bit(dspcnt as u32, 12) == 1;
         if objen {
             render_obj_line(
@@ -214,19 +218,27 @@ impl<'a> Ppu<'a> {
                 row,
                 &self.mmu,
                 dspcnt,
+                start,
+                end,
             );
         }
         objen
     }
 
-    pub(super) fn combine_line(&mut self, row: u32, dspcnt: u16) {
+    /// Combines the layers for the columns `start..end` of the given row
+    pub(super) fn combine_line(&mut self, row: u32, dspcnt: u16, start: u32, end: u32) {
         let mode = extract(dspcnt as u32, 0, 3);
-
-        let bg0en = self.bg0_drawline(mode, row, dspcnt);
-        let bg1en = self.bg1_drawline(mode, row, dspcnt);
-        let bg2en = self.bg2_drawline(mode, row, dspcnt);
-        let bg3en = self.bg3_drawline(mode, row, dspcnt);
-        let objen = self.obj_drawline(mode, row, dspcnt);
+        let full_dspcnt = dspcnt;
+        // Layers forced off for debugging act as though DISPCNT disabled them
+        let dspcnt = dspcnt & !self.state.hidden_dspcnt();
+        let effects = !self.layer_hidden(Layer::Effects);
+
+        let bg0en = self.bg0_drawline(mode, row, dspcnt, start, end);
+        let bg1en = self.bg1_drawline(mode, row, dspcnt, start, end);
+        let bg2en = self.bg2_drawline(mode, row, dspcnt, start, end);
+        let bg3en = self.bg3_drawline(mode, row, dspcnt, start, end);
+        let objen =
+            self.obj_drawline(mode, row, full_dspcnt, start, end) && !self.layer_hidden(Layer::Obj);
 
         let win_enable = extract(dspcnt as u32, 13, 3) != 0;
         let in_win0 = bit(dspcnt as u32, 13) == 1 && in_win_vert(self.io.get_priv(0x44), row);
@@ -246,7 +258,7 @@ impl<'a> Ppu<'a> {
 
         let backdrop = (self.mmu.pram.load16(0).get() as u32) | (0xe << 28);
 
-        for x in 0..COLS {
+        for x in start..end {
             let ux = x as usize;
             let en_mask = if win_enable {
                 if in_win0 && in_win_hori(win0h, x) {
@@ -332,7 +344,9 @@ impl<'a> Ppu<'a> {
                 (16, TRANSPARENT)
             };
 
-            self.state.line[ux] = if fc & SEMITRANS != 0 {
+            self.state.line[ux] = if !effects {
+                fc
+            } else if fc & SEMITRANS != 0 {
                 blend_semitrans(effect, bldcnt, bldalpha, bldy, first, fc, second, sc)
             } else if bit(en_mask, 5) == 1 && effect != 0 {
                 blend(effect, bldcnt, bldalpha, bldy, first, fc, second, sc)
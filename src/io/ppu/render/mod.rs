@@ -12,30 +12,61 @@ use serde::{Serialize, Serializer};
 
 use bit_util::{bit, extract, sign_extend};
 
-use super::{Ppu, COLS, DSPCNT, PIX_BYTES};
+use super::{Ppu, COLS, DSPCNT, PIX_BYTES, ROWS};
 
 mod background;
 mod combine;
 mod object;
 
+pub use self::background::bg_map;
+pub use self::object::{obj_affine_params, ObjAttrs, ObjMode};
+
 const TRANSPARENT: u32 = 0xf0000000;
 
-impl<'a> Ppu<'a> {
-    /// Renders the current line into the line field in state
-    pub(super) fn render_line(&mut self, row: u32) {
+impl Ppu {
+    /// Renders the columns `start..end` of the current line into the frame
+    pub(super) fn render_span(&mut self, row: u32, start: u32, end: u32) {
         let dspcnt = self.io.get_priv(DSPCNT);
         let mode = extract(dspcnt as u32, 0, 3);
-        debug!("Rendering mode {} scanline: {:#06x}", mode, dspcnt);
-        self.combine_line(row, dspcnt);
+        debug!(
+            "Rendering mode {} scanline {}, {}..{}: {:#06x}",
+            mode, row, start, end, dspcnt
+        );
+        self.combine_line(row, dspcnt, start, end);
 
-        for x in 0..COLS {
+        for x in start..end {
             let idx = row * COLS + x;
             let off = idx as usize * PIX_BYTES;
-            let colour = colour16_rgb(self.state.line[x as usize] as u16);
-            let rgb = colour_pack(colour);
+            let rgb = colour_rgb(self.state.line[x as usize] as u16);
             LittleEndian::write_u32(&mut self.pixels[off..off + PIX_BYTES], rgb);
         }
     }
+
+    /// Forces a layer off (or lets DISPCNT decide again), for debugging
+    pub fn set_layer_hidden(&mut self, layer: Layer, hidden: bool) {
+        if hidden {
+            self.state.hidden_layers |= layer.mask();
+        } else {
+            self.state.hidden_layers &= !layer.mask();
+        }
+    }
+
+    pub fn layer_hidden(&self, layer: Layer) -> bool {
+        self.state.hidden_layers & layer.mask() != 0
+    }
+
+    /// Steps the internal affine reference points on to the next line
+    pub(super) fn finish_line(&mut self) {
+        let mode = extract(self.io.get_priv(DSPCNT) as u32, 0, 3);
+        if mode != 0 {
+            let params = RotScaleParams::new(0, self.io.get_priv(0x22), 0, self.io.get_priv(0x26));
+            self.state.bg2ref.advance(&params);
+        }
+        if mode == 2 {
+            let params = RotScaleParams::new(0, self.io.get_priv(0x32), 0, self.io.get_priv(0x36));
+            self.state.bg3ref.advance(&params);
+        }
+    }
 }
 
 struct LineBuf([u32; COLS as usize]);
@@ -75,6 +106,75 @@ pub(super) struct RenderState {
 
     pub(super) bg2ref: BgRef,
     pub(super) bg3ref: BgRef,
+
+    // Set when the reference point registers are written, the internal
+    // reference points are reloaded at the start of the next line
+    pub(super) bg2ref_dirty: bool,
+    pub(super) bg3ref_dirty: bool,
+
+    // Layers forced off for debugging, one bit per `Layer`
+    hidden_layers: u16,
+}
+
+impl RenderState {
+    /// The DISPCNT enable bits of the hidden backgrounds and windows.  OBJ
+    /// isn't included since objects still have to be drawn for the OBJ window.
+    fn hidden_dspcnt(&self) -> u16 {
+        let h = self.hidden_layers;
+        ((h & 0xf) << 8) | (((h >> 6) & 0x3) << 13) | (((h >> 5) & 0x1) << 15)
+    }
+}
+
+/// The parts of the picture that can be forced off for debugging, on top of
+/// what DISPCNT enables
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Layer {
+    Bg0,
+    Bg1,
+    Bg2,
+    Bg3,
+    Obj,
+    ObjWindow,
+    Win0,
+    Win1,
+    Effects,
+}
+
+impl Layer {
+    pub const ALL: [Layer; 9] = [
+        Layer::Bg0,
+        Layer::Bg1,
+        Layer::Bg2,
+        Layer::Bg3,
+        Layer::Obj,
+        Layer::ObjWindow,
+        Layer::Win0,
+        Layer::Win1,
+        Layer::Effects,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Layer::Bg0 => "bg0",
+            Layer::Bg1 => "bg1",
+            Layer::Bg2 => "bg2",
+            Layer::Bg3 => "bg3",
+            Layer::Obj => "obj",
+            Layer::ObjWindow => "objwin",
+            Layer::Win0 => "win0",
+            Layer::Win1 => "win1",
+            Layer::Effects => "effects",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layer> {
+        Layer::ALL.iter().cloned().find(|l| l.name() == name)
+    }
+
+    #[inline]
+    fn mask(self) -> u16 {
+        1 << (self as u16)
+    }
 }
 
 #[derive(Default, Copy, Clone, Debug, Serialize, Deserialize)]
@@ -109,14 +209,22 @@ impl BgRef {
             yref: sign_extend((yl as u32) | ((yh as u32) << 16), 28),
         }
     }
+
+    fn advance(&mut self, params: &RotScaleParams) {
+        self.xref = self.xref.wrapping_add(params.b);
+        self.yref = self.yref.wrapping_add(params.d);
+    }
 }
 
+// Each 5-bit channel is widened to 8 by repeating its top bits in the
+// bottom, so full intensity is 0xff
 fn colour16_rgb(colour: u16) -> (u8, u8, u8) {
     let c32 = colour as u32;
+    let expand = |v: u32| (v << 3 | v >> 2) as u8;
     (
-        (extract(c32, 0, 5) << 3) as u8,
-        (extract(c32, 5, 5) << 3) as u8,
-        (extract(c32, 10, 5) << 3) as u8,
+        expand(extract(c32, 0, 5)),
+        expand(extract(c32, 5, 5)),
+        expand(extract(c32, 10, 5)),
     )
 }
 
@@ -124,6 +232,11 @@ fn colour_pack(colour: (u8, u8, u8)) -> u32 {
     ((colour.0 as u32) << 16 | (colour.1 as u32) << 8 | (colour.2 as u32))
 }
 
+/// Converts a BGR555 colour to the packed 0x00RRGGBB form used for frames
+pub fn colour_rgb(colour: u16) -> u32 {
+    colour_pack(colour16_rgb(colour))
+}
+
 fn in_win_vert(winv: u16, row: u32) -> bool {
     let y1 = (winv >> 8) as u32;
     let y2 = (winv & 0xff) as u32;
@@ -164,8 +277,21 @@ mod test {
     use super::*;
     #[test]
     fn test_colourconvert() {
-        assert_eq!((0xf8, 0, 0), colour16_rgb(0x1f));
-        assert_eq!((0, 0xf8, 0), colour16_rgb(0x3e0));
-        assert_eq!((0, 0, 0xf8), colour16_rgb(0x7c00));
+        assert_eq!((0xff, 0, 0), colour16_rgb(0x1f));
+        assert_eq!((0, 0xff, 0), colour16_rgb(0x3e0));
+        assert_eq!((0, 0, 0xff), colour16_rgb(0x7c00));
+        assert_eq!((0x84, 0x08, 0), colour16_rgb(0x0030));
+    }
+
+    #[test]
+    fn test_hidden_dspcnt() {
+        let mut state = RenderState::default();
+        assert_eq!(0, state.hidden_dspcnt());
+        for l in &[Layer::Bg1, Layer::Obj, Layer::ObjWindow, Layer::Win1] {
+            state.hidden_layers |= l.mask();
+        }
+        assert_eq!((1 << 9) | (1 << 14) | (1 << 15), state.hidden_dspcnt());
+        assert_eq!(Some(Layer::ObjWindow), Layer::from_name("objwin"));
+        assert_eq!(None, Layer::from_name("bg4"));
     }
 }
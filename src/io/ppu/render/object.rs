@@ -1,3 +1,5 @@
+use std::cmp::min;
+
 use super::*;
 
 use mmu::gba::Gba as GbaMmu;
@@ -5,13 +7,24 @@ use mmu::Mmu;
 
 pub(super) const SEMITRANS: u32 = 1 << 16;
 
+// Rendering cycles available for objects on each line, depending on whether
+// the H-Blank interval is left free for OAM access
+const OBJ_CYCLES: u32 = 1210;
+const OBJ_CYCLES_HBLANK_FREE: u32 = 954;
+
 trait Dspcnt {
+    fn hblank_free(self) -> bool;
     fn layout2d(self) -> bool;
     fn objwin_enable(self) -> bool;
     fn mode(self) -> u32;
 }
 
 impl Dspcnt for u16 {
+    #[inline]
+    fn hblank_free(self) -> bool {
+        bit(self as u32, 5) == 1
+    }
+
     #[inline]
     fn layout2d(self) -> bool {
         bit(self as u32, 6) == 0
@@ -28,32 +41,48 @@ impl Dspcnt for u16 {
     }
 }
 
-pub(super) fn render_obj_line(
-    line: &mut LineBuf,
-    owin: &mut LineBuf,
-    row: u32,
-    mmu: &GbaMmu,
-    dspcnt: u16,
-) {
-    for x in 0..240 {
-        line[x as usize] = TRANSPARENT;
-        owin[x as usize] = 0;
-    }
+/// How an object is drawn, from attribute 0
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum ObjMode {
+    Normal,
+    SemiTransparent,
+    Window,
+    Prohibited,
+}
 
-    let objwin = dspcnt.objwin_enable();
+/// An object's attributes, decoded from its three OAM halfwords
+#[derive(Copy, Clone, Debug)]
+pub struct ObjAttrs {
+    pub index: u32,
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub shape: u32,
+    pub size: u32,
+    pub mode: ObjMode,
+    /// The rotation/scaling parameter group, if the object is affine
+    pub affine: Option<u32>,
+    pub double_size: bool,
+    pub disabled: bool,
+    pub mosaic: bool,
+    pub hflip: bool,
+    pub vflip: bool,
+    pub bpp8: bool,
+    pub priority: u32,
+    pub palette: u32,
+    pub tile: u32,
+}
 
-    // 128 objects
-    for o in 0..128 {
+impl ObjAttrs {
+    /// Decodes object `o` (0-127) from OAM
+    pub fn decode(mmu: &GbaMmu, o: u32) -> Self {
         let a0 = mmu.oam.load16(o * 8 + 0).get() as u32;
-        if extract(a0, 8, 2) == 2 || extract(a0, 10, 2) == 3 {
-            // disabled
-            continue;
-        }
-
         let a1 = mmu.oam.load16(o * 8 + 2).get() as u32;
         let a2 = mmu.oam.load16(o * 8 + 4).get() as u32;
 
-        let (xsize, ysize): (u32, u32) = match (extract(a0, 14, 2), extract(a1, 14, 2)) {
+        let (shape, size) = (extract(a0, 14, 2), extract(a1, 14, 2));
+        let (width, height): (u32, u32) = match (shape, size) {
             (0, x) if x < 4 => (8 * (1 << x), 8 * (1 << x)),
             (1, 0) => (16, 8),
             (1, 1) => (32, 8),
@@ -71,32 +100,160 @@ pub(super) fn render_obj_line(
             (_, _) => unreachable!(),
         };
 
-        let y0 = extract(a0, 0, 8);
+        let affine = bit(a0, 8) == 1;
+        ObjAttrs {
+            index: o,
+            x: extract(a1, 0, 9),
+            y: extract(a0, 0, 8),
+            width: width,
+            height: height,
+            shape: shape,
+            size: size,
+            mode: match extract(a0, 10, 2) {
+                0 => ObjMode::Normal,
+                1 => ObjMode::SemiTransparent,
+                2 => ObjMode::Window,
+                3 => ObjMode::Prohibited,
+                _ => unreachable!(),
+            },
+            affine: if affine {
+                Some(extract(a1, 9, 5))
+            } else {
+                None
+            },
+            double_size: affine && bit(a0, 9) == 1,
+            disabled: !affine && bit(a0, 9) == 1,
+            mosaic: bit(a0, 12) == 1,
+            hflip: !affine && bit(a1, 12) == 1,
+            vflip: !affine && bit(a1, 13) == 1,
+            bpp8: bit(a0, 13) == 1,
+            priority: extract(a2, 10, 2),
+            palette: extract(a2, 12, 4),
+            tile: extract(a2, 0, 10),
+        }
+    }
+
+    /// Whether the object gets drawn at all
+    #[inline]
+    pub fn visible(&self) -> bool {
+        !self.disabled && self.mode != ObjMode::Prohibited
+    }
+
+    /// The size of the area the object covers on screen, which is doubled
+    /// for double size affine objects
+    #[inline]
+    pub fn area(&self) -> (u32, u32) {
+        if self.double_size {
+            (self.width * 2, self.height * 2)
+        } else {
+            (self.width, self.height)
+        }
+    }
 
-        let (xarea, yarea) = if extract(a0, 8, 2) == 3 {
-            (xsize * 2, ysize * 2)
+    /// The palette index of the texel at (tx, ty) in the object, 0 is
+    /// transparent
+    #[inline]
+    pub fn texel_index(&self, mmu: &GbaMmu, dspcnt: u16, tx: u32, ty: u32) -> u8 {
+        let palette_mode = self.bpp8 as u32;
+        // if 2d layout mode is enabled, bottom bit of tile is ignored
+        let row_inc = if dspcnt.layout2d() {
+            32
         } else {
-            (xsize, ysize)
+            // divide by 8 if 16/16, otherwise divide by 4
+            self.width / (4 * (2 - palette_mode))
         };
+        // 1 if 16/16, 2 if 256/1
+        let col_inc = palette_mode + 1;
+
+        let t = (self.tile + (tx / 8) * col_inc + (ty / 8) * row_inc) & 1023;
+        let idx = (tx % 8) + (ty % 8) * 8;
+
+        let tile_addr = 0x10000 + t * 32;
+        if palette_mode == 0 {
+            let v = mmu.vram.load8(tile_addr + idx / 2).get();
+            (v >> ((idx & 1) * 4)) & 0xf
+        } else {
+            mmu.vram.load8(tile_addr + idx).get()
+        }
+    }
+
+    /// The colour of a palette index from `texel_index`
+    #[inline]
+    pub fn colour(&self, mmu: &GbaMmu, index: u8) -> u16 {
+        let palette = if self.bpp8 { 0 } else { self.palette };
+        mmu.pram
+            .load16(0x200 + palette * 32 + (index as u32) * 2)
+            .get()
+    }
+}
+
+/// The raw (8.8 fixed point) PA, PB, PC, PD of an object parameter group
+pub fn obj_affine_params(mmu: &GbaMmu, idx: u32) -> [u16; 4] {
+    [
+        mmu.oam.load16(0x06 + 0x20 * idx).get(),
+        mmu.oam.load16(0x0E + 0x20 * idx).get(),
+        mmu.oam.load16(0x16 + 0x20 * idx).get(),
+        mmu.oam.load16(0x1E + 0x20 * idx).get(),
+    ]
+}
+
+pub(super) fn render_obj_line(
+    line: &mut LineBuf,
+    owin: &mut LineBuf,
+    row: u32,
+    mmu: &GbaMmu,
+    dspcnt: u16,
+    start: u32,
+    end: u32,
+) {
+    for x in start..end {
+        line[x as usize] = TRANSPARENT;
+        owin[x as usize] = 0;
+    }
+
+    let objwin = dspcnt.objwin_enable();
+
+    let mut cycles = if dspcnt.hblank_free() {
+        OBJ_CYCLES_HBLANK_FREE
+    } else {
+        OBJ_CYCLES
+    };
+
+    // 128 objects
+    for o in 0..128 {
+        if cycles == 0 {
+            // out of rendering time, the rest of the objects drop out
+            break;
+        }
+
+        let obj = ObjAttrs::decode(mmu, o);
+        if !obj.visible() {
+            continue;
+        }
+
+        let (xsize, ysize) = (obj.width, obj.height);
+        let (xarea, yarea) = obj.area();
 
         // TODO: Implement objects rendering at the top and not the bottom if it would
         // wrap around
-        let iy = (256 + row - y0) % 256;
+        let iy = (256 + row - obj.y) % 256;
         if iy >= yarea {
             continue;
         }
 
-        let (mut xval, mut yval, dx, dy) = if bit(a0, 8) == 1 {
+        // Objects on this line use up rendering cycles in OAM order, even if
+        // they're horizontally offscreen.  An object that doesn't fit in the
+        // remaining cycles is only partially drawn.
+        let affine = obj.affine.is_some();
+        let xdrawn = obj_visible_width(cycles, xarea, affine);
+        cycles = cycles.saturating_sub(obj_cycles(xarea, affine));
+
+        let (mut xval, mut yval, dx, dy) = if let Some(param_idx) = obj.affine {
             // instead of based around top-left, it is based around centre
             // q: screen coords, p: texture coords
             // p = Q * (q - q0) + p0
-            let param_idx = extract(a1, 9, 5);
-            let rparams = RotScaleParams::new(
-                mmu.oam.load16(0x06 + 0x20 * param_idx).get(),
-                mmu.oam.load16(0x0E + 0x20 * param_idx).get(),
-                mmu.oam.load16(0x16 + 0x20 * param_idx).get(),
-                mmu.oam.load16(0x1E + 0x20 * param_idx).get(),
-            );
+            let p = obj_affine_params(mmu, param_idx);
+            let rparams = RotScaleParams::new(p[0], p[1], p[2], p[3]);
 
             // p0_z = (zsize << 8) / 2
             let xval = (xsize << 7)
@@ -110,50 +267,39 @@ pub(super) fn render_obj_line(
             (xval, yval, rparams.a, rparams.c)
         } else {
             // create relevant values for horizontal/vertical flip
-            let (xval, dx) = if bit(a1, 12) == 0 {
+            let (xval, dx) = if !obj.hflip {
                 (0, 0x0100)
             } else {
                 ((xsize - 1) << 8, 0xffffff00)
             };
-            let yval = if bit(a1, 13) == 0 { iy } else { ysize - 1 - iy } << 8;
+            let yval = if !obj.vflip { iy } else { ysize - 1 - iy } << 8;
             (xval, yval, dx, 0)
         };
 
-        let palette_mode = bit(a0, 13);
-        let is_win = extract(a0, 10, 2) == 2;
+        let is_win = obj.mode == ObjMode::Window;
 
-        // if 2d layout mode is enabled, bottom bit of tile is ignored
-        let (tbase, row_inc) = if dspcnt.layout2d() {
-            (extract(a2, 0, 10), 32)
-        } else {
-            // divide by 8 if 16/16, otherwise divide by 4
-            (extract(a2, 0, 10), xsize / (4 * (2 - palette_mode)))
-        };
-        if dspcnt.mode() > 2 && tbase < 512 {
+        if dspcnt.mode() > 2 && obj.tile < 512 {
             continue;
         };
 
-        let prio = (extract(a2, 10, 2) << 28)
+        let prio = (obj.priority << 28)
             | (o << 20)
-            | if extract(a0, 10, 2) == 1 {
+            | if obj.mode == ObjMode::SemiTransparent {
                 SEMITRANS
             } else {
                 0
             };
 
-        let palette = (1 - palette_mode) * extract(a2, 12, 4);
-        // 1 if 16/16, 2 if 256/1
-        let col_inc = palette_mode + 1;
-
-        let x0 = extract(a1, 0, 9);
-        for x in x0..x0 + xarea {
+        let x0 = obj.x;
+        for x in x0..x0 + xdrawn {
             let sx = x % 512;
 
             let (tx, ty) = (xval >> 8, yval >> 8);
             xval = xval.wrapping_add(dx);
             yval = yval.wrapping_add(dy);
 
-            if sx >= 240
+            if sx < start
+                || sx >= end
                 || (!is_win && line[sx as usize] < prio)
                 || (is_win && owin[sx as usize] != 0)
             {
@@ -163,17 +309,7 @@ pub(super) fn render_obj_line(
                 continue;
             }
 
-            let t = (tbase + (tx / 8) * col_inc + (ty / 8) * row_inc) & 1023;
-            let idx = (tx % 8) + (ty % 8) * 8;
-
-            let tile_addr = 0x10000 + t * 32;
-            let palette_colour = if palette_mode == 0 {
-                let v = mmu.vram.load8(tile_addr + idx / 2).get();
-                (v >> ((idx & 1) * 4)) & 0xf
-            } else {
-                mmu.vram.load8(tile_addr + idx).get()
-            };
-
+            let palette_colour = obj.texel_index(mmu, dspcnt, tx, ty);
             if palette_colour == 0 {
                 continue;
             }
@@ -184,12 +320,52 @@ pub(super) fn render_obj_line(
                     owin[sx as usize] = 1;
                 }
             } else {
-                let colour = mmu
-                    .pram
-                    .load16(0x200 + palette * 32 + (palette_colour as u32) * 2)
-                    .get();
+                let colour = obj.colour(mmu, palette_colour);
                 line[sx as usize] = (colour as u32) | prio;
             }
         }
     }
 }
+
+/// Number of rendering cycles an object of the given on-screen width takes
+/// per line
+fn obj_cycles(width: u32, affine: bool) -> u32 {
+    if affine {
+        10 + width * 2
+    } else {
+        width
+    }
+}
+
+/// Number of pixels of an object that can be drawn with the remaining cycles
+fn obj_visible_width(cycles: u32, width: u32, affine: bool) -> u32 {
+    let max = if affine {
+        cycles.saturating_sub(10) / 2
+    } else {
+        cycles
+    };
+    min(width, max)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_obj_cycles() {
+        assert_eq!(64, obj_cycles(64, false));
+        assert_eq!(26, obj_cycles(8, true));
+        assert_eq!(266, obj_cycles(128, true));
+    }
+
+    #[test]
+    fn test_obj_budget() {
+        // 18 64-wide objects fit in a full line, with 58 cycles left over
+        let left = OBJ_CYCLES - 18 * obj_cycles(64, false);
+        assert_eq!(58, left);
+        assert_eq!(58, obj_visible_width(left, 64, false));
+        assert_eq!(24, obj_visible_width(left, 64, true));
+        assert_eq!(0, obj_visible_width(8, 32, true));
+        assert_eq!(32, obj_visible_width(OBJ_CYCLES_HBLANK_FREE, 32, true));
+    }
+}
@@ -0,0 +1,439 @@
+//! The serial port, for link cables.  Transfers between linked machines are
+//! settled once a frame: each end reports what it did over the frame and
+//! they all work out the same result from everyone's reports.  Unlinked,
+//! transfers finish on their own as if nothing was plugged in.
+
+use std::collections::VecDeque;
+
+use system::CYCLES_PER_SEC;
+
+use super::IoReg;
+
+const SIODATA32_L: u32 = 0x120;
+const SIODATA32_H: u32 = 0x122;
+const SIOMULTI0: u32 = 0x120;
+const SIOCNT: u32 = 0x128;
+const SIODATA8: u32 = 0x12a;
+const RCNT: u32 = 0x134;
+
+const START: u16 = 1 << 7;
+const IRQ_ENABLE: u16 = 1 << 14;
+const SERIAL_IRQ: u8 = 7;
+
+// Multiplayer units are a start bit, 16 data bits and a stop bit
+const UNIT_BITS: u64 = 18;
+const BAUD_RATES: [u64; 4] = [9600, 38400, 57600, 115200];
+
+/// Bytes each way the UART holds before dropping more, well over a frame's
+/// worth at the fastest baud rate
+pub const UART_QUEUE_LEN: usize = 256;
+
+/// What the serial port's set up for, from RCNT and SIOCNT
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum SioMode {
+    Normal8,
+    Normal32,
+    Multiplayer,
+    Uart,
+    /// General purpose or JOY Bus, which aren't linked
+    Off,
+}
+
+impl SioMode {
+    fn new(rcnt: u16, siocnt: u16) -> Self {
+        if rcnt & 0x8000 != 0 {
+            return SioMode::Off;
+        }
+        match siocnt >> 12 & 3 {
+            0 => SioMode::Normal8,
+            1 => SioMode::Normal32,
+            2 => SioMode::Multiplayer,
+            _ => SioMode::Uart,
+        }
+    }
+}
+
+/// What one end of a link did with its serial port over a frame
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct LinkFrame {
+    pub mode: SioMode,
+    /// The start bit is set, waiting on a transfer
+    pub ready: bool,
+    /// Started a transfer it clocks: as the master in normal mode or the
+    /// parent in multiplayer
+    pub started: bool,
+    /// SIODATA8 or SIODATA32 in normal mode, SIOMLT_SEND in multiplayer
+    pub data: u32,
+    /// Bytes sent in UART mode
+    pub uart: Vec<u8>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct Sio {
+    /// Cycles until an unlinked transfer finishes, 0 if none is going
+    countdown: u64,
+    /// A transfer started this frame, settled at the end of it
+    started: bool,
+    uart_out: Vec<u8>,
+    uart_in: VecDeque<u8>,
+    /// This end's player number and how many are linked, None unlinked
+    #[serde(skip)]
+    player: Option<(u8, u8)>,
+}
+
+impl Sio {
+    /// Takes on a save state's transfers, staying linked as it is
+    pub fn restore(&mut self, saved: Sio) {
+        let player = self.player;
+        *self = saved;
+        self.player = player;
+    }
+
+    /// Bytes waiting in the UART queues
+    pub fn queued(&self) -> usize {
+        self.uart_out.len() + self.uart_in.len()
+    }
+}
+
+impl IoReg {
+    /// Links the serial port as the given player, or unlinks it
+    pub fn set_link_player(&mut self, player: Option<(u8, u8)>) {
+        self.sio.player = player;
+        self.sio.started = false;
+    }
+
+    fn sio_mode(&self) -> SioMode {
+        SioMode::new(self.get_priv(RCNT), self.get_priv(SIOCNT))
+    }
+
+    // The parent is player 0, which is who an unlinked machine plays as
+    fn sio_parent(&self) -> bool {
+        self.sio.player.map_or(true, |(id, _)| id == 0)
+    }
+
+    /// SIOCNT with its status bits filled in
+    pub(super) fn sio_control(&self) -> u16 {
+        let cnt = self.get_priv(SIOCNT);
+        match self.sio_mode() {
+            SioMode::Multiplayer => {
+                let (id, players) = self.sio.player.unwrap_or((0, 1));
+                let child = !self.sio_parent() as u16;
+                let connected = (players > 1) as u16;
+                cnt & !0x3c | child << 2 | connected << 3 | (id as u16 & 3) << 4
+            }
+            // Sending never backs up, so only the receive side's shown
+            SioMode::Uart => {
+                let empty = self.sio.uart_in.is_empty() as u16;
+                cnt & !0x30 | empty << 5
+            }
+            _ => cnt,
+        }
+    }
+
+    /// SIODATA8, the next byte received in UART mode
+    pub(super) fn sio_data8(&self) -> u16 {
+        match self.sio_mode() {
+            SioMode::Uart => self.sio.uart_in.front().map_or(0, |&byte| byte as u16),
+            _ => self.get_priv(SIODATA8),
+        }
+    }
+
+    /// The CPU reading SIODATA8's low byte takes it off what's been received
+    /// in UART mode
+    pub(super) fn sio_read(&mut self, addr: u32, len: u32) {
+        if addr <= SIODATA8 && SIODATA8 < addr + len && self.sio_mode() == SioMode::Uart {
+            self.sio.uart_in.pop_front();
+        }
+    }
+
+    pub(super) fn sio_written(&mut self, addr: u32, old: u16, new: u16) {
+        match (addr, self.sio_mode()) {
+            (SIOCNT, SioMode::Normal8) | (SIOCNT, SioMode::Normal32) => {
+                // Only the end with the internal clock drives the transfer
+                if old & START == 0 && new & START != 0 && new & 1 != 0 {
+                    let bits = if self.sio_mode() == SioMode::Normal8 {
+                        8
+                    } else {
+                        32
+                    };
+                    let bit_cycles = if new & 2 != 0 { 8 } else { 64 };
+                    self.start_transfer(bits * bit_cycles);
+                }
+            }
+            (SIOCNT, SioMode::Multiplayer) => {
+                if !self.sio_parent() {
+                    // Children can't start transfers, the bit just shows
+                    // when one's going
+                    self.set_priv(SIOCNT, new & !START | old & START);
+                } else if old & START == 0 && new & START != 0 {
+                    let baud = BAUD_RATES[(new & 3) as usize];
+                    self.start_transfer(CYCLES_PER_SEC / baud * UNIT_BITS);
+                }
+            }
+            (SIODATA8, SioMode::Uart) => {
+                let cnt = self.get_priv(SIOCNT);
+                let room = self.sio.uart_out.len() < UART_QUEUE_LEN;
+                if cnt & (1 << 10) != 0 && self.sio.player.is_some() && room {
+                    // 7 bit data unless bit 7 asks for 8
+                    let mask = if cnt & (1 << 7) != 0 { 0xff } else { 0x7f };
+                    self.sio.uart_out.push(new as u8 & mask);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn start_transfer(&mut self, cycles: u64) {
+        if self.sio.player.is_some() {
+            self.sio.started = true;
+        } else {
+            self.sio.countdown = cycles;
+        }
+    }
+
+    /// Finishes unlinked transfers with nothing on the other end
+    pub(super) fn sio_cycle(&mut self) {
+        if self.sio.countdown == 0 {
+            return;
+        }
+        self.sio.countdown -= 1;
+        if self.sio.countdown == 0 {
+            match self.sio_mode() {
+                SioMode::Normal8 | SioMode::Normal32 => self.normal_done(0xffff_ffff),
+                SioMode::Multiplayer => {
+                    let send = self.get_priv(SIODATA8);
+                    self.multi_done([send, 0xffff, 0xffff, 0xffff]);
+                }
+                _ => {}
+            }
+        }
+    }
+
+    /// What this end did over the frame, clearing it for the next
+    pub fn link_frame(&mut self) -> LinkFrame {
+        let mode = self.sio_mode();
+        let data = match mode {
+            SioMode::Normal32 => {
+                self.get_priv(SIODATA32_L) as u32 | (self.get_priv(SIODATA32_H) as u32) << 16
+            }
+            _ => self.get_priv(SIODATA8) as u32,
+        };
+        LinkFrame {
+            mode: mode,
+            ready: self.get_priv(SIOCNT) & START != 0,
+            started: self.sio.started,
+            data: data,
+            uart: self.sio.uart_out.split_off(0),
+        }
+    }
+
+    /// Settles the frame's transfers from every end's report, indexed by
+    /// player, this end's included
+    pub fn link_settle(&mut self, frames: &[LinkFrame]) {
+        let me = match self.sio.player {
+            Some((id, _)) => id as usize,
+            None => return,
+        };
+        let mode = self.sio_mode();
+        match mode {
+            SioMode::Multiplayer => {
+                // The parent's transfer reaches everyone in multiplayer mode
+                let parent = &frames[0];
+                if parent.mode == SioMode::Multiplayer && parent.started {
+                    let mut values = [0xffff; 4];
+                    for (value, frame) in values.iter_mut().zip(frames) {
+                        if frame.mode == SioMode::Multiplayer {
+                            *value = frame.data as u16;
+                        }
+                    }
+                    self.multi_done(values);
+                }
+            }
+            SioMode::Normal8 | SioMode::Normal32 => {
+                // The master swaps data with whoever's waiting on the other
+                // end, or gets all ones if nobody is
+                let waiting =
+                    |frame: &LinkFrame| frame.mode == mode && frame.ready && !frame.started;
+                if self.sio.started {
+                    let data = frames
+                        .iter()
+                        .enumerate()
+                        .find(|&(i, frame)| i != me && waiting(frame))
+                        .map_or(0xffff_ffff, |(_, frame)| frame.data);
+                    self.normal_done(data);
+                } else if waiting(&frames[me]) {
+                    let master = frames
+                        .iter()
+                        .enumerate()
+                        .find(|&(i, frame)| i != me && frame.mode == mode && frame.started);
+                    if let Some((_, frame)) = master {
+                        self.normal_done(frame.data);
+                    }
+                }
+            }
+            SioMode::Uart => {
+                let cnt = self.get_priv(SIOCNT);
+                if cnt & (1 << 11) != 0 {
+                    let mut received = false;
+                    for (i, frame) in frames.iter().enumerate() {
+                        if i != me && frame.mode == SioMode::Uart && !frame.uart.is_empty() {
+                            let room = UART_QUEUE_LEN - self.sio.uart_in.len();
+                            self.sio.uart_in.extend(frame.uart.iter().take(room));
+                            received = true;
+                        }
+                    }
+                    if received && cnt & IRQ_ENABLE != 0 {
+                        self.raise_interrupt(SERIAL_IRQ);
+                    }
+                }
+            }
+            SioMode::Off => {}
+        }
+        self.sio.started = false;
+    }
+
+    fn normal_done(&mut self, data: u32) {
+        if self.sio_mode() == SioMode::Normal8 {
+            self.set_priv(SIODATA8, data as u16 & 0xff);
+        } else {
+            self.set_priv(SIODATA32_L, data as u16);
+            self.set_priv(SIODATA32_H, (data >> 16) as u16);
+        }
+        self.transfer_done();
+    }
+
+    fn multi_done(&mut self, values: [u16; 4]) {
+        for (i, &value) in values.iter().enumerate() {
+            self.set_priv(SIOMULTI0 + 2 * i as u32, value);
+        }
+        // Clears the error bit too
+        let cnt = self.get_priv(SIOCNT);
+        self.set_priv(SIOCNT, cnt & !(1 << 6));
+        self.transfer_done();
+    }
+
+    fn transfer_done(&mut self) {
+        let cnt = self.get_priv(SIOCNT);
+        self.set_priv(SIOCNT, cnt & !START);
+        if cnt & IRQ_ENABLE != 0 {
+            self.raise_interrupt(SERIAL_IRQ);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::Mmu;
+
+    fn io() -> IoReg {
+        let mut io = IoReg::new();
+        io.set16(RCNT, 0);
+        io
+    }
+
+    fn run(io: &mut IoReg, cycles: u64) {
+        for _ in 0..cycles {
+            io.sio_cycle();
+        }
+    }
+
+    #[test]
+    fn test_unlinked() {
+        let mut io = io();
+        // 8 bit normal mode on the 2 MHz internal clock
+        io.set16(SIODATA8, 0x12);
+        io.set16(SIOCNT, IRQ_ENABLE | START | 3);
+        run(&mut io, 8 * 8 - 1);
+        assert!(io.get_priv(SIOCNT) & START != 0);
+        run(&mut io, 1);
+        assert_eq!(0, io.get_priv(SIOCNT) & START);
+        assert_eq!(0xff, io.get_priv(SIODATA8));
+        assert!(io.get_priv(0x202) & (1 << SERIAL_IRQ) != 0);
+
+        // A multiplayer parent on its own hears nobody
+        io.set16(SIOCNT, 0x2003);
+        io.set16(SIODATA8, 0x1234);
+        io.set16(SIOCNT, 0x2003 | START);
+        run(&mut io, CYCLES_PER_SEC / 115200 * UNIT_BITS);
+        assert_eq!(0x1234, io.get_priv(SIOMULTI0));
+        assert_eq!(0xffff, io.get_priv(SIOMULTI0 + 2));
+        assert_eq!(0, io.sio_control() & 0x3c);
+    }
+
+    #[test]
+    fn test_multiplayer() {
+        let mut ios: Vec<IoReg> = (0..3).map(|_| io()).collect();
+        for (i, io) in ios.iter_mut().enumerate() {
+            io.set_link_player(Some((i as u8, 3)));
+            io.set16(SIOCNT, 0x2003 | IRQ_ENABLE);
+            io.set16(SIODATA8, 0x1111 * (i as u16 + 1));
+        }
+        // Player 2's child bit, connected bit and ID
+        assert_eq!(1 << 2 | 1 << 3 | 2 << 4, ios[2].sio_control() & 0x3c);
+        // Children can't start a transfer
+        ios[1].set16(SIOCNT, 0x2003 | IRQ_ENABLE | START);
+        assert_eq!(0, ios[1].get_priv(SIOCNT) & START);
+        ios[0].set16(SIOCNT, 0x2003 | IRQ_ENABLE | START);
+
+        let frames: Vec<LinkFrame> = ios.iter_mut().map(|io| io.link_frame()).collect();
+        assert!(frames[0].started);
+        for io in &mut ios {
+            io.link_settle(&frames);
+            let values: Vec<u16> = (0..4).map(|i| io.get_priv(SIOMULTI0 + 2 * i)).collect();
+            assert_eq!(vec![0x1111, 0x2222, 0x3333, 0xffff], values);
+            assert!(io.get_priv(0x202) & (1 << SERIAL_IRQ) != 0);
+        }
+    }
+
+    #[test]
+    fn test_normal_and_uart() {
+        let mut ios: Vec<IoReg> = (0..2).map(|_| io()).collect();
+        for (i, io) in ios.iter_mut().enumerate() {
+            io.set_link_player(Some((i as u8, 2)));
+        }
+        // A 32 bit master and a slave on the external clock
+        ios[0].set16(SIODATA32_L, 0x5678);
+        ios[0].set16(SIODATA32_H, 0x1234);
+        ios[1].set16(SIODATA32_L, 0xcdef);
+        ios[1].set16(SIODATA32_H, 0x89ab);
+        ios[1].set16(SIOCNT, 0x1000 | START);
+        ios[0].set16(SIOCNT, 0x1000 | START | 1);
+        let frames: Vec<LinkFrame> = ios.iter_mut().map(|io| io.link_frame()).collect();
+        for io in &mut ios {
+            io.link_settle(&frames);
+            assert_eq!(0, io.get_priv(SIOCNT) & START);
+        }
+        assert_eq!(0x89ab, ios[0].get_priv(SIODATA32_H));
+        assert_eq!(0x5678, ios[1].get_priv(SIODATA32_L));
+
+        // 8 bit UART with sending and receiving on
+        for io in &mut ios {
+            io.set16(SIOCNT, 0x3000 | 1 << 10 | 1 << 11 | 1 << 7);
+        }
+        ios[0].set16(SIODATA8, 0x41);
+        ios[0].set16(SIODATA8, 0x42);
+        assert!(ios[1].sio_control() & (1 << 5) != 0);
+        let frames: Vec<LinkFrame> = ios.iter_mut().map(|io| io.link_frame()).collect();
+        for io in &mut ios {
+            io.link_settle(&frames);
+        }
+        assert_eq!(0, ios[1].sio_control() & (1 << 5));
+        // Only the CPU reading the low byte takes one off
+        assert_eq!(0x41, ios[1].sio_data8());
+        assert_eq!(0x41, ios[1].load8(SIODATA8).get());
+        ios[1].cpu_read(SIODATA8 + 1, 1);
+        assert_eq!(0x41, ios[1].sio_data8());
+        ios[1].cpu_read(SIOCNT, 4);
+        assert_eq!(0x42, ios[1].sio_data8());
+        ios[1].cpu_read(SIODATA8, 1);
+        assert!(ios[1].sio_control() & (1 << 5) != 0);
+
+        // Sending stops when the queue's full
+        for _ in 0..UART_QUEUE_LEN + 10 {
+            ios[0].set16(SIODATA8, 0x43);
+        }
+        assert_eq!(UART_QUEUE_LEN, ios[0].sio.queued());
+    }
+}
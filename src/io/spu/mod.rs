@@ -1,7 +1,9 @@
+use std::collections::VecDeque;
 use std::default::Default;
+use std::io;
+use std::path::{Path, PathBuf};
 use std::sync::{Arc, Mutex};
 
-use arraydeque::{ArrayDeque, Wrapping};
 use sdl2::audio::{AudioCallback, AudioSpecDesired};
 
 use mmu::gba::Gba as GbaMmu;
@@ -9,41 +11,313 @@ use shared::Shared;
 
 use super::IoReg;
 
-// Sound runs at 32768 Hz
-// 256 samples at a time leads to audio latency of ~8ms, which is
-// probably ok.
+use self::record::Recording;
+use self::resample::{Lowpass, Resampler};
+pub use self::sound::{Sound, CHANNELS, CHANNEL_NAMES, FIFO_LEN};
 
-pub const SAMPLES: usize = 256;
+mod record;
+mod resample;
+mod sound;
+
+const SOUNDBIAS: u32 = 0x88;
+
+// The GBA mixes sound at 32768 Hz, or up to 8 times that depending on the
+// resolution set in SOUNDBIAS
 pub const FREQ: i32 = 32768;
 
-// do SAMPLES * 4 to give extra buffer room
-type SoundDeque = ArrayDeque<[(f32, f32); SAMPLES * 16], Wrapping>;
-pub struct SoundBuf(Arc<Mutex<SoundDeque>>);
+/// How sound is played on the host
+#[derive(Clone, Debug)]
+pub struct AudioOptions {
+    /// Sample rate to ask the audio device for, in Hz.  The device may end
+    /// up with another, which is resampled to as well.
+    pub freq: i32,
+    /// Samples the device takes at a time
+    pub samples: u16,
+    /// Sound kept queued for the device, in milliseconds
+    pub latency: u32,
+    /// Cutoff of a low-pass filter like the one on the GBA's sound output,
+    /// in Hz
+    pub lowpass: Option<f32>,
+}
+
+impl Default for AudioOptions {
+    fn default() -> Self {
+        AudioOptions {
+            freq: 48000,
+            samples: 512,
+            latency: 50,
+            lowpass: None,
+        }
+    }
+}
+
+impl AudioOptions {
+    pub fn desired_spec(&self) -> AudioSpecDesired {
+        AudioSpecDesired {
+            freq: Some(self.freq),
+            channels: Some(2),
+            samples: Some(self.samples),
+        }
+    }
+}
+
+/// Samples of each channel kept for oscilloscopes, a frame's worth at
+/// 32768 Hz
+pub const SCOPE_LEN: usize = 548;
+
+/// Samples are scaled by this on the way out, to leave headroom
+pub const OUTPUT_GAIN: f32 = 0.5;
+
+// The most the output rate is nudged by to keep the buffer at the latency
+// asked for.  Half a percent is too little to hear as a change in pitch.
+const MAX_RATE_ADJUST: f32 = 0.005;
+
+pub struct SoundBuf(Arc<Mutex<VecDeque<(f32, f32)>>>);
 
-pub struct Spu<'a> {
-    io: Shared<IoReg<'a>>,
+pub struct Spu {
+    io: Shared<IoReg>,
 
     buf: SoundBuf,
 
-    idx: i32,
+    muted: bool,
+    resampler: Resampler,
+    lowpass: Option<Lowpass>,
+    // Output samples per emulated one for the emulation speed
+    step: f32,
+    device_freq: i32,
+    // Samples to keep queued, the oldest are dropped past twice this
+    target: usize,
+    recording: Option<Recording>,
+    // Set once samples are taken out each frame instead of played from the
+    // callback, leaving no device clock to keep pace with
+    taken: bool,
+    // Bit set for each channel that's heard
+    channel_mask: u8,
+    // Ring of each channel's level, sampled along with recordings
+    scope: Vec<[f32; CHANNELS]>,
+    scope_pos: usize,
 }
 
-impl<'a> Spu<'a> {
-    pub fn new(io: Shared<IoReg<'a>>) -> Self {
+impl Spu {
+    pub fn new(io: Shared<IoReg>) -> Self {
         Self {
             io: io,
             buf: Default::default(),
-            idx: 0,
+            muted: false,
+            resampler: Resampler::new(),
+            lowpass: None,
+            step: 1.0,
+            device_freq: FREQ,
+            target: 4096,
+            recording: None,
+            taken: false,
+            channel_mask: (1 << CHANNELS) - 1,
+            scope: vec![[0.0; CHANNELS]; SCOPE_LEN],
+            scope_pos: 0,
         }
     }
 
     pub fn cycle(&mut self) {
-        if self.idx == 0 {
-            self.buf.0.lock().unwrap().push_back((1.0, 1.0));
-        } else if self.idx == 512 {
-            self.buf.0.lock().unwrap().push_back((-1.0, -1.0));
+        let phase = self.io.sound.next_phase();
+        // Even the highest resolution only mixes every 64 cycles
+        if phase % 64 == 0 {
+            let resolution = (self.io.get_priv(SOUNDBIAS) >> 14) & 3;
+            let period = 512 >> resolution;
+            if phase % period == 0 {
+                self.mix(period as u32, FREQ << resolution, phase == 0);
+            }
         }
-        self.idx = (self.idx + 1) % 1024;
+    }
+
+    // Mixes the channels the way the hardware does, adding them to the bias
+    // level and clipping to 10 bits.  `lowest` is set for the mixes the
+    // lowest resolution would make.
+    fn mix(&mut self, cycles: u32, freq: i32, lowest: bool) {
+        let levels = {
+            let io = &mut *self.io;
+            io.sound.advance(cycles, &mut io.reg);
+            io.sound.output(&io.reg)
+        };
+        let bias = (self.io.get_priv(SOUNDBIAS) & 0x3fe) as i32;
+        let clip = |level: i32| ((level + bias).max(0).min(0x3ff) - bias) as f32 / 512.0;
+        let mask = self.channel_mask;
+        let heard = levels
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| mask & (1 << i) != 0);
+        let (left, right) = heard.fold((0, 0), |(l, r), (_, level)| (l + level.0, r + level.1));
+        let (left, right) = (clip(left), clip(right));
+
+        // Recorded at the lowest resolution, so it's the same rate throughout
+        if lowest {
+            self.scope[self.scope_pos] = self.io.sound.levels(&self.io.reg);
+            self.scope_pos = (self.scope_pos + 1) % SCOPE_LEN;
+        }
+        if lowest && self.recording.is_some() {
+            let mut channels = [(0.0, 0.0); CHANNELS];
+            for (c, &(l, r)) in channels.iter_mut().zip(levels.iter()) {
+                *c = (l as f32 / 512.0, r as f32 / 512.0);
+            }
+            let res = self
+                .recording
+                .as_mut()
+                .unwrap()
+                .write((left, right), &channels);
+            if let Err(err) = res {
+                error!("Failed to record audio: {}", err);
+                self.stop_recording();
+            }
+        }
+
+        if self.muted {
+            self.push((0.0, 0.0), freq);
+        } else {
+            self.push((left, right), freq);
+        }
+    }
+
+    // Resamples from the rate the sample was mixed at to the device's rate,
+    // stretched so sound comes out as fast as it's played.  The rate is
+    // nudged up when less than the target is queued and down when more, so
+    // the emulation and the audio device's clock never drift apart enough to
+    // run out or overflow.
+    fn push(&mut self, sample: (f32, f32), freq: i32) {
+        let mut buf = self.buf.0.lock().unwrap();
+        let fill = buf.len() as f32 / self.target as f32;
+        let adjust = if self.taken {
+            1.0
+        } else {
+            1.0 + MAX_RATE_ADJUST * (1.0 - fill).max(-1.0)
+        };
+        let ratio = self.step * self.device_freq as f32 / freq as f32 * adjust;
+
+        let capacity = if self.taken {
+            usize::max_value()
+        } else {
+            self.target * 2
+        };
+        let lowpass = &mut self.lowpass;
+        self.resampler.push(sample, ratio, |out| {
+            let out = match *lowpass {
+                Some(ref mut lowpass) => lowpass.filter(out),
+                None => out,
+            };
+            if buf.len() >= capacity {
+                buf.pop_front();
+            }
+            buf.push_back(out);
+        });
+    }
+
+    /// Matches the sound to the emulation running at `speed` times the
+    /// normal rate, by squeezing samples together when fast and spreading
+    /// them out when slow.  A speed of 0 stops the sound, for pausing or
+    /// running unlimited.
+    pub fn set_speed(&mut self, speed: f32) {
+        let step = if speed > 0.0 { 1.0 / speed } else { 0.0 };
+        if step != self.step {
+            // Whatever's queued was made for the old speed
+            self.buf.0.lock().unwrap().clear();
+            self.resampler.reset();
+            self.step = step;
+        }
+    }
+
+    /// Sets up the output for the sample rate the audio device ended up
+    /// with
+    pub fn set_device(&mut self, freq: i32, options: &AudioOptions) {
+        self.device_freq = freq;
+        self.lowpass = options.lowpass.map(|cutoff| Lowpass::new(cutoff, freq));
+        // Less than two of the device's buffers queued would run out
+        self.target =
+            (freq as usize * options.latency as usize / 1000).max(options.samples as usize * 2);
+    }
+
+    /// Whether sound is being made to keep the audio device busy, so its
+    /// clock can pace the emulation
+    pub fn playing(&self) -> bool {
+        self.step > 0.0
+    }
+
+    /// Whether more than the latency asked for is waiting to be played
+    pub fn backed_up(&self) -> bool {
+        self.buf.0.lock().unwrap().len() > self.target
+    }
+
+    /// How full the queue for the audio device is, 1 being the latency asked
+    /// for
+    pub fn buffer_fill(&self) -> f32 {
+        self.buf.0.lock().unwrap().len() as f32 / self.target as f32
+    }
+
+    /// Outputs silence while muted, dropping whatever was still queued
+    pub fn set_muted(&mut self, muted: bool) {
+        if muted && !self.muted {
+            self.buf.0.lock().unwrap().clear();
+        }
+        self.muted = muted;
+    }
+
+    /// Starts writing the sound to a WAV file, and to one for each channel
+    /// if `stems` is set
+    pub fn start_recording(&mut self, path: &Path, stems: bool) -> io::Result<()> {
+        self.stop_recording();
+        self.recording = Some(Recording::create(path, stems)?);
+        Ok(())
+    }
+
+    /// Finishes the recording if there is one, returning its path
+    pub fn stop_recording(&mut self) -> Option<PathBuf> {
+        let recording = self.recording.take()?;
+        match recording.finish() {
+            Ok(path) => Some(path),
+            Err(err) => {
+                error!("Failed to finish audio recording: {}", err);
+                None
+            }
+        }
+    }
+
+    pub fn recording(&self) -> bool {
+        self.recording.is_some()
+    }
+
+    /// Leaves a channel out of what's heard and recorded.  Stems are still
+    /// recorded.
+    pub fn set_channel_muted(&mut self, ch: usize, muted: bool) {
+        if muted {
+            self.channel_mask &= !(1 << ch);
+        } else {
+            self.channel_mask |= 1 << ch;
+        }
+    }
+
+    pub fn channel_muted(&self, ch: usize) -> bool {
+        self.channel_mask & (1 << ch) == 0
+    }
+
+    /// Mutes every channel but one
+    pub fn solo(&mut self, ch: usize) {
+        self.channel_mask = 1 << ch;
+    }
+
+    /// Each channel's recent levels, oldest first
+    pub fn scope(&self) -> Vec<[f32; CHANNELS]> {
+        let (newer, older) = self.scope.split_at(self.scope_pos);
+        older.iter().chain(newer.iter()).cloned().collect()
+    }
+
+    /// Sets up for frontends that take each frame's sound with
+    /// `take_samples` rather than having it pulled through the callback.
+    /// The rate isn't nudged then, since the frontend keeps the pace.
+    pub fn set_taken(&mut self, freq: i32) {
+        self.device_freq = freq;
+        self.taken = true;
+    }
+
+    /// The samples made since the last call
+    pub fn take_samples(&mut self) -> Vec<(f32, f32)> {
+        self.buf.0.lock().unwrap().drain(..).collect()
     }
 
     pub fn get_callback(&self) -> SoundBuf {
@@ -57,21 +331,19 @@ impl AudioCallback for SoundBuf {
     fn callback(&mut self, out: &mut [f32]) {
         let mut buf = self.0.lock().unwrap();
         let mut missed = 0;
-        warn!("Sound buffer length: {}", buf.len());
         for i in 0..(out.len() / 2) {
             let (l, r) = match buf.pop_front() {
                 Some((l, r)) => (l, r),
                 None => {
-                    //warn!("Sound sample not available when queried");
                     missed += 1;
                     (0.0, 0.0)
                 }
             };
-            out[i * 2 + 0] = l * 0.5;
-            out[i * 2 + 1] = r * 0.5;
+            out[i * 2 + 0] = l * OUTPUT_GAIN;
+            out[i * 2 + 1] = r * OUTPUT_GAIN;
         }
         if missed != 0 {
-            warn!("Missed {} samples", missed);
+            debug!("Missed {} samples", missed);
         }
     }
 }
@@ -0,0 +1,79 @@
+//! Writing the sound to WAV files as it's emulated
+
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+
+use wav::WavWriter;
+
+use super::sound::{CHANNELS, CHANNEL_NAMES};
+use super::FREQ;
+
+/// The mixed output, and optionally each channel on its own.  Samples are
+/// taken every 512 cycles whatever SOUNDBIAS's resolution, so the files run
+/// at 32768 Hz in step with emulated time.
+pub struct Recording {
+    path: PathBuf,
+    mix: WavWriter<BufWriter<File>>,
+    stems: Vec<WavWriter<BufWriter<File>>>,
+}
+
+impl Recording {
+    /// Creates the file at `path`, with stems named after it like
+    /// `song.sq1.wav` if asked for
+    pub fn create(path: &Path, stems: bool) -> io::Result<Self> {
+        let mix = WavWriter::create(path, 2, FREQ as u32)?;
+        let stems = if stems {
+            CHANNEL_NAMES
+                .iter()
+                .map(|name| WavWriter::create(&stem_path(path, name), 2, FREQ as u32))
+                .collect::<io::Result<_>>()?
+        } else {
+            vec![]
+        };
+        Ok(Recording {
+            path: path.to_path_buf(),
+            mix: mix,
+            stems: stems,
+        })
+    }
+
+    pub fn write(&mut self, mix: (f32, f32), channels: &[(f32, f32); CHANNELS]) -> io::Result<()> {
+        self.mix.write_frame(&[mix.0, mix.1])?;
+        for (stem, &(l, r)) in self.stems.iter_mut().zip(channels.iter()) {
+            stem.write_frame(&[l, r])?;
+        }
+        Ok(())
+    }
+
+    /// Finishes the files, returning the path of the mixed one
+    pub fn finish(self) -> io::Result<PathBuf> {
+        self.mix.finish()?;
+        for stem in self.stems {
+            stem.finish()?;
+        }
+        Ok(self.path)
+    }
+}
+
+fn stem_path(path: &Path, name: &str) -> PathBuf {
+    let ext = match path.extension() {
+        Some(ext) => format!("{}.{}", name, ext.to_string_lossy()),
+        None => name.to_string(),
+    };
+    path.with_extension(ext)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_stem_path() {
+        assert_eq!(
+            Path::new("out/song.fifoa.wav"),
+            stem_path(Path::new("out/song.wav"), "fifoa")
+        );
+        assert_eq!(Path::new("song.sq1"), stem_path(Path::new("song"), "sq1"));
+    }
+}
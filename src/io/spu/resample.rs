@@ -0,0 +1,211 @@
+//! Converting the emulated sound to the rate the audio device plays at
+
+use std::collections::VecDeque;
+use std::f32::consts::PI;
+
+// Zero crossings of the sinc on each side of the centre.  More gives a
+// sharper cutoff for more work per sample.
+const ZERO_CROSSINGS: usize = 8;
+// Kernel table entries between each zero crossing
+const TABLE_RES: usize = 256;
+// Input samples kept each side of the one being interpolated around, which
+// limits how far the cutoff can be lowered when downsampling
+const SPAN: usize = 64;
+// Where the passband ends relative to the lower of the two Nyquist rates,
+// leaving room for the kernel's transition band
+const PASSBAND: f32 = 0.9;
+
+/// Band-limited interpolation with a Blackman-windowed sinc.  The ratio can
+/// change from one sample to the next, which is what lets the output rate be
+/// nudged to follow the audio device's clock.  When downsampling the cutoff
+/// is lowered with the ratio so what's above the new Nyquist rate is filtered
+/// out instead of aliasing.
+pub struct Resampler {
+    kernel: Vec<f32>,
+    // The last 2 * SPAN + 2 inputs, oldest first.  Outputs are interpolated
+    // between the two in the middle, so come out SPAN inputs late.
+    history: VecDeque<(f32, f32)>,
+    // Position of the next output past the first of the middle two
+    pos: f32,
+}
+
+impl Resampler {
+    pub fn new() -> Self {
+        let kernel = (0..ZERO_CROSSINGS * TABLE_RES + 2)
+            .map(|i| {
+                let x = i as f32 / TABLE_RES as f32;
+                sinc(x) * blackman(x / ZERO_CROSSINGS as f32)
+            })
+            .collect();
+        Resampler {
+            kernel: kernel,
+            history: vec![(0.0, 0.0); 2 * SPAN + 2].into_iter().collect(),
+            pos: 0.0,
+        }
+    }
+
+    /// Takes one input sample and passes on the output samples it produces,
+    /// `ratio` output samples per input on average.  A ratio of 0 drops the
+    /// sample.
+    pub fn push<F: FnMut((f32, f32))>(&mut self, sample: (f32, f32), ratio: f32, mut out: F) {
+        self.history.pop_front();
+        self.history.push_back(sample);
+        if ratio <= 0.0 {
+            return;
+        }
+
+        let min_cutoff = ZERO_CROSSINGS as f32 / SPAN as f32;
+        let cutoff = (ratio.min(1.0) * PASSBAND).max(min_cutoff);
+        while self.pos < 1.0 {
+            out(self.interpolate(self.pos, cutoff));
+            self.pos += 1.0 / ratio;
+        }
+        self.pos -= 1.0;
+    }
+
+    // The signal at `pos` inputs past the first of the middle two, low-passed
+    // to `cutoff` times the input's Nyquist rate
+    fn interpolate(&self, pos: f32, cutoff: f32) -> (f32, f32) {
+        let reach = ZERO_CROSSINGS as f32 / cutoff;
+        let centre = SPAN as f32 + pos;
+        let first = (centre - reach).ceil().max(0.0) as usize;
+        let last = ((centre + reach).floor() as usize).min(self.history.len() - 1);
+
+        let (mut l, mut r, mut total) = (0.0, 0.0, 0.0);
+        for i in first..last + 1 {
+            let w = self.kernel_at((i as f32 - centre).abs() * cutoff);
+            let (sl, sr) = self.history[i];
+            l += sl * w;
+            r += sr * w;
+            total += w;
+        }
+        // Normalising keeps a constant signal exactly constant, whatever the
+        // cutoff and position
+        if total != 0.0 {
+            (l / total, r / total)
+        } else {
+            (0.0, 0.0)
+        }
+    }
+
+    fn kernel_at(&self, x: f32) -> f32 {
+        let idx = x * TABLE_RES as f32;
+        let i = idx as usize;
+        if i + 1 >= self.kernel.len() {
+            return 0.0;
+        }
+        let frac = idx - i as f32;
+        self.kernel[i] + (self.kernel[i + 1] - self.kernel[i]) * frac
+    }
+
+    /// Starts over, for when what was queued has been thrown away
+    pub fn reset(&mut self) {
+        for s in self.history.iter_mut() {
+            *s = (0.0, 0.0);
+        }
+        self.pos = 0.0;
+    }
+}
+
+fn sinc(x: f32) -> f32 {
+    if x == 0.0 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+// The window from its centre at 0 to its edge at 1
+fn blackman(x: f32) -> f32 {
+    if x >= 1.0 {
+        0.0
+    } else {
+        0.42 + 0.5 * (PI * x).cos() + 0.08 * (2.0 * PI * x).cos()
+    }
+}
+
+/// A one-pole low-pass, like the RC filter on the GBA's sound output
+pub struct Lowpass {
+    alpha: f32,
+    state: (f32, f32),
+}
+
+impl Lowpass {
+    pub fn new(cutoff: f32, freq: i32) -> Self {
+        Lowpass {
+            alpha: 1.0 - (-2.0 * PI * cutoff / freq as f32).exp(),
+            state: (0.0, 0.0),
+        }
+    }
+
+    pub fn filter(&mut self, (l, r): (f32, f32)) -> (f32, f32) {
+        self.state.0 += (l - self.state.0) * self.alpha;
+        self.state.1 += (r - self.state.1) * self.alpha;
+        self.state
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn resample<I: Iterator<Item = f32>>(input: I, ratio: f32) -> Vec<f32> {
+        let mut resampler = Resampler::new();
+        let mut output = vec![];
+        for s in input {
+            resampler.push((s, -s), ratio, |(l, r)| {
+                assert_eq!(l, -r);
+                output.push(l)
+            });
+        }
+        output
+    }
+
+    // Peak level once the start up has passed
+    fn peak(output: &[f32]) -> f32 {
+        output[output.len() / 2..]
+            .iter()
+            .fold(0.0, |peak, s| s.abs().max(peak))
+    }
+
+    fn sine(freq: f32, rate: f32, len: usize) -> Box<dyn Iterator<Item = f32>> {
+        Box::new((0..len).map(move |i| (2.0 * PI * freq * i as f32 / rate).sin()))
+    }
+
+    #[test]
+    fn test_resample_ratio() {
+        assert_eq!(300, resample(sine(1.0, 8.0, 300), 1.0).len());
+        assert_eq!(400, resample(sine(1.0, 8.0, 300), 4.0 / 3.0).len());
+        assert_eq!(150, resample(sine(1.0, 8.0, 300), 0.5).len());
+        assert!(resample(sine(1.0, 8.0, 300), 0.0).is_empty());
+    }
+
+    #[test]
+    fn test_resample_constant() {
+        let output = resample((0..1000).map(|_| 0.5), 48000.0 / 32768.0);
+        for &s in &output[SPAN * 2..] {
+            assert!((s - 0.5).abs() < 1e-5, "{}", s);
+        }
+    }
+
+    #[test]
+    fn test_resample_band_limited() {
+        // 32768Hz down to 8000Hz: 1kHz passes, 6kHz would alias so is
+        // filtered out
+        let ratio = 8000.0 / 32768.0;
+        let low = resample(sine(1000.0, 32768.0, 8000), ratio);
+        assert!((peak(&low) - 1.0).abs() < 0.05, "{}", peak(&low));
+        let high = resample(sine(6000.0, 32768.0, 8000), ratio);
+        assert!(peak(&high) < 0.05, "{}", peak(&high));
+    }
+
+    #[test]
+    fn test_lowpass() {
+        let mut lowpass = Lowpass::new(1000.0, 48000);
+        let mut out = (0.0, 0.0);
+        for _ in 0..1000 {
+            out = lowpass.filter((1.0, -1.0));
+        }
+        assert!((out.0 - 1.0).abs() < 1e-3 && (out.1 + 1.0).abs() < 1e-3);
+    }
+}
@@ -0,0 +1,606 @@
+//! The sound hardware: the four PSG channels carried over from the Game Boy,
+//! and the two Direct Sound FIFOs of 8-bit samples fed by DMA.
+//!
+//! This is the part of the sound that's emulated state, kept with the IO
+//! registers so it goes into save states.  Channels are only stepped when a
+//! sample is mixed, by however many cycles have passed since the last one.
+
+use std::collections::VecDeque;
+
+use bit_util::{bit, extract};
+use mmu::ram::Ram;
+use mmu::Mmu;
+
+pub const CHANNELS: usize = 6;
+
+/// Short names for the channels, PSG 1-4 then Direct Sound A and B
+pub const CHANNEL_NAMES: [&str; CHANNELS] = ["sq1", "sq2", "wave", "noise", "fifoa", "fifob"];
+
+const SOUND1CNT_L: u32 = 0x60;
+const SOUND1CNT_H: u32 = 0x62;
+const SOUND1CNT_X: u32 = 0x64;
+const SOUND2CNT_L: u32 = 0x68;
+const SOUND2CNT_H: u32 = 0x6c;
+const SOUND3CNT_L: u32 = 0x70;
+const SOUND3CNT_H: u32 = 0x72;
+const SOUND3CNT_X: u32 = 0x74;
+const SOUND4CNT_L: u32 = 0x78;
+const SOUND4CNT_H: u32 = 0x7c;
+const SOUNDCNT_L: u32 = 0x80;
+const SOUNDCNT_H: u32 = 0x82;
+const SOUNDCNT_X: u32 = 0x84;
+const WAVE_RAM: u32 = 0x90;
+const FIFO_A: u32 = 0xa0;
+const FIFO_B: u32 = 0xa4;
+
+// The frame sequencer clocks lengths, sweeps and envelopes at 512 Hz
+const FRAME_SEQ_CYCLES: u32 = 32768;
+
+pub const FIFO_LEN: usize = 32;
+
+// Which of the 8 steps of each duty cycle are high
+const DUTY: [u8; 4] = [0b0000_0001, 0b1000_0001, 0b1000_0111, 0b0111_1110];
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Envelope {
+    volume: u8,
+    period: u8,
+    increase: bool,
+    timer: u8,
+}
+
+impl Envelope {
+    fn restart(&mut self, cnt: u16) {
+        let cnt = cnt as u32;
+        self.volume = extract(cnt, 12, 4) as u8;
+        self.increase = bit(cnt, 11) == 1;
+        self.period = extract(cnt, 8, 3) as u8;
+        self.timer = self.period;
+    }
+
+    fn clock(&mut self) {
+        if self.period == 0 {
+            return;
+        }
+        self.timer = self.timer.saturating_sub(1);
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.increase && self.volume < 15 {
+                self.volume += 1;
+            } else if !self.increase && self.volume > 0 {
+                self.volume -= 1;
+            }
+        }
+    }
+}
+
+/// Turns a channel off once its length runs out, if the length is enabled
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Length {
+    left: u32,
+}
+
+impl Length {
+    fn clock(&mut self, enabled: bool, on: &mut bool) {
+        if enabled && self.left > 0 {
+            self.left -= 1;
+            if self.left == 0 {
+                *on = false;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Square {
+    on: bool,
+    timer: i32,
+    step: u8,
+    length: Length,
+    envelope: Envelope,
+    // Channel 1's frequency sweep
+    sweep_freq: u16,
+    sweep_timer: u8,
+    sweep_on: bool,
+}
+
+impl Square {
+    fn period(freq: u16) -> i32 {
+        16 * (2048 - (freq & 0x7ff) as i32)
+    }
+
+    fn restart(&mut self, cnt_h: u16, cnt_x: u16) {
+        self.on = true;
+        if self.length.left == 0 {
+            self.length.left = 64;
+        }
+        self.timer = Square::period(cnt_x);
+        self.envelope.restart(cnt_h);
+        // No volume and nothing to raise it leaves the DAC off
+        if cnt_h & 0xf800 == 0 {
+            self.on = false;
+        }
+    }
+
+    fn advance(&mut self, cycles: i32, freq: u16) {
+        if !self.on {
+            return;
+        }
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += Square::period(freq);
+            self.step = (self.step + 1) % 8;
+        }
+    }
+
+    fn output(&self, cnt_h: u16) -> i32 {
+        if !self.on {
+            return 0;
+        }
+        let duty = DUTY[extract(cnt_h as u32, 6, 2) as usize];
+        let volume = self.envelope.volume as i32;
+        if duty & (0x80 >> self.step) != 0 {
+            volume
+        } else {
+            -volume
+        }
+    }
+
+    fn sweep_next(&self, cnt_l: u16) -> u16 {
+        let delta = self.sweep_freq >> (cnt_l & 7);
+        if bit(cnt_l as u32, 3) == 1 {
+            self.sweep_freq.wrapping_sub(delta)
+        } else {
+            self.sweep_freq + delta
+        }
+    }
+
+    fn sweep_restart(&mut self, cnt_l: u16, cnt_x: u16) {
+        let time = extract(cnt_l as u32, 4, 3) as u8;
+        let shift = cnt_l & 7;
+        self.sweep_freq = cnt_x & 0x7ff;
+        self.sweep_timer = if time == 0 { 8 } else { time };
+        self.sweep_on = time != 0 || shift != 0;
+        if shift != 0 && self.sweep_next(cnt_l) > 0x7ff {
+            self.on = false;
+        }
+    }
+
+    /// Steps the sweep, returning the new frequency if it changed
+    fn sweep_clock(&mut self, cnt_l: u16) -> Option<u16> {
+        let time = extract(cnt_l as u32, 4, 3) as u8;
+        self.sweep_timer = self.sweep_timer.saturating_sub(1);
+        if self.sweep_timer != 0 {
+            return None;
+        }
+        self.sweep_timer = if time == 0 { 8 } else { time };
+        if !self.sweep_on || time == 0 {
+            return None;
+        }
+
+        let next = self.sweep_next(cnt_l);
+        if next > 0x7ff {
+            self.on = false;
+            return None;
+        }
+        if cnt_l & 7 == 0 {
+            return None;
+        }
+        self.sweep_freq = next;
+        // The overflow check is done again with the new frequency
+        if self.sweep_next(cnt_l) > 0x7ff {
+            self.on = false;
+        }
+        Some(next)
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Wave {
+    on: bool,
+    timer: i32,
+    pos: u8,
+    length: Length,
+    // Two banks of 32 4-bit samples
+    ram: [u8; 32],
+}
+
+impl Wave {
+    fn period(freq: u16) -> i32 {
+        8 * (2048 - (freq & 0x7ff) as i32)
+    }
+
+    fn restart(&mut self, cnt_l: u16, cnt_x: u16) {
+        self.on = bit(cnt_l as u32, 7) == 1;
+        if self.length.left == 0 {
+            self.length.left = 256;
+        }
+        self.timer = Wave::period(cnt_x);
+        self.pos = 0;
+    }
+
+    fn advance(&mut self, cycles: i32, cnt_l: u16, freq: u16) {
+        if !self.on {
+            return;
+        }
+        // Both banks are played one after the other in two bank mode
+        let len = if bit(cnt_l as u32, 5) == 1 { 64 } else { 32 };
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += Wave::period(freq);
+            self.pos = (self.pos + 1) % len;
+        }
+    }
+
+    fn output(&self, cnt_l: u16, cnt_h: u16) -> i32 {
+        if !self.on || bit(cnt_l as u32, 7) == 0 {
+            return 0;
+        }
+        let bank = bit(cnt_l as u32, 6) as usize;
+        let idx = (bank * 32 + self.pos as usize) % 64;
+        let byte = self.ram[idx / 2];
+        let sample = if idx % 2 == 0 { byte >> 4 } else { byte & 0xf };
+        let sample = sample as i32 * 2 - 15;
+
+        let cnt_h = cnt_h as u32;
+        if bit(cnt_h, 15) == 1 {
+            return sample * 3 / 4;
+        }
+        match extract(cnt_h, 13, 2) {
+            0 => 0,
+            1 => sample,
+            2 => sample / 2,
+            _ => sample / 4,
+        }
+    }
+
+    /// CPU writes go to the bank that isn't being played
+    fn write_ram(&mut self, offset: u32, val: u16, cnt_l: u16) {
+        let bank = 1 - bit(cnt_l as u32, 6) as usize;
+        let idx = bank * 16 + offset as usize;
+        self.ram[idx] = val as u8;
+        self.ram[idx + 1] = (val >> 8) as u8;
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Noise {
+    on: bool,
+    timer: i32,
+    lfsr: u16,
+    length: Length,
+    envelope: Envelope,
+}
+
+impl Noise {
+    fn period(cnt_h: u16) -> i32 {
+        let cnt_h = cnt_h as u32;
+        let ratio = extract(cnt_h, 0, 3) as i32;
+        let shift = extract(cnt_h, 4, 4);
+        let base = if ratio == 0 { 16 } else { 32 * ratio };
+        base << (shift + 1).min(15)
+    }
+
+    fn restart(&mut self, cnt_l: u16, cnt_h: u16) {
+        self.on = true;
+        if self.length.left == 0 {
+            self.length.left = 64;
+        }
+        self.timer = Noise::period(cnt_h);
+        self.lfsr = 0x7fff;
+        self.envelope.restart(cnt_l);
+        if cnt_l & 0xf800 == 0 {
+            self.on = false;
+        }
+    }
+
+    fn advance(&mut self, cycles: i32, cnt_h: u16) {
+        if !self.on {
+            return;
+        }
+        let narrow = bit(cnt_h as u32, 3) == 1;
+        self.timer -= cycles;
+        while self.timer <= 0 {
+            self.timer += Noise::period(cnt_h);
+            let x = (self.lfsr ^ (self.lfsr >> 1)) & 1;
+            self.lfsr = (self.lfsr >> 1) | (x << 14);
+            if narrow {
+                self.lfsr = (self.lfsr & !(1 << 6)) | (x << 6);
+            }
+        }
+    }
+
+    fn output(&self) -> i32 {
+        if !self.on {
+            return 0;
+        }
+        let volume = self.envelope.volume as i32;
+        if self.lfsr & 1 == 0 {
+            volume
+        } else {
+            -volume
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+struct Fifo {
+    samples: VecDeque<i8>,
+    current: i8,
+}
+
+impl Fifo {
+    fn write(&mut self, val: u16) {
+        for &byte in &[val as u8, (val >> 8) as u8] {
+            if self.samples.len() < FIFO_LEN {
+                self.samples.push_back(byte as i8);
+            }
+        }
+    }
+}
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct Sound {
+    square1: Square,
+    square2: Square,
+    wave: Wave,
+    noise: Noise,
+    fifos: [Fifo; 2],
+    frame_cycles: u32,
+    frame_step: u8,
+    // Cycles into the 512 between mixes at the lowest resolution, kept with
+    // the channels so they're advanced at the same points after a state
+    // loads
+    mix_phase: u16,
+}
+
+impl Sound {
+    /// Handles writes to the sound registers that do more than set a value
+    pub fn written(&mut self, addr: u32, val: u16, reg: &Ram) {
+        let get = |addr| reg.load16(addr).get();
+        match addr {
+            SOUND1CNT_H => self.square1.length.left = 64 - (val as u32 & 63),
+            SOUND1CNT_X if bit(val as u32, 15) == 1 => {
+                self.square1.restart(get(SOUND1CNT_H), val);
+                self.square1.sweep_restart(get(SOUND1CNT_L), val);
+            }
+            SOUND2CNT_L => self.square2.length.left = 64 - (val as u32 & 63),
+            SOUND2CNT_H if bit(val as u32, 15) == 1 => self.square2.restart(get(SOUND2CNT_L), val),
+            SOUND3CNT_L if bit(val as u32, 7) == 0 => self.wave.on = false,
+            SOUND3CNT_H => self.wave.length.left = 256 - (val as u32 & 0xff),
+            SOUND3CNT_X if bit(val as u32, 15) == 1 => self.wave.restart(get(SOUND3CNT_L), val),
+            SOUND4CNT_L => self.noise.length.left = 64 - (val as u32 & 63),
+            SOUND4CNT_H if bit(val as u32, 15) == 1 => self.noise.restart(get(SOUND4CNT_L), val),
+            SOUNDCNT_H => {
+                if bit(val as u32, 11) == 1 {
+                    self.fifos[0].samples.clear();
+                }
+                if bit(val as u32, 15) == 1 {
+                    self.fifos[1].samples.clear();
+                }
+            }
+            SOUNDCNT_X if bit(val as u32, 7) == 0 => {
+                self.square1.on = false;
+                self.square2.on = false;
+                self.wave.on = false;
+                self.noise.on = false;
+            }
+            0x90..=0x9e => self.wave.write_ram(addr - WAVE_RAM, val, get(SOUND3CNT_L)),
+            FIFO_A | 0xa2 => self.fifos[0].write(val),
+            FIFO_B | 0xa6 => self.fifos[1].write(val),
+            _ => (),
+        }
+    }
+
+    /// Plays the next sample from a FIFO when its timer overflows.  Returns
+    /// whether the FIFO has room for DMA to fill it up again.
+    pub fn fifo_timer(&mut self, fifo: usize) -> bool {
+        let fifo = &mut self.fifos[fifo];
+        if let Some(sample) = fifo.samples.pop_front() {
+            fifo.current = sample;
+        }
+        fifo.samples.len() <= FIFO_LEN / 2
+    }
+
+    /// Moves on a cycle, giving how far into the 512 between mixes at the
+    /// lowest resolution it was
+    pub fn next_phase(&mut self) -> u16 {
+        let phase = self.mix_phase;
+        self.mix_phase = (phase + 1) % 512;
+        phase
+    }
+
+    /// Runs the PSG channels for the cycles since the last sample
+    pub fn advance(&mut self, cycles: u32, reg: &mut Ram) {
+        let get = |addr| reg.load16(addr).get();
+        self.square1.advance(cycles as i32, get(SOUND1CNT_X));
+        self.square2.advance(cycles as i32, get(SOUND2CNT_H));
+        self.wave
+            .advance(cycles as i32, get(SOUND3CNT_L), get(SOUND3CNT_X));
+        self.noise.advance(cycles as i32, get(SOUND4CNT_H));
+
+        self.frame_cycles += cycles;
+        while self.frame_cycles >= FRAME_SEQ_CYCLES {
+            self.frame_cycles -= FRAME_SEQ_CYCLES;
+            self.clock_frame(reg);
+        }
+
+        // The low bits of SOUNDCNT_X show which PSG channels are playing
+        let on = [
+            self.square1.on,
+            self.square2.on,
+            self.wave.on,
+            self.noise.on,
+        ];
+        let status = on
+            .iter()
+            .enumerate()
+            .fold(0, |status, (i, &on)| status | ((on as u16) << i));
+        let cnt_x = reg.load16(SOUNDCNT_X).get();
+        reg.set16(SOUNDCNT_X, (cnt_x & !0xf) | status);
+    }
+
+    fn clock_frame(&mut self, reg: &mut Ram) {
+        let enabled = |addr| bit(reg.load16(addr).get() as u32, 14) == 1;
+        self.frame_step = (self.frame_step + 1) % 8;
+        if self.frame_step % 2 == 0 {
+            let square1 = &mut self.square1;
+            square1.length.clock(enabled(SOUND1CNT_X), &mut square1.on);
+            let square2 = &mut self.square2;
+            square2.length.clock(enabled(SOUND2CNT_H), &mut square2.on);
+            let wave = &mut self.wave;
+            wave.length.clock(enabled(SOUND3CNT_X), &mut wave.on);
+            let noise = &mut self.noise;
+            noise.length.clock(enabled(SOUND4CNT_H), &mut noise.on);
+        }
+        if self.frame_step == 2 || self.frame_step == 6 {
+            let cnt_l = reg.load16(SOUND1CNT_L).get();
+            if let Some(freq) = self.square1.sweep_clock(cnt_l) {
+                let cnt_x = reg.load16(SOUND1CNT_X).get();
+                reg.set16(SOUND1CNT_X, (cnt_x & !0x7ff) | freq);
+            }
+        }
+        if self.frame_step == 7 {
+            self.square1.envelope.clock();
+            self.square2.envelope.clock();
+            self.noise.envelope.clock();
+        }
+    }
+
+    /// What each channel adds to the left and right outputs, on the same
+    /// 10-bit scale as SOUNDBIAS
+    pub fn output(&self, reg: &Ram) -> [(i32, i32); CHANNELS] {
+        let get = |addr| reg.load16(addr).get() as u32;
+        let mut out = [(0, 0); CHANNELS];
+        if bit(get(SOUNDCNT_X), 7) == 0 {
+            return out;
+        }
+
+        let cnt_l = get(SOUNDCNT_L);
+        let cnt_h = get(SOUNDCNT_H);
+        let psg = self.psg_levels(reg);
+        // 25%, 50% or 100%
+        let psg_shift = 2 - extract(cnt_h, 0, 2).min(2);
+        let right_vol = extract(cnt_l, 0, 3) as i32 + 1;
+        let left_vol = extract(cnt_l, 4, 3) as i32 + 1;
+        for (i, &level) in psg.iter().enumerate() {
+            let level = level * 4;
+            if bit(cnt_l, 8 + i as u8) == 1 {
+                out[i].1 = (level * right_vol) >> psg_shift;
+            }
+            if bit(cnt_l, 12 + i as u8) == 1 {
+                out[i].0 = (level * left_vol) >> psg_shift;
+            }
+        }
+
+        for (i, fifo) in self.fifos.iter().enumerate() {
+            let full = bit(cnt_h, 2 + i as u8) == 1;
+            let level = fifo.current as i32 * if full { 4 } else { 2 };
+            let ctrl = extract(cnt_h, 8 + 4 * i as u8, 2);
+            if bit(ctrl, 0) == 1 {
+                out[4 + i].1 = level;
+            }
+            if bit(ctrl, 1) == 1 {
+                out[4 + i].0 = level;
+            }
+        }
+        out
+    }
+
+    // Each PSG channel's own level, from -15 to 15
+    fn psg_levels(&self, reg: &Ram) -> [i32; 4] {
+        let get = |addr| reg.load16(addr).get();
+        [
+            self.square1.output(get(SOUND1CNT_H)),
+            self.square2.output(get(SOUND2CNT_L)),
+            self.wave.output(get(SOUND3CNT_L), get(SOUND3CNT_H)),
+            self.noise.output(),
+        ]
+    }
+
+    /// Each channel's own level from -1.0 to 1.0, before the volume and
+    /// panning in SOUNDCNT_L/H
+    pub fn levels(&self, reg: &Ram) -> [f32; CHANNELS] {
+        let psg = self.psg_levels(reg);
+        let mut levels = [0.0; CHANNELS];
+        for (l, &p) in levels.iter_mut().zip(psg.iter()) {
+            *l = p as f32 / 15.0;
+        }
+        levels[4] = self.fifos[0].current as f32 / 128.0;
+        levels[5] = self.fifos[1].current as f32 / 128.0;
+        levels
+    }
+
+    /// Whether a PSG channel is playing, FIFOs always are
+    pub fn channel_on(&self, ch: usize) -> bool {
+        match ch {
+            0 => self.square1.on,
+            1 => self.square2.on,
+            2 => self.wave.on,
+            3 => self.noise.on,
+            _ => true,
+        }
+    }
+
+    /// Samples waiting in a FIFO, out of 32
+    pub fn fifo_len(&self, fifo: usize) -> usize {
+        self.fifos[fifo].samples.len()
+    }
+
+    /// Which timer plays each FIFO's samples
+    pub fn fifo_timers(reg: &Ram) -> [u32; 2] {
+        let cnt_h = reg.load16(SOUNDCNT_H).get() as u32;
+        [bit(cnt_h, 10), bit(cnt_h, 14)]
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fifo() {
+        let mut sound = Sound::default();
+        let reg = Ram::new(0x100);
+        sound.written(FIFO_A, 0x02ff, &reg);
+        sound.written(0xa2, 0x7f80, &reg);
+        assert_eq!(4, sound.fifos[0].samples.len());
+
+        assert!(sound.fifo_timer(0));
+        assert_eq!(-1, sound.fifos[0].current);
+        sound.fifo_timer(0);
+        assert_eq!(2, sound.fifos[0].current);
+
+        // An empty FIFO keeps playing the last sample
+        for _ in 0..4 {
+            sound.fifo_timer(0);
+        }
+        assert_eq!(0x7f, sound.fifos[0].current);
+        assert_eq!(0, sound.fifos[1].current);
+    }
+
+    #[test]
+    fn test_square_length() {
+        let mut sound = Sound::default();
+        let mut reg = Ram::new(0x100);
+        reg.set16(SOUNDCNT_X, 0x80);
+        // Full volume, length of 2 frame sequencer ticks at 256 Hz
+        reg.set16(SOUND2CNT_L, 0xf03e);
+        sound.written(SOUND2CNT_L, 0xf03e, &reg);
+        reg.set16(SOUND2CNT_H, 0xc000);
+        sound.written(SOUND2CNT_H, 0xc000, &reg);
+
+        sound.advance(1, &mut reg);
+        assert_eq!(0x2, reg.load16(SOUNDCNT_X).get() & 0xf);
+        sound.advance(FRAME_SEQ_CYCLES * 4, &mut reg);
+        assert_eq!(0x0, reg.load16(SOUNDCNT_X).get() & 0xf);
+    }
+
+    #[test]
+    fn test_noise_lfsr() {
+        let mut noise = Noise::default();
+        noise.restart(0xf000, 0);
+        noise.advance(Noise::period(0) * 3, 0);
+        assert_eq!(0x7fff >> 3, noise.lfsr);
+    }
+}
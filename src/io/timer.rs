@@ -8,15 +8,15 @@ use super::IoReg;
 const TIMERS: usize = 4;
 
 #[derive(Copy, Clone, Default, Serialize, Deserialize)]
-pub struct Timers<'a> {
+pub struct Timers {
     timers: [u16; TIMERS],
     cycles: u64,
     #[serde(skip)]
-    io: Shared<IoReg<'a>>,
+    io: Shared<IoReg>,
 }
 
-impl<'a> Timers<'a> {
-    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+impl Timers {
+    pub fn init(&mut self, io: Shared<IoReg>) {
         self.io = io;
     }
 
@@ -28,6 +28,9 @@ impl<'a> Timers<'a> {
             let ctrl = self.io.reg.load32(0x100 + 4 * i as u32).get();
             let ret = self.timers[i].cycle(ctrl, self.cycles as u16, res);
             res = ret.0;
+            if res && i < 2 {
+                self.io.timer_overflow(i as u32);
+            }
             if ret.1 {
                 self.io.raise_interrupt(3 + i as u8);
             }
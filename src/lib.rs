@@ -0,0 +1,72 @@
+//! The emulator core, shared by the frontends in the binary and the
+//! libretro core built from this library.  Programs embedding it should
+//! start from `Emulator`.  What's exported from here, `env` and `link` are
+//! kept stable; the modules hidden from the docs are the frontends' and can
+//! change at any time.
+
+extern crate arm7tdmi_rs;
+extern crate bincode;
+extern crate byteorder;
+#[macro_use]
+extern crate log;
+extern crate memmap;
+extern crate sdl2;
+extern crate serde;
+#[macro_use]
+extern crate serde_derive;
+
+#[doc(hidden)]
+pub mod bit_util;
+#[doc(hidden)]
+pub mod checksum;
+#[doc(hidden)]
+pub mod png;
+#[doc(hidden)]
+pub mod shared;
+#[doc(hidden)]
+pub mod wav;
+
+#[doc(hidden)]
+pub mod cpu;
+#[doc(hidden)]
+pub mod io;
+#[doc(hidden)]
+pub mod mmu;
+#[doc(hidden)]
+pub mod rom;
+#[doc(hidden)]
+pub mod system;
+
+#[doc(hidden)]
+pub mod emulator;
+pub mod env;
+pub mod link;
+
+#[doc(hidden)]
+pub mod debug;
+#[doc(hidden)]
+pub mod video;
+
+#[doc(hidden)]
+pub mod libretro;
+
+pub use cpu::reg;
+pub use emulator::{Emulator, EmulatorBuilder, FrameResult};
+pub use io::key::{Key, KeyState};
+pub use mmu::gba::{MemoryRange, BATTERY_SIZE};
+pub use rom::GameRom;
+pub use video::filter::{ColourCorrection, FilterOptions, FrameBlend};
+
+#[derive(Debug)]
+pub enum GBAError {
+    RomLoadError(std::io::Error),
+    StateLoadError(bincode::Error),
+    OutputError(std::io::Error),
+    ConfigError(String),
+    MovieError(String),
+    TerminalError(std::io::Error),
+    ControlError(std::io::Error),
+    LinkError(std::io::Error),
+}
+
+pub type Result<T> = std::result::Result<T, GBAError>;
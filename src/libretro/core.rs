@@ -0,0 +1,181 @@
+use emulator::Emulator;
+use io::key::KeyState;
+use io::spu::OUTPUT_GAIN;
+use mmu::gba::BATTERY_SIZE;
+use rom::GameRom;
+
+/// A game running for a libretro frontend
+pub struct Core {
+    emu: Emulator,
+    // Interleaved stereo, the last frame's worth
+    audio: Vec<i16>,
+    // The frontend reads and writes the battery save through this, so it's
+    // kept in step with the cartridge around each frame.  Frontends hold on
+    // to its address, so it's never reallocated.
+    battery: Vec<u8>,
+}
+
+impl Core {
+    /// Boots through the BIOS when there is one, otherwise straight into the
+    /// game, which works until it calls into the missing BIOS
+    pub fn new(rom: GameRom, bios: Option<GameRom>) -> Self {
+        Core {
+            emu: boot(rom, bios),
+            audio: vec![],
+            battery: vec![0; BATTERY_SIZE],
+        }
+    }
+
+    /// Powers the machine off and on again, keeping the battery save like a
+    /// cartridge would
+    pub fn reset(&mut self, rom: GameRom, bios: Option<GameRom>) {
+        self.emu = boot(rom, bios);
+        self.audio.clear();
+    }
+
+    pub fn sample_rate(&self) -> i32 {
+        self.emu.sample_rate()
+    }
+
+    pub fn run_frame(&mut self, keys: &KeyState) {
+        // Frontends write the save they loaded after the game's loaded but
+        // before it's first run, and may write it again between frames
+        self.emu.load_battery(&self.battery).unwrap();
+
+        self.emu.run_frame(keys);
+
+        self.audio.clear();
+        for &(l, r) in self.emu.audio() {
+            self.audio.push(to_i16(l));
+            self.audio.push(to_i16(r));
+        }
+        self.battery.copy_from_slice(&self.emu.battery());
+    }
+
+    /// The last frame as XRGB8888
+    pub fn frame(&self) -> &[u32] {
+        self.emu.frame_buffer()
+    }
+
+    pub fn audio(&self) -> &[i16] {
+        &self.audio
+    }
+
+    pub fn battery(&mut self) -> &mut [u8] {
+        &mut self.battery
+    }
+
+    pub fn serialize(&self) -> Vec<u8> {
+        self.emu.save_state()
+    }
+
+    /// The longest `serialize` can return, which frontends size their
+    /// buffers by once
+    pub fn serialize_size(&self) -> usize {
+        self.emu.max_state_size()
+    }
+
+    /// Loads a state from `serialize`, which may have padding after it
+    pub fn unserialize(&mut self, data: &[u8]) -> bool {
+        match self.emu.load_state(data) {
+            Ok(_) => {
+                self.battery.copy_from_slice(&self.emu.battery());
+                true
+            }
+            Err(err) => {
+                error!("Failed to load save state: {:?}", err);
+                false
+            }
+        }
+    }
+}
+
+fn boot(rom: GameRom, bios: Option<GameRom>) -> Emulator {
+    match bios {
+        Some(bios) => Emulator::builder(rom).bios(bios).build(),
+        None => Emulator::builder(rom).build(),
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    ((sample * OUTPUT_GAIN).max(-1.0).min(1.0) * 32767.0) as i16
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use mmu::gba::MemoryRange;
+    use system::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+    #[test]
+    fn test_frames_and_states() {
+        let mut core = Core::new(GameRom::default(), None);
+        core.run_frame(&KeyState::new());
+        assert_eq!(240 * 160, core.frame().len());
+        // A frame's worth at 48 kHz, give or take the odd sample since
+        // frames don't end on a whole one
+        let samples = core.audio().len() as f32 / 2.0;
+        let expected = 48000.0 * CYCLES_PER_FRAME as f32 / CYCLES_PER_SEC as f32;
+        assert!(
+            (samples - expected).abs() <= 2.0,
+            "{} {}",
+            samples,
+            expected
+        );
+
+        let state = core.serialize();
+        core.run_frame(&KeyState::new());
+        let mut padded = state.clone();
+        padded.extend(vec![0; 16]);
+        assert!(core.unserialize(&padded));
+        assert_eq!(state, core.serialize());
+        assert!(!core.unserialize(&state[..16]));
+    }
+
+    #[test]
+    fn test_battery() {
+        let mut core = Core::new(GameRom::default(), None);
+        core.battery()[0] = 0x12;
+        core.battery()[BATTERY_SIZE - 1] = 0x34;
+        core.run_frame(&KeyState::new());
+        assert_eq!(Some(0x12), core.emu.peek8(MemoryRange::GamePakSram, 0));
+        assert_eq!(0x12, core.battery()[0]);
+        assert_eq!(0x34, core.battery()[BATTERY_SIZE - 1]);
+    }
+
+    // Frontends size their state buffers once, before the FIFOs fill
+    #[test]
+    fn test_serialize_size() {
+        let mut core = Core::new(GameRom::default(), None);
+        let size = core.serialize_size();
+        let empty = core.serialize().len();
+        assert!(empty <= size);
+        for _ in 0..16 {
+            core.emu.poke32(MemoryRange::IoRegister, 0xa0, 0x01020304);
+            core.emu.poke32(MemoryRange::IoRegister, 0xa4, 0x01020304);
+        }
+        let full = core.serialize().len();
+        assert_eq!(empty + 64, full);
+        assert!(full <= size);
+        assert_eq!(size, core.serialize_size());
+    }
+
+    // Frontends keep the pointer they're given once the game's loaded
+    #[test]
+    fn test_battery_stays_put() {
+        let mut core = Core::new(GameRom::default(), None);
+        let battery = core.battery().as_ptr();
+        core.run_frame(&KeyState::new());
+        let state = core.serialize();
+        assert!(core.unserialize(&state));
+        core.reset(GameRom::default(), None);
+        assert_eq!(battery, core.battery().as_ptr());
+
+        // Writes between frames reach the cartridge
+        core.battery()[1] = 0x56;
+        core.run_frame(&KeyState::new());
+        assert_eq!(Some(0x56), core.emu.peek8(MemoryRange::GamePakSram, 1));
+        assert_eq!(battery, core.battery().as_ptr());
+    }
+}
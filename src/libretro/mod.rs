@@ -0,0 +1,343 @@
+//! The libretro API, so frontends like RetroArch can load the library as a
+//! core.  Games are loaded from their path, with the BIOS taken from
+//! `gba_bios.bin` in the frontend's system directory.
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::path::{Path, PathBuf};
+use std::ptr;
+use std::slice;
+
+use io::key::{Key, KeyState};
+use io::ppu::{COLS, ROWS};
+use mmu::gba::BATTERY_SIZE;
+use rom::GameRom;
+use system::{CYCLES_PER_FRAME, CYCLES_PER_SEC};
+
+use self::core::Core;
+use self::sys::*;
+
+mod core;
+pub mod sys;
+
+const BIOS_NAME: &'static str = "gba_bios.bin";
+
+// Each GBA key and the joypad button that presses it
+const BUTTONS: [(Key, c_uint); 10] = [
+    (Key::A, RETRO_DEVICE_ID_JOYPAD_A),
+    (Key::B, RETRO_DEVICE_ID_JOYPAD_B),
+    (Key::Select, RETRO_DEVICE_ID_JOYPAD_SELECT),
+    (Key::Start, RETRO_DEVICE_ID_JOYPAD_START),
+    (Key::Right, RETRO_DEVICE_ID_JOYPAD_RIGHT),
+    (Key::Left, RETRO_DEVICE_ID_JOYPAD_LEFT),
+    (Key::Up, RETRO_DEVICE_ID_JOYPAD_UP),
+    (Key::Down, RETRO_DEVICE_ID_JOYPAD_DOWN),
+    (Key::R, RETRO_DEVICE_ID_JOYPAD_R),
+    (Key::L, RETRO_DEVICE_ID_JOYPAD_L),
+];
+
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>,
+}
+
+struct Game {
+    core: Core,
+    // Kept for resetting, which starts again from the files
+    rom: PathBuf,
+    bios: Option<PathBuf>,
+}
+
+// Frontends call into the core from one thread, one call at a time
+static mut CALLBACKS: Callbacks = Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+};
+static mut GAME: Option<Game> = None;
+
+fn game() -> Option<&'static mut Game> {
+    unsafe { (*ptr::addr_of_mut!(GAME)).as_mut() }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(cb: EnvironmentFn) {
+    unsafe { CALLBACKS.environment = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(cb: VideoRefreshFn) {
+    unsafe { CALLBACKS.video_refresh = Some(cb) }
+}
+
+// Sound only goes out in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_cb: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(cb: AudioSampleBatchFn) {
+    unsafe { CALLBACKS.audio_sample_batch = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(cb: InputPollFn) {
+    unsafe { CALLBACKS.input_poll = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(cb: InputStateFn) {
+    unsafe { CALLBACKS.input_state = Some(cb) }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    unsafe { GAME = None }
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: b"gba-rs\0".as_ptr() as *const c_char,
+        library_version: b"0.1.0\0".as_ptr() as *const c_char,
+        valid_extensions: b"gba|bin\0".as_ptr() as *const c_char,
+        need_fullpath: true,
+        block_extract: false,
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    let sample_rate = game().map_or(0, |game| game.core.sample_rate());
+    *info = SystemAvInfo {
+        geometry: GameGeometry {
+            base_width: COLS,
+            base_height: ROWS,
+            max_width: COLS,
+            max_height: ROWS,
+            aspect_ratio: COLS as f32 / ROWS as f32,
+        },
+        timing: SystemTiming {
+            fps: CYCLES_PER_SEC as f64 / CYCLES_PER_FRAME as f64,
+            sample_rate: sample_rate as f64,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(info: *const GameInfo) -> bool {
+    if info.is_null() || (*info).path.is_null() {
+        return false;
+    }
+    let rom = PathBuf::from(&*CStr::from_ptr((*info).path).to_string_lossy());
+
+    let mut format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut format as *mut _ as *mut c_void,
+    ) {
+        error!("Frontend doesn't support XRGB8888");
+        return false;
+    }
+
+    let mut dir: *const c_char = ptr::null();
+    environment(
+        RETRO_ENVIRONMENT_GET_SYSTEM_DIRECTORY,
+        &mut dir as *mut _ as *mut c_void,
+    );
+    let bios = if dir.is_null() {
+        None
+    } else {
+        let path = Path::new(&*CStr::from_ptr(dir).to_string_lossy()).join(BIOS_NAME);
+        if path.exists() {
+            Some(path)
+        } else {
+            warn!("No BIOS at {:?}, booting straight into the game", path);
+            None
+        }
+    };
+
+    match load(&rom, bios.as_ref()) {
+        Some(core) => {
+            GAME = Some(Game {
+                core: core,
+                rom: rom,
+                bios: bios,
+            });
+            true
+        }
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const GameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    unsafe { GAME = None }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(game) = game() {
+        if let Some((rom, bios)) = load_roms(&game.rom, game.bios.as_ref()) {
+            game.core.reset(rom, bios);
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let game = match game() {
+        Some(game) => game,
+        None => return,
+    };
+    let callbacks = unsafe { &*ptr::addr_of!(CALLBACKS) };
+
+    let mut keys = KeyState::new();
+    if let (Some(poll), Some(state)) = (callbacks.input_poll, callbacks.input_state) {
+        poll();
+        for &(key, id) in BUTTONS.iter() {
+            if state(0, RETRO_DEVICE_JOYPAD, 0, id) != 0 {
+                keys.press(key);
+            }
+        }
+    }
+
+    game.core.run_frame(&keys);
+
+    if let Some(video_refresh) = callbacks.video_refresh {
+        let frame = game.core.frame();
+        video_refresh(
+            frame.as_ptr() as *const c_void,
+            COLS,
+            ROWS,
+            COLS as usize * 4,
+        );
+    }
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        // Frontends may take less than all of it at once
+        let mut audio = game.core.audio();
+        while audio.len() >= 2 {
+            let taken = audio_sample_batch(audio.as_ptr(), audio.len() / 2);
+            if taken == 0 {
+                break;
+            }
+            audio = &audio[(taken * 2).min(audio.len())..];
+        }
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    game().map_or(0, |game| game.core.serialize_size())
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let game = match game() {
+        Some(game) => game,
+        None => return false,
+    };
+    let state = game.core.serialize();
+    if state.len() > size {
+        return false;
+    }
+    let out = slice::from_raw_parts_mut(data as *mut u8, size);
+    out[..state.len()].copy_from_slice(&state);
+    for b in &mut out[state.len()..] {
+        *b = 0;
+    }
+    true
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    match game() {
+        Some(game) => game
+            .core
+            .unserialize(slice::from_raw_parts(data as *const u8, size)),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    match game() {
+        Some(game) if id == RETRO_MEMORY_SAVE_RAM => {
+            game.core.battery().as_mut_ptr() as *mut c_void
+        }
+        _ => ptr::null_mut(),
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    match game() {
+        Some(_) if id == RETRO_MEMORY_SAVE_RAM => BATTERY_SIZE,
+        _ => 0,
+    }
+}
+
+fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    match unsafe { CALLBACKS.environment } {
+        Some(environment) => environment(cmd, data),
+        None => false,
+    }
+}
+
+fn load(rom: &Path, bios: Option<&PathBuf>) -> Option<Core> {
+    load_roms(rom, bios).map(|(rom, bios)| Core::new(rom, bios))
+}
+
+fn load_roms(rom: &Path, bios: Option<&PathBuf>) -> Option<(GameRom, Option<GameRom>)> {
+    let rom = match GameRom::new(rom) {
+        Ok(rom) => rom,
+        Err(err) => {
+            error!("Failed to load {:?}: {:?}", rom, err);
+            return None;
+        }
+    };
+    let bios = match bios.map(|path| GameRom::new(path)) {
+        Some(Ok(bios)) => Some(bios),
+        Some(Err(err)) => {
+            error!("Failed to load the BIOS: {:?}", err);
+            return None;
+        }
+        None => None,
+    };
+    Some((rom, bios))
+}
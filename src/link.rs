@@ -0,0 +1,255 @@
+//! Link cables between emulators over local sockets.  One end hosts and
+//! waits for the rest to join, then each frame it gathers every end's serial
+//! port report and sends them all back out, so each machine settles the
+//! same transfers.  Every end waits for the others at the end of each
+//! frame, keeping them in lockstep.
+
+use std::fs;
+use std::io::{self, Read, Write};
+use std::net::{Ipv4Addr, SocketAddrV4, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use bincode;
+
+use io::sio::LinkFrame;
+
+pub const MAX_PLAYERS: u8 = 4;
+
+/// A socket on this machine: a TCP port on 127.0.0.1 or a Unix socket
+#[derive(Clone, Debug, PartialEq)]
+pub enum LocalAddr {
+    Tcp(u16),
+    Unix(PathBuf),
+}
+
+/// Parses a local socket address: a port number, or a path with a `/` in
+/// it for a Unix socket
+pub fn parse_local_addr(s: &str) -> ::std::result::Result<LocalAddr, String> {
+    if let Ok(port) = s.parse::<u16>() {
+        return Ok(LocalAddr::Tcp(port));
+    }
+    if s.contains('/') {
+        return Ok(LocalAddr::Unix(PathBuf::from(s)));
+    }
+    Err("address must be a port or a socket path like ./gba.sock".to_string())
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum LinkSetup {
+    /// Waits for the other players to join, playing as the parent
+    Host {
+        addr: LocalAddr,
+        players: u8,
+    },
+    Join(LocalAddr),
+}
+
+trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+enum Ends {
+    Host(Vec<Box<dyn Stream>>),
+    Join(Box<dyn Stream>),
+}
+
+/// One end of a link cable
+pub struct Link {
+    player: u8,
+    players: u8,
+    ends: Ends,
+}
+
+impl Link {
+    /// Connects everyone, blocking until they have
+    pub fn open(setup: &LinkSetup) -> io::Result<Link> {
+        match *setup {
+            LinkSetup::Host { ref addr, players } => Link::host(addr, players),
+            LinkSetup::Join(ref addr) => Link::join(addr),
+        }
+    }
+
+    fn host(addr: &LocalAddr, players: u8) -> io::Result<Link> {
+        if !(2..=MAX_PLAYERS).contains(&players) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("links take 2 to {} players", MAX_PLAYERS),
+            ));
+        }
+        let joining = players as usize - 1;
+        let mut ends: Vec<Box<dyn Stream>> = vec![];
+        match *addr {
+            LocalAddr::Tcp(port) => {
+                let listener = TcpListener::bind(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+                while ends.len() < joining {
+                    let (stream, _) = listener.accept()?;
+                    stream.set_nodelay(true)?;
+                    ends.push(Box::new(stream));
+                }
+            }
+            LocalAddr::Unix(ref path) => {
+                let listener = UnixListener::bind(path)?;
+                let accepted: io::Result<Vec<_>> =
+                    (0..joining).map(|_| listener.accept()).collect();
+                // Nobody else can join, so the socket's done with either way
+                let _ = fs::remove_file(path);
+                for (stream, _) in accepted? {
+                    ends.push(Box::new(stream));
+                }
+            }
+        }
+        for (i, end) in ends.iter_mut().enumerate() {
+            send(end, &(i as u8 + 1, players))?;
+        }
+        Ok(Link {
+            player: 0,
+            players: players,
+            ends: Ends::Host(ends),
+        })
+    }
+
+    fn join(addr: &LocalAddr) -> io::Result<Link> {
+        let mut end: Box<dyn Stream> = match *addr {
+            LocalAddr::Tcp(port) => {
+                let stream = TcpStream::connect(SocketAddrV4::new(Ipv4Addr::LOCALHOST, port))?;
+                stream.set_nodelay(true)?;
+                Box::new(stream)
+            }
+            LocalAddr::Unix(ref path) => Box::new(UnixStream::connect(path)?),
+        };
+        let (player, players): (u8, u8) = receive(&mut end)?;
+        Ok(Link {
+            player: player,
+            players: players,
+            ends: Ends::Join(end),
+        })
+    }
+
+    /// This end's player number, from 0 for the parent, and how many there
+    /// are
+    pub fn player(&self) -> (u8, u8) {
+        (self.player, self.players)
+    }
+
+    /// Swaps this end's report on a frame for everyone's, in player order
+    pub fn exchange(&mut self, frame: LinkFrame) -> io::Result<Vec<LinkFrame>> {
+        match self.ends {
+            Ends::Host(ref mut ends) => {
+                let mut frames = vec![frame];
+                for end in ends.iter_mut() {
+                    frames.push(receive(end)?);
+                }
+                for end in ends.iter_mut() {
+                    send(end, &frames)?;
+                }
+                Ok(frames)
+            }
+            Ends::Join(ref mut end) => {
+                send(end, &frame)?;
+                receive(end)
+            }
+        }
+    }
+}
+
+fn send<T: ::serde::Serialize>(end: &mut Box<dyn Stream>, val: &T) -> io::Result<()> {
+    bincode::serialize_into(&mut *end, val).map_err(|err| to_io_error(*err))?;
+    end.flush()
+}
+
+fn receive<T: ::serde::de::DeserializeOwned>(end: &mut Box<dyn Stream>) -> io::Result<T> {
+    bincode::deserialize_from(&mut *end).map_err(|err| to_io_error(*err))
+}
+
+fn to_io_error(err: bincode::ErrorKind) -> io::Error {
+    match err {
+        bincode::ErrorKind::Io(err) => err,
+        err => io::Error::new(io::ErrorKind::InvalidData, err),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::env;
+    use std::process;
+    use std::thread;
+
+    use emulator::Emulator;
+    use io::key::KeyState;
+    use mmu::gba::MemoryRange;
+    use rom::GameRom;
+
+    #[test]
+    fn test_parse_local_addr() {
+        assert_eq!(Ok(LocalAddr::Tcp(4000)), parse_local_addr("4000"));
+        assert_eq!(
+            Ok(LocalAddr::Unix(PathBuf::from("/tmp/gba.sock"))),
+            parse_local_addr("/tmp/gba.sock")
+        );
+        assert!(parse_local_addr("gba.sock").is_err());
+    }
+
+    // Three machines in multiplayer mode, each sending its player number
+    #[test]
+    fn test_multiplayer_link() {
+        let path = env::temp_dir().join(format!("gba-link-test-{}.sock", process::id()));
+        let _ = fs::remove_file(&path);
+        let addr = LocalAddr::Unix(path.clone());
+
+        let host = LinkSetup::Host {
+            addr: addr.clone(),
+            players: 3,
+        };
+        let opened: Vec<_> = vec![host, LinkSetup::Join(addr.clone()), LinkSetup::Join(addr)]
+            .into_iter()
+            .enumerate()
+            .map(|(i, setup)| {
+                let path = path.clone();
+                // Everyone connects at once, since nobody's told their player
+                // number until all have
+                thread::spawn(move || {
+                    while i > 0 && !path.exists() {
+                        thread::yield_now();
+                    }
+                    Link::open(&setup).unwrap()
+                })
+            })
+            .collect();
+        let links: Vec<_> = opened.into_iter().map(|t| t.join().unwrap()).collect();
+        assert!(!path.exists());
+
+        let threads: Vec<_> = links
+            .into_iter()
+            .map(|link| {
+                thread::spawn(move || {
+                    let (player, players) = link.player();
+                    assert_eq!(3, players);
+                    let mut emu = Emulator::builder(GameRom::default()).build();
+                    emu.set_link(Some(link));
+                    emu.poke16(MemoryRange::IoRegister, 0x134, 0);
+                    emu.poke16(MemoryRange::IoRegister, 0x128, 0x2003);
+                    emu.poke16(MemoryRange::IoRegister, 0x12a, 0x100 + player as u16);
+                    if player == 0 {
+                        emu.poke16(MemoryRange::IoRegister, 0x128, 0x2083);
+                    }
+                    emu.run_frame(&KeyState::new());
+                    let multi: Vec<u16> = (0..4)
+                        .map(|i| emu.peek16(MemoryRange::IoRegister, 0x120 + 2 * i).unwrap())
+                        .collect();
+                    (player, multi)
+                })
+            })
+            .collect();
+        for thread in threads {
+            let (player, multi) = thread.join().unwrap();
+            assert_eq!(
+                vec![0x100, 0x101, 0x102, 0xffff],
+                multi,
+                "player {}",
+                player
+            );
+        }
+    }
+}
@@ -1,16 +1,16 @@
-extern crate arm7tdmi_rs;
-extern crate arraydeque;
 extern crate bincode;
 extern crate byteorder;
 extern crate clap;
 #[macro_use]
 extern crate log;
 extern crate env_logger;
-extern crate memmap;
+extern crate gba_rs;
+extern crate libc;
 extern crate sdl2;
 extern crate serde;
 #[macro_use]
 extern crate serde_derive;
+extern crate serde_json;
 extern crate zstd;
 
 extern crate flame;
@@ -22,15 +22,12 @@ use std::path::Path;
 
 use clap::{App, Arg, ArgMatches};
 
-mod bit_util;
-mod shared;
-
-mod cpu;
-mod io;
-mod mmu;
-mod rom;
+use gba_rs::link::{self, LinkSetup};
+use gba_rs::{checksum, cpu, debug, io, mmu, rom, shared, system, video};
+use gba_rs::{GBAError, Result};
 
 mod gba;
+mod term;
 
 fn main() {
     env_logger::init();
@@ -40,17 +37,17 @@ fn main() {
         Ok(_) => {}
         Err(errcode) => match errcode {
             RomLoadError(err) => println!("ROM failed to load: {:?}", err),
+            StateLoadError(err) => println!("Save state failed to load: {}", err),
+            OutputError(err) => println!("Failed to write output: {}", err),
+            ConfigError(err) => println!("Config failed to load: {}", err),
+            MovieError(err) => println!("Movie failed to load: {}", err),
+            TerminalError(err) => println!("Terminal couldn't be set up: {}", err),
+            ControlError(err) => println!("Control socket couldn't be opened: {}", err),
+            LinkError(err) => println!("Link cable couldn't connect: {}", err),
         },
     }
 }
 
-#[derive(Debug)]
-pub enum GBAError {
-    RomLoadError(std::io::Error),
-}
-
-pub type Result<T> = std::result::Result<T, GBAError>;
-
 fn run_emu() -> Result<()> {
     let app_m = App::new("gba-rs")
         .version("0.1")
@@ -58,12 +55,12 @@ fn run_emu() -> Result<()> {
         .author("Sean Purcell")
         .arg(
             Arg::with_name("bios")
-                .required(true)
+                .required_unless("debug-dump")
                 .help("GBA bios rom to use"),
         )
         .arg(
             Arg::with_name("rom")
-                .required(true)
+                .required_unless("debug-dump")
                 .help("ROM file to emulate"),
         )
         .arg(
@@ -129,6 +126,374 @@ fn run_emu() -> Result<()> {
                 .default_value("save")
                 .help("The save file prefix to save to"),
         )
+        .arg(
+            Arg::with_name("input")
+                .short("i")
+                .long("input")
+                .takes_value(true)
+                .value_name("file")
+                .help("JSON file mapping keyboard and controller inputs onto the GBA keys"),
+        )
+        .arg(
+            Arg::with_name("record")
+                .long("record")
+                .takes_value(true)
+                .value_name("movie")
+                .conflicts_with("play")
+                .help("Record the keys pressed on each frame to a movie file"),
+        )
+        .arg(
+            Arg::with_name("record-from")
+                .long("record-from")
+                .takes_value(true)
+                .value_name("state")
+                .requires("record")
+                .help("Start the recorded movie from a save state instead of power on"),
+        )
+        .arg(
+            Arg::with_name("play")
+                .long("play")
+                .takes_value(true)
+                .value_name("movie")
+                .help("Play back a recorded movie, ignoring live input until it ends"),
+        )
+        .arg(
+            Arg::with_name("rewind-interval")
+                .long("rewind-interval")
+                .takes_value(true)
+                .value_name("frames")
+                .default_value("4")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(x) if x > 0 => Ok(()),
+                    _ => Err("rewind interval must be at least 1 frame".to_string()),
+                })
+                .help("Frames between the snapshots kept for rewinding"),
+        )
+        .arg(
+            Arg::with_name("rewind-depth")
+                .long("rewind-depth")
+                .takes_value(true)
+                .value_name("snapshots")
+                .default_value("300")
+                .validator(|s| match s.parse::<usize>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.description().to_string()),
+                })
+                .help("Snapshots kept for rewinding with backspace, 0 turns rewinding off"),
+        )
+        .arg(
+            Arg::with_name("turbo")
+                .long("turbo")
+                .takes_value(true)
+                .value_name("multiplier")
+                .default_value("unlimited")
+                .validator(|s| gba::parse_turbo(&s).map(|_| ()))
+                .help(
+                    "Speed when fast-forwarding with tab or backquote, a multiplier like 4 \
+                     or unlimited",
+                ),
+        )
+        .arg(
+            Arg::with_name("sync")
+                .long("sync")
+                .takes_value(true)
+                .possible_values(&["audio", "video"])
+                .default_value("audio")
+                .help(
+                    "Pace the emulation by the audio device's clock, or by the wall clock \
+                     to the GBA frame rate",
+                ),
+        )
+        .arg(
+            Arg::with_name("no-audio")
+                .long("no-audio")
+                .help("Run without sound"),
+        )
+        .arg(
+            Arg::with_name("sample-rate")
+                .long("sample-rate")
+                .takes_value(true)
+                .value_name("hz")
+                .default_value("48000")
+                .validator(|s| match s.parse::<i32>() {
+                    Ok(x) if x >= 8000 => Ok(()),
+                    _ => Err("sample rate must be at least 8000Hz".to_string()),
+                })
+                .help("Sample rate to play sound at, resampled from the GBA's own"),
+        )
+        .arg(
+            Arg::with_name("audio-buffer")
+                .long("audio-buffer")
+                .takes_value(true)
+                .value_name("samples")
+                .default_value("512")
+                .validator(|s| match s.parse::<u16>() {
+                    Ok(x) if x > 0 && x.is_power_of_two() => Ok(()),
+                    _ => Err("audio buffer must be a power of 2 samples".to_string()),
+                })
+                .help("Samples the audio device takes at a time"),
+        )
+        .arg(
+            Arg::with_name("audio-latency")
+                .long("audio-latency")
+                .takes_value(true)
+                .value_name("ms")
+                .default_value("50")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.description().to_string()),
+                })
+                .help("Sound kept queued ahead of the audio device"),
+        )
+        .arg(
+            Arg::with_name("lowpass")
+                .long("lowpass")
+                .takes_value(true)
+                .value_name("hz")
+                .validator(|s| match s.parse::<f32>() {
+                    Ok(x) if x > 0.0 => Ok(()),
+                    _ => Err("low-pass cutoff must be above 0Hz".to_string()),
+                })
+                .help("Low-pass filter the sound like the GBA's output, 8000 is close"),
+        )
+        .arg(
+            Arg::with_name("record-audio")
+                .long("record-audio")
+                .takes_value(true)
+                .value_name("file")
+                .help("Record the sound to a WAV file, R starts and stops recording too"),
+        )
+        .arg(Arg::with_name("stems").long("stems").help(
+            "Also record each sound channel to its own WAV file next to the \
+                     mixed one",
+        ))
+        .arg(
+            Arg::with_name("colour")
+                .long("colour")
+                .takes_value(true)
+                .possible_values(&["off", "gba", "lcd"])
+                .default_value("off")
+                .help(
+                    "Correct colours to look like the GBA's screen, lcd is darker and more \
+                     washed out",
+                ),
+        )
+        .arg(
+            Arg::with_name("lcd-gamma")
+                .long("lcd-gamma")
+                .takes_value(true)
+                .value_name("gamma")
+                .validator(|s| match s.parse::<f32>() {
+                    Ok(x) if x > 0.0 => Ok(()),
+                    _ => Err("gamma must be above 0".to_string()),
+                })
+                .help("Gamma of the screen being corrected for, higher is darker"),
+        )
+        .arg(
+            Arg::with_name("frame-blend")
+                .long("frame-blend")
+                .takes_value(true)
+                .possible_values(&["off", "mix", "ghost"])
+                .default_value("off")
+                .help(
+                    "Blend frames like the GBA's slow LCD: mix averages pairs of frames, \
+                     ghost fades each out over the following ones",
+                ),
+        )
+        .arg(
+            Arg::with_name("ghosting")
+                .long("ghosting")
+                .takes_value(true)
+                .value_name("amount")
+                .default_value("0.5")
+                .validator(|s| match s.parse::<f32>() {
+                    Ok(x) if (0.0..1.0).contains(&x) => Ok(()),
+                    _ => Err("ghosting must be from 0 up to 1".to_string()),
+                })
+                .help("How much of each frame is left on the next with --frame-blend ghost"),
+        )
+        .arg(
+            Arg::with_name("scaler")
+                .long("scaler")
+                .takes_value(true)
+                .possible_values(&["off", "nearest", "scale2x", "scale3x", "xbr"])
+                .default_value("off")
+                .help("Upscale frames before they're shown, G switches while running"),
+        )
+        .arg(
+            Arg::with_name("scale-factor")
+                .long("scale-factor")
+                .takes_value(true)
+                .value_name("n")
+                .default_value("3")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(x) if (1..=8).contains(&x) => Ok(()),
+                    _ => Err("scale factor must be from 1 to 8".to_string()),
+                })
+                .help("How many times the nearest scaler scales up"),
+        )
+        .arg(
+            Arg::with_name("mask")
+                .long("mask")
+                .takes_value(true)
+                .possible_values(&["off", "scanlines", "grid"])
+                .default_value("off")
+                .help("Darken between upscaled pixels like an LCD, M switches while running"),
+        )
+        .arg(
+            Arg::with_name("integer-scale")
+                .long("integer-scale")
+                .help("Only scale the frame to whole multiples of its size to fit the window"),
+        )
+        .arg(
+            Arg::with_name("stretch")
+                .long("stretch")
+                .help("Stretch the frame to fill the window instead of keeping its shape"),
+        )
+        .arg(
+            Arg::with_name("stats")
+                .long("stats")
+                .help("Show the frame rate, audio buffer and keys held, H toggles them too"),
+        )
+        .arg(
+            Arg::with_name("record-video")
+                .long("record-video")
+                .takes_value(true)
+                .value_name("file")
+                .help(
+                    "Record every frame to a .y4m video, numbered .png images, or - \
+                     for y4m on stdout, which the console can't be used with.  V \
+                     starts and stops recording too",
+                ),
+        )
+        .arg(
+            Arg::with_name("video-audio")
+                .long("video-audio")
+                .help("Record the sound alongside videos, to a WAV file of the same name"),
+        )
+        .arg(
+            Arg::with_name("screenshot")
+                .long("screenshot")
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .value_name("frame")
+                .validator(|s| match s.parse::<u64>() {
+                    Ok(_) => Ok(()),
+                    Err(err) => Err(err.description().to_string()),
+                })
+                .help("Save a screenshot after this frame, C takes one too"),
+        )
+        .arg(
+            Arg::with_name("screenshot-scale")
+                .long("screenshot-scale")
+                .takes_value(true)
+                .value_name("n")
+                .default_value("1")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(x) if (1..=16).contains(&x) => Ok(()),
+                    _ => Err("screenshot scale must be from 1 to 16".to_string()),
+                })
+                .help("Also save screenshots upscaled this many times"),
+        )
+        .arg(
+            Arg::with_name("terminal")
+                .long("terminal")
+                .conflicts_with("console")
+                .help(
+                    "Play in the terminal instead of a window, without sound.  Ctrl-C or \
+                     Escape quits",
+                ),
+        )
+        .arg(
+            Arg::with_name("terminal-fps")
+                .long("terminal-fps")
+                .takes_value(true)
+                .value_name("fps")
+                .default_value("15")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(x) if (1..=60).contains(&x) => Ok(()),
+                    _ => Err("terminal frame rate must be from 1 to 60".to_string()),
+                })
+                .help("Frames drawn each second in the terminal"),
+        )
+        .arg(
+            Arg::with_name("console")
+                .short("c")
+                .long("console")
+                .help("Read debugger commands from stdin while running, see `help`"),
+        )
+        .arg(
+            Arg::with_name("control")
+                .long("control")
+                .takes_value(true)
+                .value_name("port|path")
+                .validator(|s| link::parse_local_addr(&s).map(|_| ()))
+                .help(
+                    "Take JSON requests to pause, step, press keys, read memory and more on a \
+                     localhost TCP port or a Unix socket, one per line",
+                ),
+        )
+        .arg(
+            Arg::with_name("link-host")
+                .long("link-host")
+                .takes_value(true)
+                .value_name("port|path")
+                .conflicts_with("link")
+                .validator(|s| link::parse_local_addr(&s).map(|_| ()))
+                .help(
+                    "Host a link cable on a localhost TCP port or a Unix socket, waiting for \
+                     the other players to join before starting",
+                ),
+        )
+        .arg(
+            Arg::with_name("link-players")
+                .long("link-players")
+                .takes_value(true)
+                .value_name("n")
+                .default_value("2")
+                .validator(|s| match s.parse::<u8>() {
+                    Ok(n) if (2..=link::MAX_PLAYERS).contains(&n) => Ok(()),
+                    _ => Err(format!("must be from 2 to {}", link::MAX_PLAYERS)),
+                })
+                .help("Players on the hosted link cable, parent included"),
+        )
+        .arg(
+            Arg::with_name("link")
+                .long("link")
+                .takes_value(true)
+                .value_name("port|path")
+                .validator(|s| link::parse_local_addr(&s).map(|_| ()))
+                .help("Join a link cable hosted by another emulator"),
+        )
+        .arg(
+            Arg::with_name("debug-dump")
+                .long("debug-dump")
+                .required(false)
+                .takes_value(true)
+                .value_name("state")
+                .help(
+                    "Write the VRAM tiles, palette, BG maps and OAM of a save state out, then exit",
+                ),
+        )
+        .arg(
+            Arg::with_name("dump-palette")
+                .long("dump-palette")
+                .required(false)
+                .takes_value(true)
+                .value_name("bank")
+                .default_value("0")
+                .validator(|s| match s.parse::<u32>() {
+                    Ok(x) if x < 32 => Ok(()),
+                    _ => Err("palette bank must be from 0 to 31".to_string()),
+                })
+                .help("Palette bank for dumped tiles, 16 and up are object palettes"),
+        )
+        .arg(
+            Arg::with_name("dump-8bpp")
+                .long("dump-8bpp")
+                .help("Dump tiles as 8bpp instead of 4bpp"),
+        )
         .get_matches();
 
     for _ in 0..app_m.occurrences_of("quiet") {
@@ -136,7 +501,14 @@ fn run_emu() -> Result<()> {
         reduce_logging();
     }
 
-    let res = run_gba(&app_m);
+    let res = match app_m.value_of_os("debug-dump") {
+        Some(state) => gba::debug_dump(
+            Path::new(state),
+            app_m.is_present("dump-8bpp"),
+            app_m.value_of("dump-palette").unwrap().parse().unwrap(),
+        ),
+        None => run_gba(&app_m),
+    };
 
     match app_m.value_of("profile") {
         Some("html") => flame::dump_html(&mut File::create("flame-graph.html").unwrap()).unwrap(),
@@ -161,15 +533,118 @@ fn run_gba(app_m: &ArgMatches) -> Result<()> {
         None => vec![],
     };
 
+    let input = match app_m.value_of_os("input") {
+        Some(path) => gba::InputMap::load(Path::new(path))?,
+        None => Default::default(),
+    };
+
     let opts = gba::Options {
         fps_limit: app_m.value_of("fps-limit").unwrap() == "true",
         breaks: breaks,
         step_frames: app_m.is_present("step-frames"),
         direct_boot: app_m.is_present("direct"),
         save_file: app_m.value_of_os("save-file").unwrap().to_os_string(),
+        console: app_m.is_present("console"),
+        control: app_m
+            .value_of("control")
+            .map(|s| link::parse_local_addr(s).unwrap()),
+        link: match (app_m.value_of("link-host"), app_m.value_of("link")) {
+            (Some(host), _) => Some(LinkSetup::Host {
+                addr: link::parse_local_addr(host).unwrap(),
+                players: app_m.value_of("link-players").unwrap().parse().unwrap(),
+            }),
+            (None, Some(join)) => Some(LinkSetup::Join(link::parse_local_addr(join).unwrap())),
+            (None, None) => None,
+        },
+        input: input,
+        record_movie: app_m.value_of_os("record").map(|s| s.to_os_string()),
+        play_movie: app_m.value_of_os("play").map(|s| s.to_os_string()),
+        movie_state: app_m.value_of_os("record-from").map(|s| s.to_os_string()),
+        rewind: gba::RewindOptions {
+            interval: app_m.value_of("rewind-interval").unwrap().parse().unwrap(),
+            depth: app_m.value_of("rewind-depth").unwrap().parse().unwrap(),
+        },
+        turbo: gba::parse_turbo(app_m.value_of("turbo").unwrap()).unwrap(),
+        sync: match app_m.value_of("sync").unwrap() {
+            "video" => gba::SyncMode::Video,
+            _ => gba::SyncMode::Audio,
+        },
+        audio: if app_m.is_present("no-audio") {
+            None
+        } else {
+            Some(gba::AudioOptions {
+                freq: app_m.value_of("sample-rate").unwrap().parse().unwrap(),
+                samples: app_m.value_of("audio-buffer").unwrap().parse().unwrap(),
+                latency: app_m.value_of("audio-latency").unwrap().parse().unwrap(),
+                lowpass: app_m.value_of("lowpass").map(|s| s.parse().unwrap()),
+            })
+        },
+        record_audio: app_m.value_of_os("record-audio").map(|s| s.to_os_string()),
+        record_stems: app_m.is_present("stems"),
+        filter: gba::FilterOptions {
+            colour: match app_m.value_of("colour").unwrap() {
+                "gba" => gba::ColourCorrection::Gba,
+                "lcd" => gba::ColourCorrection::Lcd,
+                _ => gba::ColourCorrection::Off,
+            },
+            lcd_gamma: app_m.value_of("lcd-gamma").map(|s| s.parse().unwrap()),
+            blend: match app_m.value_of("frame-blend").unwrap() {
+                "mix" => gba::FrameBlend::Mix,
+                "ghost" => {
+                    gba::FrameBlend::Ghost(app_m.value_of("ghosting").unwrap().parse().unwrap())
+                }
+                _ => gba::FrameBlend::Off,
+            },
+        },
+        display: gba::DisplayOptions {
+            scaler: match app_m.value_of("scaler").unwrap() {
+                "nearest" => gba::Scaler::Nearest,
+                "scale2x" => gba::Scaler::Scale2x,
+                "scale3x" => gba::Scaler::Scale3x,
+                "xbr" => gba::Scaler::Xbr,
+                _ => gba::Scaler::Off,
+            },
+            factor: app_m.value_of("scale-factor").unwrap().parse().unwrap(),
+            mask: match app_m.value_of("mask").unwrap() {
+                "scanlines" => gba::Mask::Scanlines,
+                "grid" => gba::Mask::Grid,
+                _ => gba::Mask::Off,
+            },
+            integer_scale: app_m.is_present("integer-scale"),
+            keep_aspect: !app_m.is_present("stretch"),
+        },
+        show_stats: app_m.is_present("stats"),
+        record_video: app_m.value_of_os("record-video").map(|s| s.to_os_string()),
+        record_video_audio: app_m.is_present("video-audio"),
+        screenshot_frames: match app_m.values_of("screenshot") {
+            Some(v) => v.map(|s| s.parse().unwrap()).collect(),
+            None => vec![],
+        },
+        screenshot_scale: app_m.value_of("screenshot-scale").unwrap().parse().unwrap(),
         ..Default::default()
     };
 
+    if app_m.is_present("terminal") {
+        return term::run(
+            rom,
+            bios,
+            term::Options {
+                direct_boot: opts.direct_boot,
+                input: opts.input,
+                filter: opts.filter,
+                fps: app_m.value_of("terminal-fps").unwrap().parse().unwrap(),
+            },
+        );
+    }
+
+    // The console prints to stdout, which would end up in the video
+    if opts.console && opts.record_video == Some("-".into()) {
+        return Err(GBAError::OutputError(std::io::Error::new(
+            std::io::ErrorKind::InvalidInput,
+            "video can't go to stdout while the console is on",
+        )));
+    }
+
     let mut gba = gba::Gba::new(rom, bios, opts);
 
     gba.run()
@@ -7,12 +7,12 @@ use super::{Gba, MemoryRead, Mmu};
 const BIOS_SIZE: u32 = 0x4000;
 
 #[derive(Default)]
-pub struct Bios<'a> {
+pub struct Bios {
     bios: GameRom,
-    cpu: Shared<Cpu<Gba<'a>>>,
+    cpu: Shared<Cpu<Gba>>,
 }
 
-impl<'a> Bios<'a> {
+impl Bios {
     pub fn new(bios: GameRom) -> Self {
         Self {
             bios: bios,
@@ -20,12 +20,17 @@ impl<'a> Bios<'a> {
         }
     }
 
-    pub fn init(&mut self, cpu: Shared<Cpu<Gba<'a>>>) {
+    pub fn init(&mut self, cpu: Shared<Cpu<Gba>>) {
         self.cpu = cpu;
     }
+
+    /// The whole BIOS image, regardless of where the CPU is
+    pub fn rom(&self) -> &GameRom {
+        &self.bios
+    }
 }
 
-impl<'a> Mmu for Bios<'a> {
+impl Mmu for Bios {
     fn load8(&self, addr: u32) -> MemoryRead<u8> {
         // Determine where CPU PC is
         if addr < BIOS_SIZE {
@@ -16,10 +16,17 @@ mod save;
 
 use self::bios::Bios;
 
-use self::save::Eeprom;
+use self::save::{Eeprom, EEPROM_SIZE};
 
+const SRAM_SIZE: usize = 64 * 1024;
+
+/// Bytes of battery-backed save memory on the cartridge: the SRAM, then the
+/// EEPROM.  Games only use one.
+pub const BATTERY_SIZE: usize = SRAM_SIZE + EEPROM_SIZE;
+
+/// The regions of the GBA's address space
 #[derive(Clone, Copy, PartialEq, Eq, Hash, Debug)]
-enum MemoryRange {
+pub enum MemoryRange {
     Bios,
     BoardWram,
     ChipWram,
@@ -34,8 +41,9 @@ enum MemoryRange {
 }
 
 impl MemoryRange {
+    /// The first address in the region and the one after its end
     #[inline]
-    fn bounds(&self) -> (u32, u32) {
+    pub fn bounds(&self) -> (u32, u32) {
         use self::MemoryRange::*;
         #[cfg_attr(rustfmt, rustfmt_skip)]
         match *self {
@@ -107,9 +115,9 @@ impl MemoryRange {
 
 /// Implements the memory mapping for a GBA system
 #[derive(Serialize, Deserialize)]
-pub struct Gba<'a> {
+pub struct Gba {
     #[serde(skip)]
-    pub bios: Bios<'a>,
+    pub bios: Bios,
     pub bram: Ram,
     pub cram: Ram,
     pub pram: Ram,
@@ -120,15 +128,15 @@ pub struct Gba<'a> {
     pub gram: Ram,
 
     #[serde(skip)]
-    pub io: Shared<IoReg<'a>>,
-    pub ee: Eeprom<'a>,
+    pub io: Shared<IoReg>,
+    pub ee: Eeprom,
 
     #[serde(skip)]
-    pub cpu: Shared<Cpu<Gba<'a>>>,
+    pub cpu: Shared<Cpu<Gba>>,
 }
 
-impl<'a> Gba<'a> {
-    pub fn new(rom: GameRom, bios: GameRom, io: Shared<IoReg<'a>>) -> Gba {
+impl Gba {
+    pub fn new(rom: GameRom, bios: GameRom, io: Shared<IoReg>) -> Gba {
         let mut ee = Eeprom::default();
         ee.init(io);
         Gba {
@@ -140,17 +148,52 @@ impl<'a> Gba<'a> {
             oam: Ram::new(1024),
             rom: rom,
             ee: ee,
-            gram: Ram::new(64 * 1024),
+            gram: Ram::new(SRAM_SIZE),
             io: io,
             cpu: Default::default(),
         }
     }
 
-    pub fn init(&mut self, cpu: Shared<Cpu<Gba<'a>>>) {
+    pub fn init(&mut self, cpu: Shared<Cpu<Gba>>) {
         self.cpu = cpu;
         self.bios.init(cpu);
     }
 
+    /// Takes on the memory contents of a save state, keeping the ROM and BIOS
+    pub fn restore(&mut self, saved: Gba) {
+        self.bram = saved.bram;
+        self.cram = saved.cram;
+        self.pram = saved.pram;
+        self.vram = saved.vram;
+        self.oam = saved.oam;
+        self.gram = saved.gram;
+        self.ee = saved.ee;
+        self.ee.init(self.io);
+    }
+
+    /// The battery-backed save memory, laid out as described for
+    /// `BATTERY_SIZE`
+    pub fn battery(&self) -> Vec<u8> {
+        let mut data = self.gram.bytes().to_vec();
+        data.extend(self.ee.contents());
+        data
+    }
+
+    /// Replaces the battery-backed save memory with what `battery` gave
+    pub fn load_battery(&mut self, data: &[u8]) {
+        let split = data.len().min(SRAM_SIZE);
+        self.gram.bytes_mut()[..split].copy_from_slice(&data[..split]);
+        self.ee.load_contents(&data[split..]);
+    }
+
+    fn before_set(&mut self, addr: u32) {
+        use self::MemoryRange::*;
+        match MemoryRange::match_addr(addr) {
+            Palette | VideoRam | ObjectAttr => self.io.video_written(),
+            _ => {}
+        }
+    }
+
     pub fn get_range(&self, addr: u32) -> Option<(u32, &Mmu)> {
         use self::MemoryRange::*;
         let range = MemoryRange::match_addr(addr);
@@ -201,7 +244,7 @@ impl<'a> Gba<'a> {
     }
 }
 
-impl<'a> MemoryUnit for Gba<'a> {
+impl MemoryUnit for Gba {
     fn load8(&self, addr: u32) -> u8 {
         use self::MemoryRead::*;
 
@@ -222,6 +265,7 @@ impl<'a> MemoryUnit for Gba<'a> {
 
     fn set8(&mut self, addr: u32, val: u8) {
         debug!("set08\t@ {:#010x}: {:#04x}", addr, val);
+        self.before_set(addr);
         match self.get_range_mut(addr) {
             Some((naddr, mmu)) => mmu.set8(naddr, val),
             None => warning(addr),
@@ -248,6 +292,7 @@ impl<'a> MemoryUnit for Gba<'a> {
 
     fn set16(&mut self, addr: u32, val: u16) {
         debug!("set16\t@ {:#010x}: {:#06x}", addr, val);
+        self.before_set(addr);
         match self.get_range_mut(addr) {
             Some((naddr, mmu)) => mmu.set16(naddr, val),
             None => warning(addr),
@@ -274,11 +319,20 @@ impl<'a> MemoryUnit for Gba<'a> {
 
     fn set32(&mut self, addr: u32, val: u32) {
         debug!("set32\t@ {:#010x}: {:#010x}", addr, val);
+        self.before_set(addr);
         match self.get_range_mut(addr) {
             Some((naddr, mmu)) => mmu.set32(naddr, val),
             None => warning(addr),
         }
     }
+
+    fn cpu_read(&mut self, addr: u32, len: u32) {
+        let range = MemoryRange::match_addr(addr);
+        if range == MemoryRange::IoRegister {
+            let naddr = range.convert_addr(addr) & !(len - 1);
+            self.io.cpu_read(naddr, len);
+        }
+    }
 }
 
 fn warning(addr: u32) {
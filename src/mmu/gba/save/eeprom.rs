@@ -2,6 +2,7 @@ use std::cell::RefCell;
 use std::fmt;
 use std::ops::{Deref, DerefMut};
 
+use byteorder::{BigEndian, ByteOrder};
 use serde::de::{Error, SeqAccess, Visitor};
 use serde::ser::SerializeTuple;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
@@ -14,16 +15,19 @@ use mmu::{MemoryRead, Mmu};
 
 const MEM_SIZE: usize = 1024;
 
+/// Bytes of EEPROM, the largest chip there is
+pub const EEPROM_SIZE: usize = MEM_SIZE * 8;
+
 #[derive(Serialize, Deserialize)]
-pub struct Eeprom<'a> {
-    ee: RefCell<EepromInner<'a>>,
+pub struct Eeprom {
+    ee: RefCell<EepromInner>,
 }
 
 // Note: Everything other than mem shouldn't actually need serializing
 // DMA currently is instantaneous and so it can't span a frame barrier
 // (where the save state takes place)
 #[derive(Serialize, Deserialize)]
-struct EepromInner<'a> {
+struct EepromInner {
     mem: EepromMem,
     state: State,
     write: bool,
@@ -32,7 +36,7 @@ struct EepromInner<'a> {
     data: u64,
 
     #[serde(skip)]
-    io: Shared<IoReg<'a>>,
+    io: Shared<IoReg>,
 }
 
 #[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
@@ -45,7 +49,7 @@ enum State {
     ReadData,
 }
 
-impl<'a> Default for Eeprom<'a> {
+impl Default for Eeprom {
     fn default() -> Self {
         Eeprom {
             ee: RefCell::new(Default::default()),
@@ -53,13 +57,32 @@ impl<'a> Default for Eeprom<'a> {
     }
 }
 
-impl<'a> Eeprom<'a> {
-    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+impl Eeprom {
+    pub fn init(&mut self, io: Shared<IoReg>) {
         self.ee.borrow_mut().init(io);
     }
+
+    /// The memory as bytes, each 64-bit block most significant byte first
+    /// like it's sent over the bus
+    pub fn contents(&self) -> Vec<u8> {
+        let mut bytes = vec![0; EEPROM_SIZE];
+        BigEndian::write_u64_into(&self.ee.borrow().mem, &mut bytes);
+        bytes
+    }
+
+    /// Replaces the memory with bytes laid out like `contents`, leaving the
+    /// rest untouched if there aren't enough
+    pub fn load_contents(&mut self, bytes: &[u8]) {
+        let mut ee = self.ee.borrow_mut();
+        for (block, chunk) in ee.mem.iter_mut().zip(bytes.chunks(8)) {
+            if chunk.len() == 8 {
+                *block = BigEndian::read_u64(chunk);
+            }
+        }
+    }
 }
 
-impl<'a> Default for EepromInner<'a> {
+impl Default for EepromInner {
     fn default() -> Self {
         EepromInner {
             mem: Default::default(),
@@ -73,8 +96,8 @@ impl<'a> Default for EepromInner<'a> {
     }
 }
 
-impl<'a> EepromInner<'a> {
-    pub fn init(&mut self, io: Shared<IoReg<'a>>) {
+impl EepromInner {
+    pub fn init(&mut self, io: Shared<IoReg>) {
         self.io = io;
     }
 
@@ -230,7 +253,7 @@ impl<'de> Deserialize<'de> for EepromMem {
     }
 }
 
-impl<'a> Mmu for Eeprom<'a> {
+impl Mmu for Eeprom {
     fn load8(&self, _addr: u32) -> MemoryRead<u8> {
         MemoryRead::Value(self.ee.borrow_mut().read() as u8)
     }
@@ -1,2 +1,2 @@
 mod eeprom;
-pub use self::eeprom::Eeprom;
+pub use self::eeprom::{Eeprom, EEPROM_SIZE};
@@ -28,6 +28,11 @@ pub trait MemoryUnit {
     fn set16(&mut self, addr: u32, val: u16);
     fn load32(&self, addr: u32) -> u32;
     fn set32(&mut self, addr: u32, val: u32);
+
+    /// Called after the CPU reads `len` bytes, for registers that change
+    /// when read.  Loads on their own never change anything, so debuggers
+    /// can peek freely.
+    fn cpu_read(&mut self, _addr: u32, _len: u32) {}
 }
 
 /// A subpiece of the MMU TODO: rename
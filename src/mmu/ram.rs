@@ -24,6 +24,14 @@ impl Ram {
     pub fn len(&self) -> usize {
         self.mem.len()
     }
+
+    pub fn bytes(&self) -> &[u8] {
+        &self.mem
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8] {
+        &mut self.mem
+    }
 }
 
 impl Mmu for Ram {
@@ -0,0 +1,118 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{BigEndian, WriteBytesExt};
+
+use checksum::{adler32, crc32_update};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+// Deflate stored blocks can hold at most 64k - 1 bytes each
+const MAX_STORED: usize = 0xffff;
+
+/// Writes an RGB image as a PNG.  Pixels are packed as 0x00RRGGBB, row by row.
+///
+/// The image data is stored without compression, which keeps this free of
+/// any dependencies at the cost of larger files.
+pub fn write_rgb<W: Write>(w: &mut W, width: u32, height: u32, pixels: &[u32]) -> io::Result<()> {
+    assert_eq!((width * height) as usize, pixels.len());
+
+    w.write_all(&SIGNATURE)?;
+
+    let mut ihdr = Vec::with_capacity(13);
+    ihdr.write_u32::<BigEndian>(width)?;
+    ihdr.write_u32::<BigEndian>(height)?;
+    // 8 bit depth, truecolour, default compression/filter, no interlace
+    ihdr.extend_from_slice(&[8, 2, 0, 0, 0]);
+    write_chunk(w, b"IHDR", &ihdr)?;
+
+    // Each row starts with its filter type, which is always none here
+    let mut raw = Vec::with_capacity((height * (1 + width * 3)) as usize);
+    for row in pixels.chunks(width as usize) {
+        raw.push(0);
+        for p in row {
+            raw.extend_from_slice(&[(p >> 16) as u8, (p >> 8) as u8, *p as u8]);
+        }
+    }
+    write_chunk(w, b"IDAT", &zlib_stored(&raw))?;
+
+    write_chunk(w, b"IEND", &[])
+}
+
+/// Writes an RGB image to a PNG file at the given path
+pub fn save_rgb(path: &Path, width: u32, height: u32, pixels: &[u32]) -> io::Result<()> {
+    let mut file = BufWriter::new(File::create(path)?);
+    write_rgb(&mut file, width, height, pixels)?;
+    file.flush()
+}
+
+fn write_chunk<W: Write>(w: &mut W, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+    w.write_u32::<BigEndian>(data.len() as u32)?;
+    w.write_all(kind)?;
+    w.write_all(data)?;
+    let crc = crc32_update(crc32_update(0, kind), data);
+    w.write_u32::<BigEndian>(crc)
+}
+
+/// Wraps data in a zlib stream made of uncompressed deflate blocks
+fn zlib_stored(data: &[u8]) -> Vec<u8> {
+    let blocks = data.len() / MAX_STORED + 1;
+    let mut out = Vec::with_capacity(data.len() + blocks * 5 + 6);
+    // deflate with a 32k window, no preset dictionary, fastest compression
+    out.extend_from_slice(&[0x78, 0x01]);
+
+    let mut chunks = data.chunks(MAX_STORED).peekable();
+    if chunks.peek().is_none() {
+        // an empty stream still needs a final block
+        out.extend_from_slice(&[1, 0, 0, 0xff, 0xff]);
+    }
+    while let Some(chunk) = chunks.next() {
+        let last = chunks.peek().is_none();
+        let len = chunk.len() as u16;
+        out.push(last as u8);
+        out.extend_from_slice(&[len as u8, (len >> 8) as u8]);
+        out.extend_from_slice(&[!len as u8, (!len >> 8) as u8]);
+        out.extend_from_slice(chunk);
+    }
+
+    let adler = adler32(data);
+    out.extend_from_slice(&[
+        (adler >> 24) as u8,
+        (adler >> 16) as u8,
+        (adler >> 8) as u8,
+        adler as u8,
+    ]);
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_zlib_stored() {
+        let z = zlib_stored(b"abc");
+        assert_eq!(
+            vec![0x78, 0x01, 1, 3, 0, 0xfc, 0xff, b'a', b'b', b'c', 0x02, 0x4d, 0x01, 0x27],
+            z
+        );
+
+        let big = vec![0u8; MAX_STORED + 1];
+        let z = zlib_stored(&big);
+        assert_eq!(2 + 5 + MAX_STORED + 5 + 1 + 4, z.len());
+        assert_eq!(0, z[2]);
+        assert_eq!(1, z[2 + 5 + MAX_STORED]);
+    }
+
+    #[test]
+    fn test_png_layout() {
+        let mut out = Vec::new();
+        write_rgb(&mut out, 2, 1, &[0xff0000, 0x0000ff]).unwrap();
+        assert_eq!(&SIGNATURE[..], &out[..8]);
+        assert_eq!(&b"IHDR"[..], &out[12..16]);
+        assert_eq!(&b"IEND"[..], &out[out.len() - 8..out.len() - 4]);
+        // IEND's CRC is fixed
+        assert_eq!(&[0xae, 0x42, 0x60, 0x82][..], &out[out.len() - 4..]);
+    }
+}
@@ -3,7 +3,7 @@ use std::fs::File;
 use std::ops::Deref;
 use std::path::Path;
 
-use memmap::{Mmap, MmapMut};
+use memmap::Mmap;
 
 use mmu::{bytes, MemoryRead, Mmu};
 
@@ -11,14 +11,15 @@ use GBAError;
 use Result;
 
 pub struct GameRom {
-    rom: Mmap,
+    // None when empty, since nothing can be mapped with no length
+    rom: Option<Mmap>,
 }
 
 impl GameRom {
     pub fn new(path: &Path) -> Result<GameRom> {
         match File::open(path) {
             Ok(file) => match unsafe { Mmap::map(&file) } {
-                Ok(mmap) => Ok(GameRom { rom: mmap }),
+                Ok(mmap) => Ok(GameRom { rom: Some(mmap) }),
                 Err(err) => Err(GBAError::RomLoadError(err)),
             },
             Err(err) => Err(GBAError::RomLoadError(err)),
@@ -26,11 +27,30 @@ impl GameRom {
     }
 }
 
+impl GameRom {
+    /// The game title from the cartridge header
+    pub fn title(&self) -> String {
+        self.header_string(0xa0, 12)
+    }
+
+    /// The four character game code from the cartridge header
+    pub fn game_code(&self) -> String {
+        self.header_string(0xac, 4)
+    }
+
+    fn header_string(&self, start: usize, len: usize) -> String {
+        self.get(start..start + len)
+            .unwrap_or(&[])
+            .iter()
+            .take_while(|&&c| c != 0)
+            .map(|&c| c as char)
+            .collect()
+    }
+}
+
 impl Default for GameRom {
     fn default() -> Self {
-        return GameRom {
-            rom: MmapMut::map_anon(0).unwrap().make_read_only().unwrap(),
-        };
+        GameRom { rom: None }
     }
 }
 
@@ -39,7 +59,10 @@ impl Deref for GameRom {
 
     #[inline]
     fn deref(&self) -> &[u8] {
-        self.rom.deref()
+        match self.rom {
+            Some(ref rom) => rom.deref(),
+            None => &[],
+        }
     }
 }
 
@@ -55,7 +78,7 @@ where
 
 impl Mmu for GameRom {
     fn load8(&self, addr: u32) -> MemoryRead<u8> {
-        if (addr as usize) < self.rom.len() {
+        if (addr as usize) < self.len() {
             bytes::load8(self.deref(), addr)
         } else {
             MemoryRead::Value((((addr >> 1) & 0xffff) << ((addr & 1) * 8)) as u8)
@@ -67,7 +90,7 @@ impl Mmu for GameRom {
     }
 
     fn load16(&self, addr: u32) -> MemoryRead<u16> {
-        if (addr as usize) < self.rom.len() {
+        if (addr as usize) < self.len() {
             bytes::load16(self.deref(), addr)
         } else {
             MemoryRead::Value((addr >> 1) as u16)
@@ -79,7 +102,7 @@ impl Mmu for GameRom {
     }
 
     fn load32(&self, addr: u32) -> MemoryRead<u32> {
-        if (addr as usize) < self.rom.len() {
+        if (addr as usize) < self.len() {
             bytes::load32(self.deref(), addr)
         } else {
             let r = (addr >> 1) & 0xffff;
@@ -95,9 +118,9 @@ impl Mmu for GameRom {
 impl fmt::Debug for GameRom {
     fn fmt(&self, fmt: &mut fmt::Formatter) -> fmt::Result {
         fmt.debug_struct("GameRom")
-            .field("len", &self.rom.len())
-            .field("ptr", &self.rom.as_ptr())
-            .field("val", &format!("{:#x}", self.rom[0xb2]))
+            .field("len", &self.len())
+            .field("ptr", &self.as_ptr())
+            .field("val", &format!("{:#x}", self[0xb2]))
             .finish()
     }
 }
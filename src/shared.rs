@@ -26,7 +26,11 @@ impl<T> Shared<T> {
         Shared { t: ptr::null_mut() }
     }
 
-    pub fn new(val: &mut T) -> Self {
+    /// # Safety
+    ///
+    /// `val` has to stay where it is for as long as any copy is used, and
+    /// nothing else can be using it while a copy's dereferenced.
+    pub unsafe fn new(val: &mut T) -> Self {
         Shared { t: val as *mut T }
     }
 }
@@ -70,7 +74,7 @@ mod test {
     #[test]
     fn test_shared() {
         let mut a = 5;
-        let mut b = Shared::new(&mut a);
+        let mut b = unsafe { Shared::new(&mut a) };
 
         (*b) = 3;
 
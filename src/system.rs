@@ -0,0 +1,210 @@
+//! The emulated hardware on its own, run a frame at a time.  Frontends feed
+//! it keys and take frames and sound out of it.
+
+use std::io::Read;
+use std::mem::MaybeUninit;
+use std::ptr;
+
+use bincode;
+use serde::ser::SerializeStruct;
+use serde::{Serialize, Serializer};
+
+use shared::Shared;
+
+use cpu::Cpu;
+use io::ppu::Ppu;
+use io::spu::Spu;
+use io::{IoReg, MAX_QUEUED};
+use link::Link;
+use mmu::gba::Gba as GbaMmu;
+use rom::GameRom;
+
+use GBAError;
+
+pub const CYCLES_PER_SEC: u64 = 16 * 1024 * 1024;
+pub const CYCLES_PER_FRAME: u64 = 280896;
+
+/// A whole GBA.  Its parts hold pointers to each other, so it's only ever
+/// used from the box `new` makes, which keeps them in place.  Any number
+/// can run at once, on whichever threads.
+pub struct System {
+    // Frames run since power on
+    pub frame: u64,
+    /// Where the CPU hit breakpoints, in order, for whoever's watching to
+    /// take.  Frames run to the end regardless.
+    pub break_hits: Vec<u32>,
+
+    pub cpu: Cpu<GbaMmu>,
+    pub mmu: GbaMmu,
+    pub io: IoReg,
+    pub ppu: Ppu,
+    pub spu: Spu,
+
+    link: Option<Link>,
+}
+
+// The parts only point at each other, all inside the one box, so the whole
+// can move between threads together
+unsafe impl Send for System {}
+
+impl System {
+    /// Powers on with the ROM and BIOS loaded, starting from the BIOS unless
+    /// `direct_boot` skips straight to the ROM
+    pub fn new(rom: GameRom, bios: GameRom, direct_boot: bool) -> Box<Self> {
+        // The parts point at each other, so they're built in place
+        let raw = Box::into_raw(Box::new(MaybeUninit::<System>::uninit())) as *mut System;
+        unsafe {
+            ptr::addr_of_mut!((*raw).frame).write(0);
+            ptr::addr_of_mut!((*raw).break_hits).write(vec![]);
+
+            ptr::addr_of_mut!((*raw).io).write(IoReg::new());
+            let io = Shared::new(&mut (*raw).io);
+            ptr::addr_of_mut!((*raw).mmu).write(GbaMmu::new(rom, bios, io));
+            let mmu = Shared::new(&mut (*raw).mmu);
+
+            ptr::addr_of_mut!((*raw).cpu).write(Cpu::new(mmu, &[]));
+            if direct_boot {
+                (*raw).cpu.init_direct();
+            } else {
+                (*raw).cpu.init_arm();
+            }
+
+            ptr::addr_of_mut!((*raw).ppu).write(Ppu::new(io, mmu));
+            ptr::addr_of_mut!((*raw).spu).write(Spu::new(io));
+            ptr::addr_of_mut!((*raw).link).write(None);
+
+            let mut sys = Box::from_raw(raw);
+            let cpu = Shared::new(&mut sys.cpu);
+            let ppu = Shared::new(&mut sys.ppu);
+            sys.mmu.init(cpu);
+            sys.io.init(cpu, mmu, ppu);
+            sys
+        }
+    }
+
+    pub fn emulate_frame(&mut self) {
+        for _ in 0..CYCLES_PER_FRAME {
+            self.cycle();
+        }
+        self.frame += 1;
+        if self.link.is_some() {
+            self.exchange_link();
+        }
+    }
+
+    /// Plugs in a link cable, or unplugs it with None
+    pub fn set_link(&mut self, link: Option<Link>) {
+        self.io.set_link_player(link.as_ref().map(Link::player));
+        self.link = link;
+    }
+
+    // Waits for the other ends to finish the frame, then settles the
+    // transfers between them
+    fn exchange_link(&mut self) {
+        let frame = self.io.link_frame();
+        let res = self.link.as_mut().unwrap().exchange(frame);
+        match res {
+            Ok(frames) => self.io.link_settle(&frames),
+            Err(err) => {
+                warn!("Link cable disconnected: {}", err);
+                self.set_link(None);
+            }
+        }
+    }
+
+    fn cycle(&mut self) {
+        if self.cpu.cycle() {
+            self.break_hits.push(self.cpu.get_prefetch_addr());
+        }
+        self.ppu.cycle();
+        self.spu.cycle();
+        self.io.cycle();
+    }
+
+    /// Replaces the state of the hardware with a saved one, keeping the ROM
+    /// and BIOS
+    pub fn load_state<R: Read>(&mut self, reader: R) -> ::Result<()> {
+        let state: StateFields =
+            bincode::deserialize_from(reader).map_err(GBAError::StateLoadError)?;
+        self.cpu.restore(state.cpu);
+        self.mmu.restore(state.mmu);
+        self.io.restore(state.io);
+        self.ppu.restore(state.ppu);
+        self.frame = state.frame;
+        Ok(())
+    }
+
+    /// The longest a save state can get.  Only what's queued in the sound
+    /// FIFOs and UART changes the length, so this stays the same.
+    pub fn max_state_size(&self) -> usize {
+        bincode::serialized_size(self).unwrap() as usize - self.io.queued() + MAX_QUEUED
+    }
+}
+
+#[derive(Deserialize)]
+struct StateFields {
+    cpu: Cpu<GbaMmu>,
+    mmu: GbaMmu,
+    io: IoReg,
+    ppu: Ppu,
+    frame: u64,
+}
+
+// The field order is relied on by the frontend's `StateMemory`
+impl Serialize for System {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut s = serializer.serialize_struct("gba_rs::Gba", 5)?;
+        s.serialize_field("cpu", &self.cpu)?;
+        s.serialize_field("mmu", &self.mmu)?;
+        s.serialize_field("io", &self.io)?;
+        s.serialize_field("ppu", &self.ppu)?;
+        s.serialize_field("frame", &self.frame)?;
+        s.end()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::thread;
+
+    use mmu::MemoryUnit;
+
+    // Runs a few frames with a marker in work RAM, giving the state after
+    fn run(marker: u8) -> Vec<u8> {
+        let mut sys = System::new(GameRom::default(), GameRom::default(), true);
+        sys.mmu.set8(0x2000000, marker);
+        for _ in 0..3 {
+            sys.emulate_frame();
+        }
+        assert_eq!(marker, sys.mmu.load8(0x2000000));
+        bincode::serialize(&*sys).unwrap()
+    }
+
+    #[test]
+    fn test_instances_on_threads() {
+        let threads: Vec<_> = (0..4).map(|i| thread::spawn(move || run(i))).collect();
+        let states: Vec<Vec<u8>> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+        for (i, state) in states.iter().enumerate() {
+            assert_eq!(&run(i as u8), state);
+        }
+        assert!(states[0] != states[1]);
+    }
+
+    // Frames don't end on a whole mix period, so the sound has to pick up
+    // where the state left it
+    #[test]
+    fn test_load_state_replays() {
+        let mut sys = System::new(GameRom::default(), GameRom::default(), true);
+        sys.emulate_frame();
+        let start = bincode::serialize(&*sys).unwrap();
+        let run = |sys: &mut System| {
+            sys.emulate_frame();
+            bincode::serialize(&*sys).unwrap()
+        };
+        let first = run(&mut sys);
+        sys.load_state(&start[..]).unwrap();
+        assert_eq!(first, run(&mut sys));
+    }
+}
@@ -0,0 +1,73 @@
+//! Playing in a terminal, for SSH sessions with no display to open a window
+//! on.  Runs without sound and without initialising SDL at all.
+
+use std::io;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use gba::InputMap;
+use io::ppu::COLS;
+use rom::GameRom;
+use system::{System, CYCLES_PER_FRAME, CYCLES_PER_SEC};
+use video::filter::{Filter, FilterOptions};
+
+use GBAError;
+use Result;
+
+mod render;
+mod tty;
+
+#[derive(Clone, Debug)]
+pub struct Options {
+    pub direct_boot: bool,
+    /// Only the keyboard bindings are used
+    pub input: InputMap,
+    pub filter: FilterOptions,
+    /// Frames drawn each second, the game still runs at full speed
+    pub fps: u32,
+}
+
+/// Runs until Ctrl-C or Escape is pressed
+pub fn run(rom: GameRom, bios: GameRom, opts: Options) -> Result<()> {
+    let mut sys = System::new(rom, bios, opts.direct_boot);
+    let mut filter = Filter::new(&opts.filter);
+    let mut keys = tty::Keys::new();
+    let mut tty = tty::Tty::open().map_err(GBAError::TerminalError)?;
+    let stdout = io::stdout();
+    let mut out = stdout.lock();
+
+    let frame_duration =
+        Duration::from_nanos((1_000_000_000u64 * CYCLES_PER_FRAME) / CYCLES_PER_SEC);
+    let full_speed = CYCLES_PER_SEC as f32 / CYCLES_PER_FRAME as f32;
+    // Emulated frames for each one drawn
+    let draw_every = (full_speed / opts.fps.max(1) as f32).round().max(1.0) as u64;
+    let mut next_frame = Instant::now();
+    loop {
+        let typed = tty.read().map_err(GBAError::TerminalError)?;
+        keys.feed(&typed, Instant::now());
+        if keys.quit() {
+            break;
+        }
+        let state = opts.input.keyboard_state(|code| keys.held(code));
+        sys.io.set_keyreg(&state);
+
+        sys.emulate_frame();
+        // Every frame goes through the filter so blending sees them all
+        let frame = filter.process(&sys.ppu.frame());
+        if sys.frame % draw_every == 0 {
+            let (cols, rows) = tty.size();
+            render::draw(&mut out, frame, COLS, cols, rows).map_err(GBAError::OutputError)?;
+        }
+
+        next_frame += frame_duration;
+        let now = Instant::now();
+        if next_frame > now {
+            thread::sleep(next_frame - now);
+        } else {
+            // Too far behind to catch up, so carry on from here rather than
+            // running flat out
+            next_frame = now;
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,99 @@
+//! Frames drawn as text, two pixels to a character: the top one in the
+//! upper half block's colour and the bottom one in the background's
+
+use std::fmt::Write as FmtWrite;
+use std::io::{self, Write};
+
+use video::scale::shrink;
+
+const UPPER_HALF: char = '\u{2580}';
+
+/// Draws a frame of 0x00RRGGBB pixels from the top left of the terminal,
+/// shrunk by whatever whole factor fits it in `cols` by `rows`
+pub fn draw<W: Write>(
+    out: &mut W,
+    frame: &[u32],
+    width: u32,
+    cols: u32,
+    rows: u32,
+) -> io::Result<()> {
+    out.write_all(frame_text(frame, width, cols, rows).as_bytes())?;
+    out.flush()
+}
+
+fn frame_text(frame: &[u32], width: u32, cols: u32, rows: u32) -> String {
+    let height = frame.len() as u32 / width;
+    let factor = ceil_div(width, cols.max(1)).max(ceil_div(height, 2 * rows.max(1)));
+    let (pixels, width, height) = shrink(frame, width, height, factor.max(1));
+
+    let mut text = String::from("\x1b[H");
+    for y in (0..height).step_by(2) {
+        // Colours carry on from one character to the next, so they're only
+        // set where they change
+        let mut colours = None;
+        for x in 0..width {
+            let top = pixels[(y * width + x) as usize];
+            let bottom = if y + 1 < height {
+                pixels[((y + 1) * width + x) as usize]
+            } else {
+                0
+            };
+            if colours.map_or(true, |(t, _)| t != top) {
+                let (r, g, b) = rgb(top);
+                write!(text, "\x1b[38;2;{};{};{}m", r, g, b).unwrap();
+            }
+            if colours.map_or(true, |(_, b)| b != bottom) {
+                let (r, g, b) = rgb(bottom);
+                write!(text, "\x1b[48;2;{};{};{}m", r, g, b).unwrap();
+            }
+            colours = Some((top, bottom));
+            text.push(UPPER_HALF);
+        }
+        // Clears whatever was right of the frame before a resize
+        text.push_str("\x1b[0m\x1b[K");
+        if y + 2 < height {
+            text.push_str("\r\n");
+        }
+    }
+    // And below it
+    text.push_str("\x1b[J");
+    text
+}
+
+fn rgb(p: u32) -> (u32, u32, u32) {
+    (p >> 16 & 0xff, p >> 8 & 0xff, p & 0xff)
+}
+
+fn ceil_div(a: u32, b: u32) -> u32 {
+    (a + b - 1) / b
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_half_blocks() {
+        let frame = [0xff0000, 0xff0000, 0x00ff00, 0x0000ff, 0xffffff, 0xffffff];
+        assert_eq!(
+            "\x1b[H\
+             \x1b[38;2;255;0;0m\x1b[48;2;0;255;0m\u{2580}\x1b[48;2;0;0;255m\u{2580}\
+             \x1b[0m\x1b[K\r\n\
+             \x1b[38;2;255;255;255m\x1b[48;2;0;0;0m\u{2580}\u{2580}\
+             \x1b[0m\x1b[K\x1b[J",
+            frame_text(&frame, 2, 80, 24)
+        );
+    }
+
+    #[test]
+    fn test_shrink_to_fit() {
+        let frame = vec![0x804020; 240 * 160];
+        let text = frame_text(&frame, 240, 80, 24);
+        // Shrunk by 4 to 60 by 40 pixels, taking 20 rows
+        assert_eq!(20, text.matches("\x1b[K").count());
+        assert_eq!(60 * 20, text.matches(UPPER_HALF).count());
+
+        let (pixels, w, h) = shrink(&[0x000000, 0x0000ff, 0xff0000, 0x00ff00], 2, 2, 2);
+        assert_eq!((vec![0x3f3f3f], 1, 1), (pixels, w, h));
+    }
+}
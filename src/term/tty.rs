@@ -0,0 +1,230 @@
+//! The terminal in raw mode, and the keys typed into it
+
+use std::io::{self, Write};
+use std::mem;
+use std::time::{Duration, Instant};
+
+use libc;
+use sdl2::keyboard::Scancode;
+
+// Terminals only send keys as they're typed and never say when they're let
+// go, so a key counts as held for this long after it last came in.  Keys
+// held down are sent again at the keyboard's repeat rate, which keeps them
+// held once the repeating starts.
+const HOLD_MILLIS: u64 = 150;
+
+// Switches to the alternate screen so the shell's scrollback is left alone,
+// and hides the cursor
+const ENTER: &'static str = "\x1b[?1049h\x1b[?25l\x1b[2J";
+const LEAVE: &'static str = "\x1b[0m\x1b[?25h\x1b[?1049l";
+
+/// Raw, non-blocking mode on stdin for as long as this is around, with the
+/// terminal set back how it was when dropped
+pub struct Tty {
+    saved: libc::termios,
+}
+
+impl Tty {
+    pub fn open() -> io::Result<Self> {
+        let saved = unsafe {
+            let mut attrs: libc::termios = mem::zeroed();
+            if libc::tcgetattr(libc::STDIN_FILENO, &mut attrs) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            let saved = attrs;
+            libc::cfmakeraw(&mut attrs);
+            // Reads return straight away with whatever's been typed
+            attrs.c_cc[libc::VMIN] = 0;
+            attrs.c_cc[libc::VTIME] = 0;
+            if libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &attrs) != 0 {
+                return Err(io::Error::last_os_error());
+            }
+            saved
+        };
+        let tty = Tty { saved: saved };
+        let mut out = io::stdout();
+        out.write_all(ENTER.as_bytes())?;
+        out.flush()?;
+        Ok(tty)
+    }
+
+    /// The terminal's size in columns and rows
+    pub fn size(&self) -> (u32, u32) {
+        unsafe {
+            let mut size: libc::winsize = mem::zeroed();
+            if libc::ioctl(libc::STDOUT_FILENO, libc::TIOCGWINSZ, &mut size) == 0
+                && size.ws_col > 0
+                && size.ws_row > 0
+            {
+                (size.ws_col as u32, size.ws_row as u32)
+            } else {
+                (80, 24)
+            }
+        }
+    }
+
+    /// Everything typed since the last read
+    pub fn read(&mut self) -> io::Result<Vec<u8>> {
+        let mut bytes = vec![];
+        let mut buf = [0u8; 64];
+        loop {
+            let n = unsafe {
+                libc::read(
+                    libc::STDIN_FILENO,
+                    buf.as_mut_ptr() as *mut libc::c_void,
+                    buf.len(),
+                )
+            };
+            if n < 0 {
+                let err = io::Error::last_os_error();
+                if err.kind() == io::ErrorKind::Interrupted {
+                    continue;
+                }
+                return Err(err);
+            }
+            if n == 0 {
+                return Ok(bytes);
+            }
+            bytes.extend_from_slice(&buf[..n as usize]);
+        }
+    }
+}
+
+impl Drop for Tty {
+    fn drop(&mut self) {
+        let mut out = io::stdout();
+        let _ = out.write_all(LEAVE.as_bytes());
+        let _ = out.flush();
+        unsafe {
+            libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &self.saved);
+        }
+    }
+}
+
+/// The keys typed recently, as SDL scancodes so the keyboard bindings of an
+/// `InputMap` work the same as in the window
+pub struct Keys {
+    last_seen: Vec<(Scancode, Instant)>,
+    quit: bool,
+}
+
+impl Keys {
+    pub fn new() -> Self {
+        Keys {
+            last_seen: vec![],
+            quit: false,
+        }
+    }
+
+    /// Takes in the bytes read from the terminal at `now`
+    pub fn feed(&mut self, bytes: &[u8], now: Instant) {
+        let (codes, quit) = parse(bytes);
+        self.quit |= quit;
+        for code in codes {
+            match self.last_seen.iter_mut().find(|&&mut (c, _)| c == code) {
+                Some(seen) => seen.1 = now,
+                None => self.last_seen.push((code, now)),
+            }
+        }
+        let hold = Duration::from_millis(HOLD_MILLIS);
+        self.last_seen.retain(|&(_, seen)| now - seen <= hold);
+    }
+
+    pub fn held(&self, code: Scancode) -> bool {
+        self.last_seen.iter().any(|&(c, _)| c == code)
+    }
+
+    /// Whether Ctrl-C or Escape has been pressed
+    pub fn quit(&self) -> bool {
+        self.quit
+    }
+}
+
+// The keys in a run of terminal input, and whether it asks to quit
+fn parse(bytes: &[u8]) -> (Vec<Scancode>, bool) {
+    let mut codes = vec![];
+    let mut quit = false;
+    let mut i = 0;
+    while i < bytes.len() {
+        let b = bytes[i];
+        i += 1;
+        let code = match b {
+            0x03 => {
+                quit = true;
+                None
+            }
+            // An escape on its own is the key, otherwise it starts a sequence
+            // like the arrow keys send
+            0x1b if i == bytes.len() => {
+                quit = true;
+                None
+            }
+            0x1b => {
+                let (code, len) = escape_sequence(&bytes[i..]);
+                i += len;
+                code
+            }
+            b'a'..=b'z' => Scancode::from_i32(Scancode::A as i32 + (b - b'a') as i32),
+            b'A'..=b'Z' => Scancode::from_i32(Scancode::A as i32 + (b - b'A') as i32),
+            b'0' => Some(Scancode::Num0),
+            b'1'..=b'9' => Scancode::from_i32(Scancode::Num1 as i32 + (b - b'1') as i32),
+            b' ' => Some(Scancode::Space),
+            b'\r' | b'\n' => Some(Scancode::Return),
+            b'\t' => Some(Scancode::Tab),
+            0x08 | 0x7f => Some(Scancode::Backspace),
+            b'-' => Some(Scancode::Minus),
+            b'`' => Some(Scancode::Grave),
+            _ => None,
+        };
+        codes.extend(code);
+    }
+    (codes, quit)
+}
+
+// The key for the sequence after an escape, and how many bytes it took up.
+// Sequences that aren't arrow keys are skipped.
+fn escape_sequence(bytes: &[u8]) -> (Option<Scancode>, usize) {
+    match bytes.first() {
+        Some(&b'[') | Some(&b'O') => {}
+        _ => return (None, 0),
+    }
+    // Parameters, then a final byte from @ to ~
+    let end = bytes[1..]
+        .iter()
+        .position(|b| (0x40..=0x7e).contains(b))
+        .map_or(bytes.len(), |pos| pos + 2);
+    let code = match bytes.get(end - 1) {
+        Some(&b'A') if end == 2 => Some(Scancode::Up),
+        Some(&b'B') if end == 2 => Some(Scancode::Down),
+        Some(&b'C') if end == 2 => Some(Scancode::Right),
+        Some(&b'D') if end == 2 => Some(Scancode::Left),
+        _ => None,
+    };
+    (code, end)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        use self::Scancode::*;
+        assert_eq!((vec![W, A, Num1, Num0, Space], false), parse(b"wA10 "));
+        assert_eq!((vec![Up, Left, X], false), parse(b"\x1b[A\x1bOD\x1b[1;5Cx"));
+        assert_eq!((vec![Z], true), parse(b"z\x03"));
+        assert_eq!((vec![], true), parse(b"\x1b"));
+    }
+
+    #[test]
+    fn test_held() {
+        let mut keys = Keys::new();
+        let start = Instant::now();
+        keys.feed(b"l", start);
+        keys.feed(b"k", start + Duration::from_millis(100));
+        assert!(keys.held(Scancode::L) && keys.held(Scancode::K));
+
+        keys.feed(b"", start + Duration::from_millis(200));
+        assert!(!keys.held(Scancode::L) && keys.held(Scancode::K));
+    }
+}
@@ -0,0 +1,260 @@
+//! Making frames look like they do on the GBA's screen
+
+/// How the GBA's colours are mapped to the monitor's
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ColourCorrection {
+    /// The colours as the game stored them
+    Off,
+    /// Pokefan531's GBA matrix, dimmer and less saturated
+    Gba,
+    /// higan's, which models the LCD's steep gamma and washes colours out
+    /// further
+    Lcd,
+}
+
+/// What of the previous frames shows through, like the GBA's slow LCD
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum FrameBlend {
+    Off,
+    /// Averages each frame with the one before, so effects that flicker at
+    /// 30 Hz for transparency come out transparent
+    Mix,
+    /// Each frame fades out over the ones after, keeping this much of the
+    /// last output each frame, from 0 to 1
+    Ghost(f32),
+}
+
+#[derive(Clone, Debug)]
+pub struct FilterOptions {
+    pub colour: ColourCorrection,
+    /// Overrides the correction's LCD gamma, higher is darker
+    pub lcd_gamma: Option<f32>,
+    pub blend: FrameBlend,
+}
+
+impl Default for FilterOptions {
+    fn default() -> Self {
+        FilterOptions {
+            colour: ColourCorrection::Off,
+            lcd_gamma: None,
+            blend: FrameBlend::Off,
+        }
+    }
+}
+
+// Colours are converted to linear light with the LCD's gamma, mixed by the
+// matrix, scaled and converted back with the monitor's gamma
+struct Correction {
+    matrix: [[f32; 3]; 3],
+    lcd_gamma: f32,
+    out_gamma: f32,
+    scale: f32,
+}
+
+impl ColourCorrection {
+    fn correction(self) -> Correction {
+        match self {
+            ColourCorrection::Off => Correction {
+                matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+                lcd_gamma: 2.2,
+                out_gamma: 2.2,
+                scale: 1.0,
+            },
+            ColourCorrection::Gba => Correction {
+                matrix: [
+                    [0.82, 0.24, -0.06],
+                    [0.125, 0.665, 0.21],
+                    [0.195, 0.075, 0.73],
+                ],
+                lcd_gamma: 2.2,
+                out_gamma: 2.2,
+                scale: 0.94,
+            },
+            ColourCorrection::Lcd => Correction {
+                matrix: [
+                    [1.0, 50.0 / 255.0, 0.0],
+                    [10.0 / 255.0, 230.0 / 255.0, 30.0 / 255.0],
+                    [50.0 / 255.0, 10.0 / 255.0, 220.0 / 255.0],
+                ],
+                lcd_gamma: 4.0,
+                out_gamma: 2.2,
+                scale: 255.0 / 280.0,
+            },
+        }
+    }
+}
+
+/// Post-processing between the PPU's frames and the screen.  Frames are
+/// packed as 0x00RRGGBB the way the PPU draws them, each channel widened from
+/// the GBA's 5 bits.
+pub struct Filter {
+    // The output colour for every BGR555 colour, None leaves them as drawn
+    lut: Option<Vec<u32>>,
+    blend: FrameBlend,
+    // The previous frame when mixing, the previous output when ghosting
+    prev: Vec<u32>,
+    out: Vec<u32>,
+}
+
+impl Filter {
+    pub fn new(opts: &FilterOptions) -> Self {
+        Filter {
+            lut: build_lut(opts),
+            blend: opts.blend,
+            prev: vec![],
+            out: vec![],
+        }
+    }
+
+    /// Forgets the frames before, which blending would otherwise carry over
+    pub fn reset(&mut self) {
+        self.prev.clear();
+        self.out.clear();
+    }
+
+    /// The last frame processed
+    pub fn last(&self) -> &[u32] {
+        &self.out
+    }
+
+    /// The frame as it should be shown
+    pub fn process(&mut self, frame: &[u32]) -> &[u32] {
+        self.out.clear();
+        match self.lut {
+            Some(ref lut) => self.out.extend(frame.iter().map(|&p| lut[bgr555(p)])),
+            None => self.out.extend_from_slice(frame),
+        }
+        if self.prev.len() != self.out.len() {
+            self.prev = self.out.clone();
+        }
+
+        match self.blend {
+            FrameBlend::Off => {}
+            FrameBlend::Mix => {
+                for (out, prev) in self.out.iter_mut().zip(self.prev.iter_mut()) {
+                    let cur = *out;
+                    *out = mix(cur, *prev, 128);
+                    *prev = cur;
+                }
+            }
+            FrameBlend::Ghost(persistence) => {
+                let weight = (persistence.max(0.0).min(1.0) * 256.0) as u32;
+                for (out, prev) in self.out.iter_mut().zip(self.prev.iter_mut()) {
+                    *out = mix(*out, *prev, weight);
+                    *prev = *out;
+                }
+            }
+        }
+        &self.out
+    }
+}
+
+fn build_lut(opts: &FilterOptions) -> Option<Vec<u32>> {
+    let mut c = opts.colour.correction();
+    if opts.colour == ColourCorrection::Off && opts.lcd_gamma.is_none() {
+        return None;
+    }
+    if let Some(gamma) = opts.lcd_gamma {
+        c.lcd_gamma = gamma;
+    }
+
+    let linear: Vec<f32> = (0..32)
+        .map(|v| (v as f32 / 31.0).powf(c.lcd_gamma))
+        .collect();
+    let lut = (0..0x8000)
+        .map(|colour| {
+            let rgb = [
+                linear[colour & 31],
+                linear[colour >> 5 & 31],
+                linear[colour >> 10 & 31],
+            ];
+            let mut packed = 0;
+            for row in c.matrix.iter() {
+                let v = (row[0] * rgb[0] + row[1] * rgb[1] + row[2] * rgb[2]) * c.scale;
+                let v = v.max(0.0).min(1.0).powf(1.0 / c.out_gamma);
+                packed = packed << 8 | (v * 255.0).round() as u32;
+            }
+            packed
+        })
+        .collect();
+    Some(lut)
+}
+
+// Back to the GBA's BGR555 from the top bits of each channel, which the
+// PPU's widening leaves as they were
+fn bgr555(p: u32) -> usize {
+    (p >> 19 & 31 | (p >> 11 & 31) << 5 | (p >> 3 & 31) << 10) as usize
+}
+
+// a with `weight` 256ths of b mixed in, for each channel
+pub(super) fn mix(a: u32, b: u32, weight: u32) -> u32 {
+    let mut out = 0;
+    for shift in [16, 8, 0].iter() {
+        let ca = (a >> shift & 0xff) as i32;
+        let cb = (b >> shift & 0xff) as i32;
+        let c = ca + (((cb - ca) * weight as i32) >> 8);
+        out |= (c as u32) << shift;
+    }
+    out
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use io::ppu::colour_rgb;
+
+    fn filter(colour: ColourCorrection, blend: FrameBlend) -> Filter {
+        Filter::new(&FilterOptions {
+            colour: colour,
+            lcd_gamma: None,
+            blend: blend,
+        })
+    }
+
+    #[test]
+    fn test_passthrough() {
+        let mut f = filter(ColourCorrection::Off, FrameBlend::Off);
+        let frame: Vec<u32> = [0x7fff, 0, 0x1f, 0x30]
+            .iter()
+            .map(|&c| colour_rgb(c))
+            .collect();
+        assert_eq!(&[0xffffff, 0x000000, 0xff0000, 0x840800], f.process(&frame));
+    }
+
+    #[test]
+    fn test_colour_correction() {
+        let frame: Vec<u32> = [0x7fff, 0x1f, 0].iter().map(|&c| colour_rgb(c)).collect();
+        for &colour in [ColourCorrection::Gba, ColourCorrection::Lcd].iter() {
+            let mut f = filter(colour, FrameBlend::Off);
+            let out = f.process(&frame).to_vec();
+            // White's dimmed, pure red bleeds into the other channels, and
+            // black stays black
+            assert_ne!(0xffffff, out[0], "{:?}", colour);
+            assert!(out[1] & 0xff > 0, "{:?}", colour);
+            assert_eq!(0, out[2], "{:?}", colour);
+        }
+
+        // Only changing the gamma keeps the ends where they are
+        let mut f = Filter::new(&FilterOptions {
+            colour: ColourCorrection::Off,
+            lcd_gamma: Some(2.2),
+            blend: FrameBlend::Off,
+        });
+        assert_eq!(&[0xffffff, 0xff0000, 0], f.process(&frame));
+    }
+
+    #[test]
+    fn test_frame_blend() {
+        let white = colour_rgb(0x7fff);
+        let mut f = filter(ColourCorrection::Off, FrameBlend::Mix);
+        assert_eq!(&[0xffffff], f.process(&[white]));
+        assert_eq!(&[0x7f7f7f], f.process(&[0x000000]));
+        assert_eq!(&[0x7f7f7f], f.process(&[white]));
+
+        let mut f = filter(ColourCorrection::Off, FrameBlend::Ghost(0.5));
+        f.process(&[white]);
+        assert_eq!(&[0x7f7f7f], f.process(&[0x000000]));
+        assert_eq!(&[0x3f3f3f], f.process(&[0x000000]));
+    }
+}
@@ -0,0 +1,10 @@
+//! What happens to emulated frames once they're drawn: post-processing and
+//! upscaling them for the screen with text drawn over, and recording them as
+//! video
+
+pub mod filter;
+pub mod font;
+pub mod osd;
+pub mod record;
+pub mod scale;
+pub mod y4m;
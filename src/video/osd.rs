@@ -0,0 +1,198 @@
+//! Text drawn over the frame: messages that disappear after a few seconds,
+//! and optionally stats in the corner
+
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+use super::filter::mix;
+use super::font::{draw_text, text_width, ADVANCE, GLYPH_HEIGHT};
+
+const MESSAGE_SECS: u64 = 3;
+// Older messages are dropped once there are more than this
+const MAX_MESSAGES: usize = 4;
+
+// Sizes are in frame pixels, scaled up with the frame
+const MARGIN: u32 = 3;
+const LINE_HEIGHT: u32 = GLYPH_HEIGHT + 3;
+
+const MESSAGE_COLOUR: u32 = 0xffffff;
+const STATS_COLOUR: u32 = 0xffff80;
+// How much of the frame shows through behind text, out of 256
+const BACKDROP_LEVEL: u32 = 96;
+
+pub struct Osd {
+    messages: VecDeque<(String, Instant)>,
+    show_stats: bool,
+    stats: Vec<String>,
+    // When each frame in the last second was run
+    frames: VecDeque<Instant>,
+}
+
+impl Osd {
+    pub fn new(show_stats: bool) -> Self {
+        Osd {
+            messages: VecDeque::new(),
+            show_stats: show_stats,
+            stats: vec![],
+            frames: VecDeque::new(),
+        }
+    }
+
+    /// Shows a message in the bottom left for a few seconds
+    pub fn message(&mut self, text: String) {
+        if self.messages.len() == MAX_MESSAGES {
+            self.messages.pop_front();
+        }
+        self.messages.push_back((text, Instant::now()));
+    }
+
+    pub fn toggle_stats(&mut self) {
+        self.show_stats = !self.show_stats;
+    }
+
+    pub fn stats_shown(&self) -> bool {
+        self.show_stats
+    }
+
+    /// Replaces the lines of stats shown in the top left
+    pub fn set_stats(&mut self, lines: Vec<String>) {
+        self.stats = lines;
+    }
+
+    /// Counts an emulated frame for the frame rate
+    pub fn frame_run(&mut self, now: Instant) {
+        self.frames.push_back(now);
+        self.forget_frames(now);
+    }
+
+    /// Emulated frames run over the last second
+    pub fn fps(&mut self, now: Instant) -> usize {
+        self.forget_frames(now);
+        self.frames.len()
+    }
+
+    fn forget_frames(&mut self, now: Instant) {
+        let second = Duration::from_secs(1);
+        while self.frames.front().map_or(false, |&t| now - t > second) {
+            self.frames.pop_front();
+        }
+    }
+
+    /// Draws over a frame that's been scaled up `scale` times.  Text that
+    /// doesn't fit is cut off.
+    pub fn draw(&mut self, pixels: &mut [u32], width: u32, scale: u32, now: Instant) {
+        let timeout = Duration::from_secs(MESSAGE_SECS);
+        while self
+            .messages
+            .front()
+            .map_or(false, |&(_, shown)| now - shown > timeout)
+        {
+            self.messages.pop_front();
+        }
+
+        let height = pixels.len() as u32 / width;
+        if self.show_stats {
+            for (i, line) in self.stats.iter().enumerate() {
+                let y = (MARGIN + i as u32 * LINE_HEIGHT) * scale;
+                draw_line(pixels, width, scale, y, line, STATS_COLOUR);
+            }
+        }
+        let bottom = height / scale - MARGIN - GLYPH_HEIGHT;
+        for (i, (text, _)) in self.messages.iter().rev().enumerate() {
+            let y = match bottom.checked_sub(i as u32 * LINE_HEIGHT) {
+                Some(y) => y * scale,
+                None => break,
+            };
+            draw_line(pixels, width, scale, y, text, MESSAGE_COLOUR);
+        }
+    }
+}
+
+// Draws a line of text at the left margin, darkening the frame behind it so
+// it stands out against anything
+fn draw_line(pixels: &mut [u32], width: u32, scale: u32, y: u32, text: &str, colour: u32) {
+    let x = MARGIN * scale;
+    let fits = ((width / scale).saturating_sub(2 * MARGIN) + 1) / ADVANCE;
+    let text: String = text.chars().take(fits as usize).collect();
+    if text.is_empty() {
+        return;
+    }
+
+    let height = pixels.len() as u32 / width;
+    let (left, top) = (x - scale, y.saturating_sub(scale));
+    let right = (x + text_width(&text, scale) + scale).min(width);
+    let bottom = (y + (GLYPH_HEIGHT + 1) * scale).min(height);
+    for j in top..bottom {
+        for i in left..right {
+            let p = &mut pixels[(j * width + i) as usize];
+            *p = mix(0, *p, BACKDROP_LEVEL);
+        }
+    }
+    draw_text(pixels, width, x, y, scale, &text, colour);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const WIDTH: u32 = 60;
+    const HEIGHT: u32 = 40;
+
+    fn lit_rows(pixels: &[u32]) -> Vec<u32> {
+        (0..HEIGHT)
+            .filter(|&y| {
+                pixels[(y * WIDTH) as usize..((y + 1) * WIDTH) as usize]
+                    .iter()
+                    .any(|&p| p == MESSAGE_COLOUR || p == STATS_COLOUR)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_messages() {
+        let mut osd = Osd::new(false);
+        osd.message("saved".to_string());
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        let now = Instant::now();
+        osd.draw(&mut pixels, WIDTH, 1, now);
+        let rows = lit_rows(&pixels);
+        assert_eq!(HEIGHT - MARGIN - 1, *rows.last().unwrap());
+        assert!(*rows.first().unwrap() >= HEIGHT - MARGIN - GLYPH_HEIGHT);
+
+        // Gone once they've been shown long enough
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        osd.draw(&mut pixels, WIDTH, 1, now + Duration::from_secs(4));
+        assert!(lit_rows(&pixels).is_empty());
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut osd = Osd::new(false);
+        osd.set_stats(vec![
+            "60 FPS".to_string(),
+            "a very long line of stats".to_string(),
+        ]);
+        let mut pixels = vec![0; (WIDTH * HEIGHT) as usize];
+        osd.draw(&mut pixels, WIDTH, 1, Instant::now());
+        assert!(lit_rows(&pixels).is_empty());
+
+        osd.toggle_stats();
+        osd.draw(&mut pixels, WIDTH, 1, Instant::now());
+        let rows = lit_rows(&pixels);
+        assert_eq!(MARGIN, rows[0]);
+        assert_eq!(
+            MARGIN + LINE_HEIGHT + GLYPH_HEIGHT - 1,
+            *rows.last().unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fps() {
+        let mut osd = Osd::new(false);
+        let start = Instant::now();
+        for i in 0..90 {
+            osd.frame_run(start + Duration::from_millis(i * 1000 / 60));
+        }
+        assert_eq!(60, osd.fps(start + Duration::from_millis(1500)));
+    }
+}
@@ -0,0 +1,113 @@
+//! Writing every emulated frame out as video
+
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use png;
+
+use super::y4m::Y4mWriter;
+
+/// Where the frames go, picked by the file's extension
+enum Output {
+    Y4m(Y4mWriter<Box<dyn Write>>),
+    // Numbered PNGs next to the given path, the next one to write
+    Png(u64),
+}
+
+/// A video of the frames passed in, one video frame per emulated frame.
+/// Frames are timed by the emulated clock rather than the wall clock, so the
+/// video plays at the GBA's own rate however fast it was run.
+pub struct Recording {
+    path: PathBuf,
+    width: u32,
+    height: u32,
+    output: Output,
+}
+
+impl Recording {
+    /// Starts a video at `path`: `.y4m` writes a YUV4MPEG2 stream, `-` writes
+    /// one to stdout for piping to an encoder, and `.png` writes a sequence of
+    /// numbered images like `frames.000000.png`.  Frames last `den / num`
+    /// seconds.  Stdout is held for as long as the recording, nothing else
+    /// should print there.
+    pub fn create(path: &Path, width: u32, height: u32, num: u64, den: u64) -> io::Result<Self> {
+        let ext = path
+            .extension()
+            .map(|ext| ext.to_string_lossy().to_lowercase());
+        let output = if path == Path::new("-") {
+            let out: Box<dyn Write> = Box::new(BufWriter::new(io::stdout().lock()));
+            Output::Y4m(Y4mWriter::new(out, width, height, num, den)?)
+        } else {
+            match ext.as_ref().map(|ext| ext.as_str()) {
+                Some("y4m") => {
+                    let out: Box<dyn Write> = Box::new(BufWriter::new(File::create(path)?));
+                    Output::Y4m(Y4mWriter::new(out, width, height, num, den)?)
+                }
+                Some("png") => Output::Png(0),
+                _ => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidInput,
+                        "video must be a .y4m or .png file, or - for stdout",
+                    ))
+                }
+            }
+        };
+        Ok(Recording {
+            path: path.to_path_buf(),
+            width: width,
+            height: height,
+            output: output,
+        })
+    }
+
+    pub fn write(&mut self, pixels: &[u32]) -> io::Result<()> {
+        match self.output {
+            Output::Y4m(ref mut y4m) => y4m.write_frame(pixels),
+            Output::Png(ref mut next) => {
+                png::save_rgb(
+                    &frame_path(&self.path, *next),
+                    self.width,
+                    self.height,
+                    pixels,
+                )?;
+                *next += 1;
+                Ok(())
+            }
+        }
+    }
+
+    /// Flushes what's left to write, returning the path recorded to
+    pub fn finish(self) -> io::Result<PathBuf> {
+        if let Output::Y4m(y4m) = self.output {
+            y4m.finish()?;
+        }
+        Ok(self.path)
+    }
+}
+
+fn frame_path(path: &Path, frame: u64) -> PathBuf {
+    let ext = path.extension().unwrap().to_string_lossy();
+    path.with_extension(format!("{:06}.{}", frame, ext))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_frame_path() {
+        assert_eq!(
+            Path::new("out/run.000012.png"),
+            frame_path(Path::new("out/run.png"), 12)
+        );
+    }
+
+    #[test]
+    fn test_unknown_format() {
+        let err = Recording::create(Path::new("run.mp4"), 240, 160, 60, 1)
+            .err()
+            .unwrap();
+        assert_eq!(io::ErrorKind::InvalidInput, err.kind());
+    }
+}
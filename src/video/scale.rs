@@ -0,0 +1,589 @@
+//! Upscaling frames on the CPU before they're shown, and fitting them into
+//! the window
+
+use super::filter::mix;
+
+/// How frames are blown up before the window stretches them to fit
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Scaler {
+    /// Left to the window, which stretches the frame smoothly
+    Off,
+    /// Each pixel becomes a square block, by the display options' factor
+    Nearest,
+    /// AdvMAME's edge-following 2x and 3x
+    Scale2x,
+    Scale3x,
+    /// Hyllian's 2xBR, which blends along edges to smooth diagonals
+    Xbr,
+}
+
+impl Scaler {
+    /// The next one along, for switching between them while running
+    pub fn next(self) -> Scaler {
+        match self {
+            Scaler::Off => Scaler::Nearest,
+            Scaler::Nearest => Scaler::Scale2x,
+            Scaler::Scale2x => Scaler::Scale3x,
+            Scaler::Scale3x => Scaler::Xbr,
+            Scaler::Xbr => Scaler::Off,
+        }
+    }
+}
+
+/// Darkening between the upscaled pixels, like the gaps in the GBA's LCD
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mask {
+    Off,
+    /// Dark bottom row for each pixel
+    Scanlines,
+    /// Dark bottom row and right column for each pixel
+    Grid,
+}
+
+impl Mask {
+    pub fn next(self) -> Mask {
+        match self {
+            Mask::Off => Mask::Scanlines,
+            Mask::Scanlines => Mask::Grid,
+            Mask::Grid => Mask::Off,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DisplayOptions {
+    pub scaler: Scaler,
+    /// How many times Nearest scales up
+    pub factor: u32,
+    pub mask: Mask,
+    /// Only show the frame at whole multiples of its size
+    pub integer_scale: bool,
+    /// Keep the frame's shape, leaving black bars around it
+    pub keep_aspect: bool,
+}
+
+impl Default for DisplayOptions {
+    fn default() -> Self {
+        DisplayOptions {
+            scaler: Scaler::Off,
+            factor: 3,
+            mask: Mask::Off,
+            integer_scale: false,
+            keep_aspect: true,
+        }
+    }
+}
+
+// What's left of a pixel's brightness under the mask, out of 256
+const SCANLINE_LEVEL: u32 = 128;
+const GRID_LEVEL: u32 = 192;
+
+/// Upscales frames by the scaler and mask picked, which can be changed
+/// between frames.  Frames are packed as 0x00RRGGBB.
+pub struct Upscaler {
+    opts: DisplayOptions,
+    out: Vec<u32>,
+}
+
+impl Upscaler {
+    pub fn new(opts: &DisplayOptions) -> Self {
+        Upscaler {
+            opts: opts.clone(),
+            out: vec![],
+        }
+    }
+
+    pub fn scaler(&self) -> Scaler {
+        self.opts.scaler
+    }
+
+    pub fn mask(&self) -> Mask {
+        self.opts.mask
+    }
+
+    pub fn next_scaler(&mut self) {
+        self.opts.scaler = self.opts.scaler.next();
+    }
+
+    pub fn next_mask(&mut self) {
+        self.opts.mask = self.opts.mask.next();
+    }
+
+    /// How many times frames are scaled up
+    pub fn factor(&self) -> u32 {
+        match self.opts.scaler {
+            Scaler::Off => 1,
+            Scaler::Nearest => self.opts.factor,
+            Scaler::Scale2x | Scaler::Xbr => 2,
+            Scaler::Scale3x => 3,
+        }
+    }
+
+    /// Scales up a frame, returning it with its new width and height
+    pub fn scale(&mut self, frame: &[u32], width: u32, height: u32) -> (&mut [u32], u32, u32) {
+        let n = self.factor();
+        let src = Frame {
+            pixels: frame,
+            width: width as i32,
+            height: height as i32,
+        };
+        self.out.clear();
+        self.out.resize((width * height * n * n) as usize, 0);
+        match self.opts.scaler {
+            Scaler::Off => self.out.copy_from_slice(frame),
+            Scaler::Nearest => nearest(&src, n, &mut self.out),
+            Scaler::Scale2x => scale2x(&src, &mut self.out),
+            Scaler::Scale3x => scale3x(&src, &mut self.out),
+            Scaler::Xbr => xbr2x(&src, &mut self.out),
+        }
+        if n > 1 {
+            apply_mask(self.opts.mask, n, width * n, &mut self.out);
+        }
+        (&mut self.out, width * n, height * n)
+    }
+
+    /// Where the frame goes in a window of the given size, as x, y, width
+    /// and height
+    pub fn frame_rect(&self, window: (u32, u32), frame: (u32, u32)) -> (i32, i32, u32, u32) {
+        frame_rect(
+            window,
+            frame,
+            self.opts.integer_scale,
+            self.opts.keep_aspect,
+        )
+    }
+}
+
+fn frame_rect(
+    (win_w, win_h): (u32, u32),
+    (w, h): (u32, u32),
+    integer: bool,
+    keep_aspect: bool,
+) -> (i32, i32, u32, u32) {
+    let (out_w, out_h) = match (integer, keep_aspect) {
+        (true, true) => {
+            let n = (win_w / w).min(win_h / h).max(1);
+            (w * n, h * n)
+        }
+        (true, false) => (w * (win_w / w).max(1), h * (win_h / h).max(1)),
+        (false, true) => {
+            let s = (win_w as f32 / w as f32).min(win_h as f32 / h as f32);
+            ((w as f32 * s) as u32, (h as f32 * s) as u32)
+        }
+        (false, false) => (win_w, win_h),
+    };
+    (
+        (win_w as i32 - out_w as i32) / 2,
+        (win_h as i32 - out_h as i32) / 2,
+        out_w,
+        out_h,
+    )
+}
+
+struct Frame<'a> {
+    pixels: &'a [u32],
+    width: i32,
+    height: i32,
+}
+
+impl<'a> Frame<'a> {
+    // The pixel at (x, y), with the edge pixels carried on past the edges
+    #[inline]
+    fn at(&self, x: i32, y: i32) -> u32 {
+        let x = x.max(0).min(self.width - 1);
+        let y = y.max(0).min(self.height - 1);
+        self.pixels[(y * self.width + x) as usize]
+    }
+}
+
+fn nearest(src: &Frame, n: u32, out: &mut [u32]) {
+    let out_w = src.width as u32 * n;
+    for (i, p) in out.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % out_w / n, i as u32 / out_w / n);
+        *p = src.at(x as i32, y as i32);
+    }
+}
+
+fn scale2x(src: &Frame, out: &mut [u32]) {
+    let out_w = src.width as usize * 2;
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let e = src.at(x, y);
+            let (b, d, f, h) = (
+                src.at(x, y - 1),
+                src.at(x - 1, y),
+                src.at(x + 1, y),
+                src.at(x, y + 1),
+            );
+            let mut block = [e; 4];
+            if b != h && d != f {
+                if d == b {
+                    block[0] = d;
+                }
+                if b == f {
+                    block[1] = f;
+                }
+                if d == h {
+                    block[2] = d;
+                }
+                if h == f {
+                    block[3] = f;
+                }
+            }
+            let i = y as usize * 2 * out_w + x as usize * 2;
+            out[i..i + 2].copy_from_slice(&block[0..2]);
+            out[i + out_w..i + out_w + 2].copy_from_slice(&block[2..4]);
+        }
+    }
+}
+
+fn scale3x(src: &Frame, out: &mut [u32]) {
+    let out_w = src.width as usize * 3;
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let (a, b, c) = (src.at(x - 1, y - 1), src.at(x, y - 1), src.at(x + 1, y - 1));
+            let (d, e, f) = (src.at(x - 1, y), src.at(x, y), src.at(x + 1, y));
+            let (g, h, i) = (src.at(x - 1, y + 1), src.at(x, y + 1), src.at(x + 1, y + 1));
+            let mut block = [e; 9];
+            if b != h && d != f {
+                let pick = |cond: bool, p: u32| if cond { p } else { e };
+                block[0] = pick(d == b, d);
+                block[1] = pick((d == b && e != c) || (b == f && e != a), b);
+                block[2] = pick(b == f, f);
+                block[3] = pick((d == b && e != g) || (d == h && e != a), d);
+                block[5] = pick((b == f && e != i) || (h == f && e != c), f);
+                block[6] = pick(d == h, d);
+                block[7] = pick((d == h && e != i) || (h == f && e != g), h);
+                block[8] = pick(h == f, f);
+            }
+            let start = y as usize * 3 * out_w + x as usize * 3;
+            for row in 0..3 {
+                let i = start + row * out_w;
+                out[i..i + 3].copy_from_slice(&block[row * 3..row * 3 + 3]);
+            }
+        }
+    }
+}
+
+// Colours closer than this are treated as the same by xBR
+const XBR_EQ: u32 = 155;
+
+// The neighbours xBR looks at for each corner, as offsets in the orientation
+// where the corner being smoothed is the bottom right:
+//
+//       .  .  .
+//    .  .  PB .  .
+//    .  PD PE PF F4
+//    .  PG PH PI I4
+//       .  H5 I5
+const PE: usize = 0;
+const PI: usize = 1;
+const PH: usize = 2;
+const PF: usize = 3;
+const PG: usize = 4;
+const PC: usize = 5;
+const PD: usize = 6;
+const PB: usize = 7;
+const F4: usize = 8;
+const I4: usize = 9;
+const H5: usize = 10;
+const I5: usize = 11;
+const XBR_OFFSETS: [(i32, i32); 12] = [
+    (0, 0),
+    (1, 1),
+    (0, 1),
+    (1, 0),
+    (-1, 1),
+    (1, -1),
+    (-1, 0),
+    (0, -1),
+    (2, 0),
+    (2, 1),
+    (0, 2),
+    (1, 2),
+];
+
+// Offset (x, y) turned a quarter turn anticlockwise `turns` times
+fn rotate((x, y): (i32, i32), turns: u32) -> (i32, i32) {
+    match turns {
+        0 => (x, y),
+        1 => (y, -x),
+        2 => (-x, -y),
+        _ => (-y, x),
+    }
+}
+
+// Index in the 2x2 output block of the corner in the direction of (x, y)
+fn corner((x, y): (i32, i32)) -> usize {
+    (if x > 0 { 1 } else { 0 }) + (if y > 0 { 2 } else { 0 })
+}
+
+// A luma/chroma distance between two colours, which is closer to how
+// different they look than comparing RGB
+fn yuv_diff(a: (i32, i32, i32), b: (i32, i32, i32)) -> u32 {
+    ((a.0 - b.0).abs() + (a.1 - b.1).abs() + (a.2 - b.2).abs()) as u32
+}
+
+fn yuv(p: u32) -> (i32, i32, i32) {
+    let r = (p >> 16 & 0xff) as f32;
+    let g = (p >> 8 & 0xff) as f32;
+    let b = (p & 0xff) as f32;
+    (
+        (0.299 * r + 0.587 * g + 0.114 * b) as i32,
+        (-0.169 * r - 0.331 * g + 0.5 * b) as i32,
+        (0.5 * r - 0.419 * g - 0.081 * b) as i32,
+    )
+}
+
+fn xbr2x(src: &Frame, out: &mut [u32]) {
+    let yuvs: Vec<_> = src.pixels.iter().map(|&p| yuv(p)).collect();
+    let yuv_src = YuvFrame {
+        yuvs: &yuvs,
+        width: src.width,
+        height: src.height,
+    };
+    let out_w = src.width as usize * 2;
+    let mut px = [0u32; 12];
+    let mut pyuv = [(0, 0, 0); 12];
+    for y in 0..src.height {
+        for x in 0..src.width {
+            let e = src.at(x, y);
+            let mut block = [e; 4];
+            for turns in 0..4 {
+                for (k, &off) in XBR_OFFSETS.iter().enumerate() {
+                    let (dx, dy) = rotate(off, turns);
+                    px[k] = src.at(x + dx, y + dy);
+                    pyuv[k] = yuv_src.at(x + dx, y + dy);
+                }
+                let corners = [
+                    corner(rotate((1, -1), turns)),
+                    corner(rotate((-1, 1), turns)),
+                    corner(rotate((1, 1), turns)),
+                ];
+                xbr_corner(&px, &pyuv, &mut block, corners);
+            }
+            let i = y as usize * 2 * out_w + x as usize * 2;
+            out[i..i + 2].copy_from_slice(&block[0..2]);
+            out[i + out_w..i + out_w + 2].copy_from_slice(&block[2..4]);
+        }
+    }
+}
+
+struct YuvFrame<'a> {
+    yuvs: &'a [(i32, i32, i32)],
+    width: i32,
+    height: i32,
+}
+
+impl<'a> YuvFrame<'a> {
+    #[inline]
+    fn at(&self, x: i32, y: i32) -> (i32, i32, i32) {
+        let x = x.max(0).min(self.width - 1);
+        let y = y.max(0).min(self.height - 1);
+        self.yuvs[(y * self.width + x) as usize]
+    }
+}
+
+// Smooths the bottom right corner of the block, in the rotated orientation,
+// if there's an edge running across it.  `corners` are the block indices of
+// the top right, bottom left and bottom right corners.
+fn xbr_corner(
+    px: &[u32; 12],
+    yuv: &[(i32, i32, i32); 12],
+    block: &mut [u32; 4],
+    corners: [usize; 3],
+) {
+    if px[PE] == px[PH] || px[PE] == px[PF] {
+        return;
+    }
+    let df = |a: usize, b: usize| yuv_diff(yuv[a], yuv[b]);
+    let eq = |a: usize, b: usize| df(a, b) < XBR_EQ;
+
+    // How strongly the edge runs through the centre one way or the other
+    let e = df(PE, PC) + df(PE, PG) + df(PI, H5) + df(PI, F4) + (df(PH, PF) << 2);
+    let i = df(PH, PD) + df(PH, I5) + df(PF, I4) + df(PF, PB) + (df(PE, PI) << 2);
+    if e > i {
+        return;
+    }
+
+    let [n1, n2, n3] = corners;
+    let p = if df(PE, PF) <= df(PE, PH) {
+        px[PF]
+    } else {
+        px[PH]
+    };
+    let sharp = e < i
+        && ((!eq(PF, PB) && !eq(PH, PD))
+            || (eq(PE, PI) && !eq(PF, I4) && !eq(PH, I5))
+            || eq(PE, PG)
+            || eq(PE, PC));
+    if !sharp {
+        block[n3] = mix(block[n3], p, 128);
+        return;
+    }
+
+    // Shallow edges are blended along the next corner over as well
+    let ke = df(PF, PG);
+    let ki = df(PH, PC);
+    let left = ke << 1 <= ki && px[PE] != px[PG] && px[PD] != px[PG];
+    let up = ke >= ki << 1 && px[PE] != px[PC] && px[PB] != px[PC];
+    if left && up {
+        block[n3] = mix(block[n3], p, 224);
+        block[n2] = mix(block[n2], p, 64);
+        block[n1] = block[n2];
+    } else if left {
+        block[n3] = mix(block[n3], p, 192);
+        block[n2] = mix(block[n2], p, 64);
+    } else if up {
+        block[n3] = mix(block[n3], p, 192);
+        block[n1] = mix(block[n1], p, 64);
+    } else {
+        block[n3] = mix(block[n3], p, 128);
+    }
+}
+
+/// Averages each `factor` by `factor` square of pixels into one, returning
+/// the smaller frame and its size
+pub fn shrink(frame: &[u32], width: u32, height: u32, factor: u32) -> (Vec<u32>, u32, u32) {
+    if factor == 1 {
+        return (frame.to_vec(), width, height);
+    }
+    let (w, h) = (width / factor, height / factor);
+    let mut out = Vec::with_capacity((w * h) as usize);
+    for y in 0..h {
+        for x in 0..w {
+            let mut sums = [0; 3];
+            for j in 0..factor {
+                for i in 0..factor {
+                    let p = frame[((y * factor + j) * width + x * factor + i) as usize];
+                    sums[0] += p >> 16 & 0xff;
+                    sums[1] += p >> 8 & 0xff;
+                    sums[2] += p & 0xff;
+                }
+            }
+            let n = factor * factor;
+            out.push((sums[0] / n) << 16 | (sums[1] / n) << 8 | (sums[2] / n));
+        }
+    }
+    (out, w, h)
+}
+
+fn apply_mask(mask: Mask, n: u32, width: u32, out: &mut [u32]) {
+    if mask == Mask::Off {
+        return;
+    }
+    for (i, p) in out.iter_mut().enumerate() {
+        let (x, y) = (i as u32 % width % n, i as u32 / width % n);
+        let dark = match mask {
+            Mask::Scanlines => y == n - 1,
+            _ => x == n - 1 || y == n - 1,
+        };
+        if dark {
+            let level = match mask {
+                Mask::Scanlines => SCANLINE_LEVEL,
+                _ => GRID_LEVEL,
+            };
+            *p = mix(0, *p, level);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn upscaler(scaler: Scaler, mask: Mask) -> Upscaler {
+        Upscaler::new(&DisplayOptions {
+            scaler: scaler,
+            factor: 2,
+            mask: mask,
+            ..Default::default()
+        })
+    }
+
+    // A white diagonal edge over black:
+    //   W . .
+    //   W W .
+    //   W W W
+    const DIAGONAL: [u32; 9] = [
+        0xffffff, 0, 0, 0xffffff, 0xffffff, 0, 0xffffff, 0xffffff, 0xffffff,
+    ];
+
+    #[test]
+    fn test_nearest() {
+        let mut up = upscaler(Scaler::Nearest, Mask::Off);
+        let (out, w, h) = up.scale(&[1, 2], 2, 1);
+        assert_eq!((4, 2), (w, h));
+        assert_eq!(&[1, 1, 2, 2, 1, 1, 2, 2], out);
+    }
+
+    #[test]
+    fn test_scale2x() {
+        let mut up = upscaler(Scaler::Scale2x, Mask::Off);
+        let (out, w, _) = up.scale(&DIAGONAL, 3, 3);
+        // The centre pixel's top right corner is cut off by the edge
+        let at = |x: u32, y: u32| out[(y * w + x) as usize];
+        assert_eq!(0, at(3, 2));
+        assert_eq!(0xffffff, at(2, 2));
+        assert_eq!(0xffffff, at(3, 3));
+    }
+
+    #[test]
+    fn test_scale3x() {
+        let mut up = upscaler(Scaler::Scale3x, Mask::Off);
+        let (out, w, h) = up.scale(&DIAGONAL, 3, 3);
+        assert_eq!((9, 9), (w, h));
+        let at = |x: u32, y: u32| out[(y * w + x) as usize];
+        assert_eq!(0, at(5, 3));
+        assert_eq!(0xffffff, at(4, 4));
+        assert_eq!(0xffffff, at(3, 5));
+    }
+
+    #[test]
+    fn test_xbr() {
+        let mut up = upscaler(Scaler::Xbr, Mask::Off);
+        let (out, _, _) = up.scale(&[0x808080; 9], 3, 3);
+        assert!(out.iter().all(|&p| p == 0x808080));
+
+        // The edge's corners are blended rather than stepped
+        let (out, w, _) = up.scale(&DIAGONAL, 3, 3);
+        let at = |x: u32, y: u32| out[(y * w + x) as usize];
+        let centre_corner = at(3, 2);
+        assert!(centre_corner != 0 && centre_corner != 0xffffff);
+    }
+
+    #[test]
+    fn test_mask() {
+        let mut up = upscaler(Scaler::Nearest, Mask::Scanlines);
+        let (out, _, _) = up.scale(&[0xffffff], 1, 1);
+        assert_eq!(&[0xffffff, 0xffffff, 0x7f7f7f, 0x7f7f7f], out);
+
+        let mut up = upscaler(Scaler::Nearest, Mask::Grid);
+        let (out, _, _) = up.scale(&[0xffffff], 1, 1);
+        assert_eq!(&[0xffffff, 0xbfbfbf, 0xbfbfbf, 0xbfbfbf], out);
+    }
+
+    #[test]
+    fn test_frame_rect() {
+        assert_eq!(
+            (0, 40, 720, 480),
+            frame_rect((720, 560), (240, 160), false, true)
+        );
+        assert_eq!(
+            (110, 90, 480, 320),
+            frame_rect((700, 500), (240, 160), true, true)
+        );
+        assert_eq!(
+            (0, 0, 720, 560),
+            frame_rect((720, 560), (240, 160), false, false)
+        );
+        assert_eq!(
+            (110, 10, 480, 480),
+            frame_rect((700, 500), (240, 160), true, false)
+        );
+        assert_eq!(
+            (-120, 0, 240, 160),
+            frame_rect((0, 160), (240, 160), true, true)
+        );
+    }
+}
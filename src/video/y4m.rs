@@ -0,0 +1,93 @@
+use std::io::{self, Write};
+
+/// Writes frames as an uncompressed YUV4MPEG2 stream, which most video
+/// encoders read from a file or a pipe.  Pixels are packed as 0x00RRGGBB and
+/// converted to BT.601 limited range YCbCr, without subsampling the chroma so
+/// the GBA's sharp pixels survive.
+pub struct Y4mWriter<W: Write> {
+    w: W,
+    width: u32,
+    height: u32,
+    // Y, then Cb, then Cr planes of the frame being written
+    planes: Vec<u8>,
+}
+
+impl<W: Write> Y4mWriter<W> {
+    /// Writes the header, for frames that each last `den / num` seconds
+    pub fn new(mut w: W, width: u32, height: u32, num: u64, den: u64) -> io::Result<Self> {
+        let div = gcd(num, den);
+        writeln!(
+            w,
+            "YUV4MPEG2 W{} H{} F{}:{} Ip A1:1 C444 XCOLORRANGE=LIMITED",
+            width,
+            height,
+            num / div,
+            den / div
+        )?;
+        Ok(Y4mWriter {
+            w: w,
+            width: width,
+            height: height,
+            planes: vec![0; (width * height * 3) as usize],
+        })
+    }
+
+    pub fn write_frame(&mut self, pixels: &[u32]) -> io::Result<()> {
+        let len = (self.width * self.height) as usize;
+        assert_eq!(len, pixels.len());
+        for (i, &p) in pixels.iter().enumerate() {
+            let (y, cb, cr) = ycbcr(p);
+            self.planes[i] = y;
+            self.planes[len + i] = cb;
+            self.planes[2 * len + i] = cr;
+        }
+        self.w.write_all(b"FRAME\n")?;
+        self.w.write_all(&self.planes)
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+fn ycbcr(p: u32) -> (u8, u8, u8) {
+    let r = (p >> 16 & 0xff) as i32;
+    let g = (p >> 8 & 0xff) as i32;
+    let b = (p & 0xff) as i32;
+    let y = ((66 * r + 129 * g + 25 * b + 128) >> 8) + 16;
+    let cb = ((-38 * r - 74 * g + 112 * b + 128) >> 8) + 128;
+    let cr = ((112 * r - 94 * g - 18 * b + 128) >> 8) + 128;
+    (y as u8, cb as u8, cr as u8)
+}
+
+fn gcd(a: u64, b: u64) -> u64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_y4m_layout() {
+        let mut y4m = Y4mWriter::new(vec![], 2, 1, 16 * 1024 * 1024, 280896).unwrap();
+        y4m.write_frame(&[0xffffff, 0x000000]).unwrap();
+        let data = y4m.finish().unwrap();
+
+        let header = b"YUV4MPEG2 W2 H1 F262144:4389 Ip A1:1 C444 XCOLORRANGE=LIMITED\n";
+        assert_eq!(&header[..], &data[..header.len()]);
+        assert_eq!(b"FRAME\n\xeb\x10\x80\x80\x80\x80", &data[header.len()..]);
+    }
+
+    #[test]
+    fn test_ycbcr() {
+        assert_eq!((82, 90, 240), ycbcr(0xff0000));
+        assert_eq!((144, 54, 34), ycbcr(0x00ff00));
+        assert_eq!((41, 240, 110), ycbcr(0x0000ff));
+    }
+}
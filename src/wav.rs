@@ -0,0 +1,98 @@
+use std::fs::File;
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+use byteorder::{LittleEndian, WriteBytesExt};
+
+// Size of the RIFF header, fmt chunk and data chunk header
+const HEADER_LEN: u32 = 44;
+
+/// Writes 16-bit PCM samples as a WAV file.  The sizes in the header are
+/// filled in by `finish` once the length is known.
+pub struct WavWriter<W: Write + Seek> {
+    w: W,
+    channels: u16,
+    frames: u32,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    pub fn new(mut w: W, channels: u16, rate: u32) -> io::Result<Self> {
+        let block_align = channels * 2;
+        w.write_all(b"RIFF")?;
+        w.write_u32::<LittleEndian>(HEADER_LEN - 8)?;
+        w.write_all(b"WAVE")?;
+
+        w.write_all(b"fmt ")?;
+        w.write_u32::<LittleEndian>(16)?;
+        // PCM
+        w.write_u16::<LittleEndian>(1)?;
+        w.write_u16::<LittleEndian>(channels)?;
+        w.write_u32::<LittleEndian>(rate)?;
+        w.write_u32::<LittleEndian>(rate * block_align as u32)?;
+        w.write_u16::<LittleEndian>(block_align)?;
+        w.write_u16::<LittleEndian>(16)?;
+
+        w.write_all(b"data")?;
+        w.write_u32::<LittleEndian>(0)?;
+        Ok(WavWriter {
+            w: w,
+            channels: channels,
+            frames: 0,
+        })
+    }
+
+    /// Writes one sample for each channel, clipped to -1.0 to 1.0
+    pub fn write_frame(&mut self, samples: &[f32]) -> io::Result<()> {
+        assert_eq!(self.channels as usize, samples.len());
+        for &s in samples {
+            let s = s.max(-1.0).min(1.0);
+            self.w.write_i16::<LittleEndian>((s * 32767.0) as i16)?;
+        }
+        self.frames += 1;
+        Ok(())
+    }
+
+    /// Fills in the sizes in the header
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = self.frames * self.channels as u32 * 2;
+        self.w.seek(SeekFrom::Start(4))?;
+        self.w
+            .write_u32::<LittleEndian>(HEADER_LEN - 8 + data_len)?;
+        self.w.seek(SeekFrom::Start(40))?;
+        self.w.write_u32::<LittleEndian>(data_len)?;
+        self.w.seek(SeekFrom::End(0))?;
+        self.w.flush()?;
+        Ok(self.w)
+    }
+}
+
+impl WavWriter<BufWriter<File>> {
+    pub fn create(path: &Path, channels: u16, rate: u32) -> io::Result<Self> {
+        WavWriter::new(BufWriter::new(File::create(path)?), channels, rate)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    use std::io::Cursor;
+
+    #[test]
+    fn test_wav_layout() {
+        let mut wav = WavWriter::new(Cursor::new(vec![]), 2, 32768).unwrap();
+        wav.write_frame(&[0.0, 1.0]).unwrap();
+        wav.write_frame(&[-2.0, 0.5]).unwrap();
+        let data = wav.finish().unwrap().into_inner();
+
+        assert_eq!(HEADER_LEN as usize + 8, data.len());
+        assert_eq!(b"RIFF", &data[0..4]);
+        assert_eq!(&[44, 0, 0, 0], &data[4..8]);
+        assert_eq!(b"WAVE", &data[8..12]);
+        // 32768 Hz, 4 byte frames
+        assert_eq!(&[0x00, 0x80, 0, 0], &data[24..28]);
+        assert_eq!(&[0x00, 0x00, 0x02, 0], &data[28..32]);
+        assert_eq!(&[8, 0, 0, 0], &data[40..44]);
+        assert_eq!(&[0, 0, 0xff, 0x7f, 0x01, 0x80, 0xff, 0x3f], &data[44..]);
+    }
+}